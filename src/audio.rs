@@ -0,0 +1,245 @@
+use crate::camera::Camera;
+use crate::events::GameEvent;
+use crate::game_objects::MAX_SHIPS;
+use crate::shapes::Point;
+use crate::world::World;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::Sdl;
+
+use std::f32::consts::FRAC_PI_4;
+
+// -----------------------------------------------------------------------------
+
+const SAMPLE_RATE: i32 = 44100;
+const BUFFER_SAMPLES: u16 = 512;
+const MAX_VOICES: usize = 32;
+// Fraction of the way to the target thrust level covered per sample, so it doesn't click
+const THRUST_SMOOTHING: f32 = 0.002;
+
+// Retro sound effects, synthesized on the fly from square waves and noise. Upper case like every
+// other enum in the game
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone)]
+enum Sound {
+    LASER,
+    HIT,
+    EXPLOSION { radius: f32 },
+    DAMAGE,
+}
+
+impl Sound {
+    // Seconds
+    fn duration(&self) -> f32 {
+        match self {
+            Sound::LASER => 0.12,
+            Sound::HIT => 0.04,
+            Sound::EXPLOSION { radius } => 0.2 + radius * 0.1,
+            Sound::DAMAGE => 0.15,
+        }
+    }
+}
+
+struct Voice {
+    sound: Sound,
+    gain: f32,
+    // -1 is fully left, 1 fully right
+    pan: f32,
+    time: f32,
+    phase: f32,
+    filtered: f32,
+}
+
+#[derive(Copy, Clone, Default)]
+struct Thrust {
+    target: f32,
+    level: f32,
+    pan: f32,
+    filtered: f32,
+}
+
+// Runs in the SDL audio thread, the game only queues sounds into it
+struct Synth {
+    sample_rate: f32,
+    volume: f32,
+    voices: Vec<Voice>,
+    thrust: [Thrust; MAX_SHIPS],
+    noise_state: u32,
+}
+
+impl Synth {
+    // Xorshift, the game's random numbers are left alone
+    fn noise(&mut self) -> f32 {
+        self.noise_state ^= self.noise_state << 13;
+        self.noise_state ^= self.noise_state >> 17;
+        self.noise_state ^= self.noise_state << 5;
+        self.noise_state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    fn play(&mut self, sound: Sound, gain: f32, pan: f32) {
+        if self.voices.len() >= MAX_VOICES {
+            self.voices.remove(0);
+        }
+        self.voices.push(Voice {
+            sound,
+            gain,
+            pan,
+            time: 0.0,
+            phase: 0.0,
+            filtered: 0.0,
+        });
+    }
+
+    fn voice_sample(&mut self, i: usize) -> f32 {
+        let noise = self.noise();
+        let dt = 1.0 / self.sample_rate;
+        let voice = &mut self.voices[i];
+        let progress = voice.time / voice.sound.duration();
+        let envelope = (1.0 - progress) * (1.0 - progress);
+        let sample = match voice.sound {
+            Sound::LASER => {
+                let frequency = 1400.0 * (1.0 - progress) + 200.0 * progress;
+                voice.phase = (voice.phase + frequency * dt).fract();
+                square(voice.phase) * 0.3
+            }
+            Sound::HIT => {
+                voice.filtered += 0.5 * (noise - voice.filtered);
+                voice.filtered * 0.5
+            }
+            Sound::EXPLOSION { radius } => {
+                // Bigger asteroids rumble lower
+                voice.filtered += (0.3 / radius).min(0.5) * (noise - voice.filtered);
+                voice.filtered * 2.0
+            }
+            Sound::DAMAGE => {
+                voice.phase = (voice.phase + 90.0 * dt).fract();
+                square(voice.phase) * 0.3 + noise * 0.1
+            }
+        };
+        voice.time += dt;
+        sample * envelope * voice.gain
+    }
+}
+
+impl AudioCallback for Synth {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for frame in out.chunks_mut(2) {
+            let (mut left, mut right) = (0.0, 0.0);
+            for i in 0..self.voices.len() {
+                let pan = self.voices[i].pan;
+                let sample = self.voice_sample(i);
+                left += sample * pan_gain(-pan);
+                right += sample * pan_gain(pan);
+            }
+            for i in 0..MAX_SHIPS {
+                let noise = self.noise();
+                let thrust = &mut self.thrust[i];
+                thrust.level += (thrust.target - thrust.level) * THRUST_SMOOTHING;
+                thrust.filtered += 0.02 * (noise - thrust.filtered);
+                let sample = thrust.filtered * thrust.level * 0.8;
+                left += sample * pan_gain(-thrust.pan);
+                right += sample * pan_gain(thrust.pan);
+            }
+            self.voices.retain(|v| v.time < v.sound.duration());
+            frame[0] = (left * self.volume).clamp(-1.0, 1.0);
+            frame[1] = (right * self.volume).clamp(-1.0, 1.0);
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+pub struct Audio {
+    device: AudioDevice<Synth>,
+}
+
+impl Audio {
+    // Volume goes from 0 to 1
+    pub fn new(sdl_context: &Sdl, volume: f32) -> Result<Audio, String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(2),
+            samples: Some(BUFFER_SAMPLES),
+        };
+        let device = audio_subsystem.open_playback(None, &desired, |spec| Synth {
+            sample_rate: spec.freq as f32,
+            volume,
+            voices: Vec::with_capacity(MAX_VOICES),
+            thrust: [Thrust::default(); MAX_SHIPS],
+            noise_state: 0x9e3779b9,
+        })?;
+        device.resume();
+        Ok(Audio { device })
+    }
+
+    // Plays the sounds for the last update, panned by where they happened on screen
+    pub fn update(&mut self, world: &World, camera: &Camera) {
+        let mut synth = self.device.lock();
+        for event in &world.events {
+            match *event {
+                GameEvent::BulletFired { position } => {
+                    synth.play(Sound::LASER, 1.0, screen_pan(camera, position))
+                }
+                GameEvent::BulletHit { position, .. } => {
+                    synth.play(Sound::HIT, 1.0, screen_pan(camera, position))
+                }
+                GameEvent::AsteroidDestroyed {
+                    position, radius, ..
+                } => synth.play(
+                    Sound::EXPLOSION { radius },
+                    (radius / 6.0).min(1.0),
+                    screen_pan(camera, position),
+                ),
+                GameEvent::ShipDamaged {
+                    position, damage, ..
+                } => synth.play(
+                    Sound::DAMAGE,
+                    (damage as f32 / 20.0).clamp(0.2, 1.0),
+                    screen_pan(camera, position),
+                ),
+                GameEvent::ShipDestroyed { triangle, .. } => synth.play(
+                    Sound::EXPLOSION { radius: 8.0 },
+                    1.0,
+                    screen_pan(camera, triangle.circumcenter()),
+                ),
+                GameEvent::GameOver { .. } => {}
+            }
+        }
+        let max_acceleration = world
+            .ships
+            .tuning
+            .acceleration
+            .into_iter()
+            .fold(0.0, f32::max);
+        for i in 0..MAX_SHIPS {
+            let thrust = &mut synth.thrust[i];
+            if world.ships.pool.exists(i) {
+                thrust.target = world.ships.acceleration(i) / max_acceleration;
+                thrust.pan = screen_pan(camera, world.ships.triangle[i].circumcenter());
+            } else {
+                thrust.target = 0.0;
+            }
+        }
+    }
+}
+
+fn square(phase: f32) -> f32 {
+    if phase < 0.5 {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+// Equal power panning, the gain of the right channel, or of the left one with the pan negated
+fn pan_gain(pan: f32) -> f32 {
+    ((pan + 1.0) * FRAC_PI_4).sin()
+}
+
+fn screen_pan(camera: &Camera, position: Point) -> f32 {
+    let x = camera.to_view(position).x / camera.visible_size().x;
+    (x * 2.0 - 1.0).clamp(-1.0, 1.0)
+}
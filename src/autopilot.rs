@@ -0,0 +1,238 @@
+use crate::game_objects::{Player, Ships};
+use crate::input::PlayerInput;
+use crate::intercept::{solve_intercept, Intercept};
+use crate::pool::Handle;
+use crate::shapes::Point;
+use crate::world::World;
+
+use std::f32::consts::{PI, TAU};
+
+// -----------------------------------------------------------------------------
+
+// Seconds ahead that collisions are looked for
+const THREAT_HORIZON: f32 = 1.5;
+// Extra room kept between the ship and asteroids, and gravity wells
+const THREAT_MARGIN: f32 = 3.0;
+const WELL_MARGIN: f32 = 8.0;
+// Fractions of the ship's max speed, cruising towards a target and running away from a threat
+const CRUISE_SPEED: f32 = 0.2;
+const EVADE_SPEED: f32 = 0.4;
+// Targets further than this are flown towards
+const ENGAGE_DISTANCE: f32 = 40.0;
+// Thrust only when heading this close to where the ship should go
+const THRUST_ANGLE: f32 = PI / 6.0;
+// A new target has to be this much cheaper than the current one to switch to it
+const TARGET_SWITCH: f32 = 0.7;
+// Fraction of the intercept's tolerance that shots can be off by
+const AIM_TOLERANCE: f32 = 0.75;
+
+// Autopilot
+// A scripted pilot that flies a ship with the same controls a player has: picks the asteroid that's
+// quickest to shoot, leads its shots, gets out of the way of asteroids and gravity wells about to
+// hit the ship and only thrusts as fast as the ship can slow down again
+pub struct Autopilot {
+    pub player: Player,
+    // The input from the last decision, and the ship it was pressed on
+    input: PlayerInput,
+    ship: Option<Handle>,
+    target: Option<Handle>,
+}
+
+// Something the ship will hit if nothing changes
+struct Threat {
+    // Where it will be relative to the ship when they're closest
+    closest: Point,
+    relative_velocity: Point,
+    time: f32,
+}
+
+impl Autopilot {
+    pub fn new(player: Player) -> Autopilot {
+        Autopilot {
+            player,
+            input: PlayerInput::default(),
+            ship: None,
+            target: None,
+        }
+    }
+
+    // Decides and presses the controls on the player's ship, call it before every update
+    pub fn fly(&mut self, world: &mut World) {
+        let index = self.player.to_index();
+        let ships = &world.ships;
+        let ship = ships.pool.exists(index).then(|| ships.pool.handle(index));
+        // A new ship starts with nothing pressed
+        let previous = if ship.is_some() && ship == self.ship {
+            self.input
+        } else {
+            PlayerInput::default()
+        };
+        let input = self.decide(world);
+        if ship.is_some() {
+            input.apply(previous, &mut world.ships, self.player);
+        }
+        self.ship = ship;
+    }
+
+    // Lets go of every control, before somebody else takes over the ship
+    pub fn release(&mut self, ships: &mut Ships) {
+        let index = self.player.to_index();
+        if ships.pool.exists(index) && self.ship == Some(ships.pool.handle(index)) {
+            PlayerInput::default().apply(self.input, ships, self.player);
+        }
+        self.input = PlayerInput::default();
+        self.ship = None;
+        self.target = None;
+    }
+
+    // What to press this tick, without pressing it. Firing is let go every other tick, like a
+    // player tapping the button
+    pub fn decide(&mut self, world: &World) -> PlayerInput {
+        let ships = &world.ships;
+        let index = self.player.to_index();
+        if !ships.pool.exists(index) {
+            self.input = PlayerInput::default();
+            return self.input;
+        }
+        let position = ships.triangle[index].circumcenter();
+        let velocity = ships.velocity[index];
+        let heading = ships.triangle[index].angle();
+        let max_speed = ships.tuning.max_speed;
+
+        let mut input = PlayerInput::default();
+        let (desired_angle, cruise_speed) = match self.threat(world, position, velocity) {
+            Some(threat) => {
+                // Away from where it will be, or to the side when it's coming straight on
+                let away = if threat.closest.magnitude_squared() > f32::EPSILON {
+                    -threat.closest
+                } else {
+                    threat.relative_velocity.perpendicular()
+                };
+                (away.angle(), EVADE_SPEED * max_speed)
+            }
+            None => match self.aim(world) {
+                Some(aim) => {
+                    let tolerance = AIM_TOLERANCE * aim.tolerance;
+                    let on_target = angle_difference(aim.angle, heading).abs() < tolerance;
+                    let gun_ready =
+                        ships.gun_cooldown(index) <= (1.0 / world.tuning.frame_rate) as f32;
+                    input.fire = on_target && gun_ready && !self.input.fire;
+                    // Close in on far targets, as fast as the ship can still stop before them
+                    let distance = (aim.point - position).magnitude();
+                    let stopping_distance = (distance - ENGAGE_DISTANCE).max(0.0);
+                    let deacceleration = ships.tuning.deacceleration;
+                    let speed = (2.0 * deacceleration * stopping_distance).sqrt();
+                    (aim.angle, speed.min(CRUISE_SPEED * max_speed))
+                }
+                None => (heading, 0.0),
+            },
+        };
+
+        let difference = angle_difference(desired_angle, heading);
+        let angular_velocity = ships.angular_velocity[index];
+        let angular_acceleration =
+            ships.tuning.angular_acceleration[ships.side_thrusters_level[index].to_index()];
+        // Let go early enough for the turn to stop where it should
+        let stopping_angle = angular_velocity * angular_velocity / (2.0 * angular_acceleration);
+        let turning_towards = angular_velocity * difference > 0.0;
+        if !(turning_towards && stopping_angle >= difference.abs()) {
+            input.left = difference < 0.0;
+            input.right = difference > 0.0;
+        }
+
+        let forward_speed = velocity.dot(Point::from_polar(1.0, heading));
+        input.thrust = difference.abs() < THRUST_ANGLE && forward_speed < cruise_speed;
+
+        self.input = input;
+        input
+    }
+
+    // The threat that hits soonest, asteroids moving and gravity wells standing still
+    fn threat(&self, world: &World, position: Point, velocity: Point) -> Option<Threat> {
+        let size = world.size;
+        let ship_radius = world.ships.triangle[self.player.to_index()].circumradius();
+        let mut threats = Vec::new();
+        let asteroids = &world.asteroids;
+        for i in (0..asteroids.pool.capacity()).filter(|&i| asteroids.pool.exists(i)) {
+            let circle = &asteroids.circle[i];
+            let offset = (circle.center - position).wrapped(size);
+            let relative_velocity = asteroids.velocity[i] - velocity;
+            let radius = circle.radius + ship_radius + THREAT_MARGIN;
+            threats.extend(closest_approach(offset, relative_velocity, radius));
+        }
+        let wells = &world.gravity_wells;
+        for i in (0..wells.pool.capacity()).filter(|&i| wells.pool.exists(i)) {
+            let offset = (wells.circle[i].center - position).wrapped(size);
+            let well_radius = wells.circle[i].radius.max(wells.kill_radius[i]);
+            let radius = well_radius + ship_radius + WELL_MARGIN;
+            threats.extend(closest_approach(offset, -velocity, radius));
+        }
+        threats.into_iter().min_by(|a, b| a.time.total_cmp(&b.time))
+    }
+
+    // Where to shoot to hit the cheapest target, keeping the current one unless another is
+    // clearly better
+    fn aim(&mut self, world: &World) -> Option<Intercept> {
+        let ships = &world.ships;
+        let index = self.player.to_index();
+        let heading = ships.triangle[index].angle();
+        let turn_rate = ships.tuning.max_angular_velocity;
+        // Bullets start at the nose and don't get the ship's velocity
+        let gun = ships.triangle[index].v1;
+        let bullet_speed = world.bullets.tuning.speed;
+        let asteroids = &world.asteroids;
+        let mut best: Option<(f32, Handle, Intercept)> = None;
+        let mut current: Option<(f32, Handle, Intercept)> = None;
+        for i in (0..asteroids.pool.capacity()).filter(|&i| asteroids.pool.exists(i)) {
+            let Some(intercept) = solve_intercept(
+                gun,
+                Point::default(),
+                bullet_speed,
+                &asteroids.circle[i],
+                asteroids.velocity[i],
+                world.size,
+            ) else {
+                continue;
+            };
+            let cost =
+                intercept.time + angle_difference(intercept.angle, heading).abs() / turn_rate;
+            let handle = asteroids.pool.handle(i);
+            if self.target == Some(handle) {
+                current = Some((cost, handle, intercept));
+            }
+            if best
+                .as_ref()
+                .is_none_or(|(best_cost, _, _)| cost < *best_cost)
+            {
+                best = Some((cost, handle, intercept));
+            }
+        }
+        let (_, handle, intercept) = match (current, best) {
+            (Some(current), Some(best)) if best.0 >= current.0 * TARGET_SWITCH => current,
+            (_, best) => best?,
+        };
+        self.target = Some(handle);
+        Some(intercept)
+    }
+}
+
+// Something at offset moving at relative_velocity, if it comes within radius before the horizon
+fn closest_approach(offset: Point, relative_velocity: Point, radius: f32) -> Option<Threat> {
+    let speed_squared = relative_velocity.magnitude_squared();
+    let time = if speed_squared > 0.0 {
+        (-offset.dot(relative_velocity) / speed_squared).max(0.0)
+    } else {
+        0.0
+    };
+    let closest = offset + relative_velocity * time;
+    (time <= THREAT_HORIZON && closest.magnitude() < radius).then_some(Threat {
+        closest,
+        relative_velocity,
+        time,
+    })
+}
+
+// From -PI to PI, positive when to is clockwise from from
+fn angle_difference(to: f32, from: f32) -> f32 {
+    (to - from + PI).rem_euclid(TAU) - PI
+}
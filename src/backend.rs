@@ -0,0 +1,118 @@
+use crate::shapes::Point;
+
+use sdl2::gfx::primitives::DrawRenderer;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, RenderTarget};
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+// -----------------------------------------------------------------------------
+
+// The drawing primitives the game needs, all positions and sizes are in pixels
+pub trait RenderBackend {
+    fn size(&self) -> (u32, u32);
+    fn clear(&mut self, color: Color);
+    // Nothing is drawn outside of the clip rectangle, None to draw everywhere
+    fn set_clip(&mut self, clip: Option<Rect>);
+    fn line(&mut self, p1: Point, p2: Point, width: f32, color: Color);
+    fn ellipse(&mut self, center: Point, rx: f32, ry: f32, color: Color);
+    fn filled_ellipse(&mut self, center: Point, rx: f32, ry: f32, color: Color);
+    fn polygon(&mut self, vertices: &[Point], color: Color);
+    fn text(&mut self, position: Point, text: &str, color: Color);
+}
+
+// -----------------------------------------------------------------------------
+
+// SDL canvas, either a window or a software surface
+pub struct SdlBackend<T: RenderTarget> {
+    pub canvas: Canvas<T>,
+}
+
+impl<T: RenderTarget> SdlBackend<T> {
+    pub fn new(canvas: Canvas<T>) -> SdlBackend<T> {
+        SdlBackend { canvas }
+    }
+
+    // Rows of RGB bytes, top to bottom
+    pub fn pixels(&self) -> Result<Vec<u8>, String> {
+        self.canvas.read_pixels(None, PixelFormatEnum::RGB24)
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), String> {
+        let (width, height) = self.size();
+        let pixels = self.pixels()?;
+        let file = File::create(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&pixels))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+impl<T: RenderTarget> RenderBackend for SdlBackend<T> {
+    fn size(&self) -> (u32, u32) {
+        self.canvas.output_size().unwrap()
+    }
+
+    fn clear(&mut self, color: Color) {
+        self.canvas.set_draw_color(color);
+        self.canvas.clear();
+    }
+
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        self.canvas.set_clip_rect(clip);
+    }
+
+    fn line(&mut self, p1: Point, p2: Point, width: f32, color: Color) {
+        let (x1, y1, x2, y2) = (p1.x as i16, p1.y as i16, p2.x as i16, p2.y as i16);
+        if width <= 1.0 {
+            self.canvas.aa_line(x1, y1, x2, y2, color).unwrap();
+        } else {
+            self.canvas
+                .thick_line(x1, y1, x2, y2, width as u8, color)
+                .unwrap();
+        }
+    }
+
+    fn ellipse(&mut self, center: Point, rx: f32, ry: f32, color: Color) {
+        self.canvas
+            .aa_ellipse(
+                center.x as i16,
+                center.y as i16,
+                rx as i16,
+                ry as i16,
+                color,
+            )
+            .unwrap();
+    }
+
+    fn filled_ellipse(&mut self, center: Point, rx: f32, ry: f32, color: Color) {
+        self.canvas
+            .filled_ellipse(
+                center.x as i16,
+                center.y as i16,
+                rx as i16,
+                ry as i16,
+                color,
+            )
+            .unwrap();
+    }
+
+    fn polygon(&mut self, vertices: &[Point], color: Color) {
+        let vx: Vec<i16> = vertices.iter().map(|v| v.x as i16).collect();
+        let vy: Vec<i16> = vertices.iter().map(|v| v.y as i16).collect();
+        self.canvas.aa_polygon(&vx, &vy, color).unwrap();
+    }
+
+    fn text(&mut self, position: Point, text: &str, color: Color) {
+        self.canvas
+            .string(position.x as i16, position.y as i16, text, color)
+            .unwrap();
+    }
+}
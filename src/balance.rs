@@ -0,0 +1,417 @@
+use crate::autopilot::Autopilot;
+use crate::events::{DamageSource, GameEvent};
+use crate::game_objects::{Player, MAX_ASTEROIDS, MAX_BULLETS};
+use crate::input::PlayerInput;
+use crate::levels::Campaign;
+use crate::pool::Pool;
+use crate::shapes::Point;
+use crate::tuning::Tuning;
+use crate::world::{World, DEFAULT_WORLD_SIZE};
+
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// -----------------------------------------------------------------------------
+
+pub const DEFAULT_MAX_TIME: f32 = 600.0; // Seconds
+
+// Who flies the ship in balance games
+#[derive(Copy, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    // Never touches the controls
+    IDLE,
+    // Presses random controls every tick
+    RANDOM,
+    #[default]
+    AUTOPILOT,
+}
+
+impl Policy {
+    pub fn parse(text: &str) -> Option<Policy> {
+        match text {
+            "idle" => Some(Policy::IDLE),
+            "random" => Some(Policy::RANDOM),
+            "autopilot" => Some(Policy::AUTOPILOT),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BalanceConfig {
+    pub games: u32,
+    // Games get this seed, the next one and so on
+    pub seed: u64,
+    pub policy: Policy,
+    pub world_size: Point,
+    pub tuning: Tuning,
+    pub campaign: Campaign,
+    // Seconds, games still going after this are cut short
+    pub max_time: f32,
+}
+
+impl Default for BalanceConfig {
+    fn default() -> Self {
+        BalanceConfig {
+            games: 1,
+            seed: 0,
+            policy: Policy::default(),
+            world_size: DEFAULT_WORLD_SIZE,
+            tuning: Tuning::default(),
+            campaign: Campaign::default(),
+            max_time: DEFAULT_MAX_TIME,
+        }
+    }
+}
+
+// How one game went
+#[derive(Clone, Default, Serialize)]
+pub struct GameStats {
+    pub seed: u64,
+    pub score: u32,
+    // The level the game ended on
+    pub level: i32,
+    pub survival_time: f32, // Seconds
+    // Seconds on each level, from level 1
+    pub level_times: Vec<f32>,
+    // What destroyed the ship, None when the game was cut short
+    pub death: Option<DamageSource>,
+    pub shots: u32,
+    pub hits: u32,
+    pub kills: u32,
+    // HP lost to each source
+    pub damage: BTreeMap<&'static str, u32>,
+    // Most objects in their pool at once, and the fraction of ticks with at least MAX_ASTEROIDS
+    // asteroids or MAX_BULLETS bullets in flight
+    pub peak_asteroids: usize,
+    pub asteroid_saturation: f32,
+    pub peak_bullets: usize,
+    pub bullet_saturation: f32,
+}
+
+impl GameStats {
+    pub fn shots_per_kill(&self) -> Option<f32> {
+        (self.kills > 0).then(|| self.shots as f32 / self.kills as f32)
+    }
+}
+
+#[derive(Clone, Default, Serialize)]
+pub struct LevelStats {
+    pub level: i32,
+    // Games that got to the level, and that ended on it with the ship destroyed
+    pub reached: u32,
+    pub died: u32,
+    // Seconds on the level, over the games that got to it
+    pub average_time: f32,
+}
+
+// BalanceReport
+// Statistics over many seeded games played by a bot, to compare balance changes with
+#[derive(Clone, Default, Serialize)]
+pub struct BalanceReport {
+    pub policy: Policy,
+    pub games: u32,
+    pub max_time: f32,
+    pub average_score: f32,
+    pub average_level: f32,
+    pub average_survival_time: f32,
+    // Games still going after max_time
+    pub cut_short: u32,
+    pub shots_per_kill: Option<f32>,
+    pub accuracy: Option<f32>,
+    // Average HP lost per game to each source, and ships destroyed by each
+    pub damage: BTreeMap<&'static str, f32>,
+    pub deaths: BTreeMap<&'static str, u32>,
+    pub peak_asteroids: usize,
+    pub asteroid_saturation: f32,
+    pub peak_bullets: usize,
+    pub bullet_saturation: f32,
+    pub levels: Vec<LevelStats>,
+    pub game_stats: Vec<GameStats>,
+}
+
+// Plays all the games on every core, the results only depend on the config
+pub fn run_balance(config: &BalanceConfig) -> BalanceReport {
+    let game_stats: Vec<GameStats> = (0..config.games)
+        .into_par_iter()
+        .map(|game| play_game(config, config.seed.wrapping_add(game as u64)))
+        .collect();
+    BalanceReport::new(config, game_stats)
+}
+
+fn play_game(config: &BalanceConfig, seed: u64) -> GameStats {
+    let mut world = World::new(config.world_size, seed);
+    world.campaign = config.campaign.clone();
+    world.set_tuning(config.tuning);
+    let dt = (1.0 / world.tuning.frame_rate) as f32;
+    let mut autopilot = Autopilot::new(Player::PLAYER1);
+    // Apart from the world's, so the random policy doesn't change how the game plays out
+    let mut rng = Pcg32::seed_from_u64(seed);
+    let mut input = PlayerInput::default();
+    let mut stats = GameStats {
+        seed,
+        damage: DamageSource::ALL.iter().map(|s| (s.name(), 0)).collect(),
+        ..Default::default()
+    };
+    let (mut asteroids_saturated, mut bullets_saturated) = (0, 0);
+    let max_ticks = (config.max_time / dt).ceil() as u32;
+    let mut ticks = 0;
+    let mut game_over = false;
+    while !game_over && ticks < max_ticks {
+        match config.policy {
+            Policy::IDLE => {}
+            Policy::RANDOM => {
+                let next = PlayerInput {
+                    thrust: rng.gen(),
+                    left: rng.gen(),
+                    right: rng.gen(),
+                    fire: rng.gen(),
+                };
+                next.apply(input, &mut world.ships, Player::PLAYER1);
+                input = next;
+            }
+            Policy::AUTOPILOT => autopilot.fly(&mut world),
+        }
+        world.update(dt);
+        ticks += 1;
+
+        for event in &world.events {
+            match *event {
+                GameEvent::BulletFired { .. } => stats.shots += 1,
+                GameEvent::BulletHit { .. } => stats.hits += 1,
+                GameEvent::AsteroidDestroyed { .. } => stats.kills += 1,
+                GameEvent::ShipDamaged { damage, source, .. } => {
+                    *stats.damage.entry(source.name()).or_default() += damage.max(0) as u32;
+                }
+                GameEvent::ShipDestroyed { cause, .. } => stats.death = Some(cause),
+                GameEvent::GameOver { score, level } => {
+                    (stats.score, stats.level) = (score, level);
+                    game_over = true;
+                }
+            }
+        }
+        if game_over {
+            break;
+        }
+        (stats.score, stats.level) = (world.score, world.level);
+        // The first level starts on the first update
+        if let Ok(level) = usize::try_from(world.level - 1) {
+            if stats.level_times.len() <= level {
+                stats.level_times.resize(level + 1, 0.0);
+            }
+            stats.level_times[level] += dt;
+        }
+
+        let asteroids = count(&world.asteroids.pool);
+        let bullets = count(&world.bullets.pool);
+        stats.peak_asteroids = stats.peak_asteroids.max(asteroids);
+        stats.peak_bullets = stats.peak_bullets.max(bullets);
+        asteroids_saturated += (asteroids >= MAX_ASTEROIDS) as u32;
+        bullets_saturated += (bullets >= MAX_BULLETS) as u32;
+    }
+    if !game_over {
+        stats.death = None;
+    }
+    stats.survival_time = ticks as f32 * dt;
+    stats.asteroid_saturation = asteroids_saturated as f32 / ticks.max(1) as f32;
+    stats.bullet_saturation = bullets_saturated as f32 / ticks.max(1) as f32;
+    stats
+}
+
+fn count(pool: &Pool) -> usize {
+    (0..pool.capacity()).filter(|&i| pool.exists(i)).count()
+}
+
+impl BalanceReport {
+    fn new(config: &BalanceConfig, game_stats: Vec<GameStats>) -> BalanceReport {
+        let games = game_stats.len().max(1) as f32;
+        let average =
+            |value: fn(&GameStats) -> f32| game_stats.iter().map(value).sum::<f32>() / games;
+        let total = |value: fn(&GameStats) -> u32| game_stats.iter().map(value).sum::<u32>();
+        let (shots, hits, kills) = (total(|g| g.shots), total(|g| g.hits), total(|g| g.kills));
+
+        let mut levels: Vec<LevelStats> = Vec::new();
+        for game in &game_stats {
+            for (i, &time) in game.level_times.iter().enumerate() {
+                if levels.len() <= i {
+                    levels.push(LevelStats {
+                        level: i as i32 + 1,
+                        ..Default::default()
+                    });
+                }
+                levels[i].reached += 1;
+                levels[i].average_time += time;
+            }
+            if let (Some(_), Some(level)) = (game.death, game.level_times.len().checked_sub(1)) {
+                levels[level].died += 1;
+            }
+        }
+        for level in &mut levels {
+            level.average_time /= level.reached as f32;
+        }
+
+        BalanceReport {
+            policy: config.policy,
+            games: game_stats.len() as u32,
+            max_time: config.max_time,
+            average_score: average(|g| g.score as f32),
+            average_level: average(|g| g.level as f32),
+            average_survival_time: average(|g| g.survival_time),
+            cut_short: game_stats.iter().filter(|g| g.death.is_none()).count() as u32,
+            shots_per_kill: (kills > 0).then(|| shots as f32 / kills as f32),
+            accuracy: (shots > 0).then(|| hits as f32 / shots as f32),
+            damage: DamageSource::ALL
+                .iter()
+                .map(|source| {
+                    let name = source.name();
+                    let damage: u32 = game_stats.iter().map(|g| g.damage[name]).sum();
+                    (name, damage as f32 / games)
+                })
+                .collect(),
+            deaths: DamageSource::ALL
+                .iter()
+                .map(|&source| {
+                    let deaths = game_stats.iter().filter(|g| g.death == Some(source));
+                    (source.name(), deaths.count() as u32)
+                })
+                .collect(),
+            peak_asteroids: game_stats
+                .iter()
+                .map(|g| g.peak_asteroids)
+                .max()
+                .unwrap_or(0),
+            asteroid_saturation: average(|g| g.asteroid_saturation),
+            peak_bullets: game_stats.iter().map(|g| g.peak_bullets).max().unwrap_or(0),
+            bullet_saturation: average(|g| g.bullet_saturation),
+            levels,
+            game_stats,
+        }
+    }
+
+    // JSON when the path ends in .json, the whole report. Otherwise CSV, one row per game, and the
+    // levels in a second file next to it ending in -levels.csv
+    pub fn save(&self, path: &Path) -> Result<Vec<PathBuf>, String> {
+        let write = |path: &Path, text: String| {
+            fs::write(path, text).map_err(|e| format!("{}: {e}", path.display()))
+        };
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            let json = serde_json::to_string_pretty(self)
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+            write(path, json)?;
+            return Ok(vec![path.to_path_buf()]);
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let levels_path = path.with_file_name(format!("{stem}-levels.csv"));
+        write(path, self.games_csv())?;
+        write(&levels_path, self.levels_csv())?;
+        Ok(vec![path.to_path_buf(), levels_path])
+    }
+
+    pub fn games_csv(&self) -> String {
+        let mut csv =
+            "seed,score,level,survival_time,death,shots,hits,kills,shots_per_kill".to_string();
+        for source in DamageSource::ALL {
+            csv += &format!(",damage_{}", source.name());
+        }
+        csv += ",peak_asteroids,asteroid_saturation,peak_bullets,bullet_saturation\n";
+        for game in &self.game_stats {
+            csv += &format!(
+                "{},{},{},{},{},{},{},{},{}",
+                game.seed,
+                game.score,
+                game.level,
+                game.survival_time,
+                game.death.map_or("", |cause| cause.name()),
+                game.shots,
+                game.hits,
+                game.kills,
+                game.shots_per_kill()
+                    .map_or(String::new(), |s| s.to_string()),
+            );
+            for source in DamageSource::ALL {
+                csv += &format!(",{}", game.damage[source.name()]);
+            }
+            csv += &format!(
+                ",{},{},{},{}\n",
+                game.peak_asteroids,
+                game.asteroid_saturation,
+                game.peak_bullets,
+                game.bullet_saturation
+            );
+        }
+        csv
+    }
+
+    pub fn levels_csv(&self) -> String {
+        let mut csv = "level,reached,died,average_time\n".to_string();
+        for level in &self.levels {
+            csv += &format!(
+                "{},{},{},{}\n",
+                level.level, level.reached, level.died, level.average_time
+            );
+        }
+        csv
+    }
+}
+
+// The summary, without the games one by one
+impl fmt::Display for BalanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let optional = |value: Option<f32>| value.map_or("-".to_string(), |v| format!("{v:.2}"));
+        writeln!(
+            f,
+            "Games: {} ({} cut short at {}s)",
+            self.games, self.cut_short, self.max_time
+        )?;
+        writeln!(f, "Average score: {:.0}", self.average_score)?;
+        writeln!(f, "Average level: {:.2}", self.average_level)?;
+        writeln!(
+            f,
+            "Average survival time: {:.1}s",
+            self.average_survival_time
+        )?;
+        writeln!(f, "Shots per kill: {}", optional(self.shots_per_kill))?;
+        writeln!(f, "Accuracy: {}", optional(self.accuracy))?;
+        writeln!(f, "Damage per game and deaths by source:")?;
+        for source in DamageSource::ALL {
+            let name = source.name();
+            writeln!(
+                f,
+                "  {name:<10} {:>8.1} {:>6}",
+                self.damage[name], self.deaths[name]
+            )?;
+        }
+        writeln!(
+            f,
+            "Asteroids: peak {} of {MAX_ASTEROIDS}, {:.1}% of the time at or over it",
+            self.peak_asteroids,
+            self.asteroid_saturation * 100.0
+        )?;
+        writeln!(
+            f,
+            "Bullets: peak {} of {MAX_BULLETS}, {:.1}% of the time at it",
+            self.peak_bullets,
+            self.bullet_saturation * 100.0
+        )?;
+        writeln!(f, "Level  Reached  Died  Average time")?;
+        for level in &self.levels {
+            writeln!(
+                f,
+                "{:>5}  {:>7}  {:>4}  {:>11.1}s",
+                level.level, level.reached, level.died, level.average_time
+            )?;
+        }
+        Ok(())
+    }
+}
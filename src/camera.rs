@@ -0,0 +1,112 @@
+use crate::game_objects::{Ships, MAX_SHIPS};
+use crate::shapes::Point;
+
+// -----------------------------------------------------------------------------
+
+const CAMERA_FOLLOW_RATE: f32 = 4.0; // Fraction of the distance to the target covered per second
+const CAMERA_ZOOM_RATE: f32 = 2.0;
+const CAMERA_FRAMING_MARGIN: f32 = 20.0; // World units kept around the players
+
+// Camera
+// Decides which part of the world is visible. When the view is as big as the world it stays put,
+// otherwise it follows the players around, zooming out to keep all of them in frame.
+#[derive(Clone)]
+pub struct Camera {
+    pub center: Point,
+    pub zoom: f32,
+    world_size: Point,
+    view_size: Point,
+}
+
+impl Camera {
+    pub fn new(world_size: Point, view_size: Point) -> Camera {
+        Camera {
+            center: world_size / 2.0,
+            zoom: 1.0,
+            world_size,
+            view_size: Point {
+                x: view_size.x.min(world_size.x),
+                y: view_size.y.min(world_size.y),
+            },
+        }
+    }
+
+    pub fn scrolling(&self) -> bool {
+        self.view_size.x < self.world_size.x || self.view_size.y < self.world_size.y
+    }
+
+    pub fn visible_size(&self) -> Point {
+        self.view_size * self.zoom
+    }
+
+    pub fn follow(&mut self, ships: &Ships, dt: f32) {
+        if !self.scrolling() {
+            return;
+        }
+        // Work relative to the current center so players across the wraparound are close together
+        let mut min = Point::default();
+        let mut max = Point::default();
+        let mut sum = Point::default();
+        let mut count = 0;
+        for i in 0..MAX_SHIPS {
+            if !ships.pool.exists(i) {
+                continue;
+            }
+            let d = self.wrapped_delta(ships.triangle[i].circumcenter());
+            if count == 0 {
+                (min, max) = (d, d);
+            } else {
+                min = Point {
+                    x: min.x.min(d.x),
+                    y: min.y.min(d.y),
+                };
+                max = Point {
+                    x: max.x.max(d.x),
+                    y: max.y.max(d.y),
+                };
+            }
+            sum += d;
+            count += 1;
+        }
+        if count == 0 {
+            return;
+        }
+
+        let target = if count == 1 { sum } else { (min + max) / 2.0 };
+        let t = (CAMERA_FOLLOW_RATE * dt).min(1.0);
+        self.center += target * t;
+        self.center.x = self.center.x.rem_euclid(self.world_size.x);
+        self.center.y = self.center.y.rem_euclid(self.world_size.y);
+
+        let spread = max - min;
+        let max_zoom =
+            (self.world_size.x / self.view_size.x).min(self.world_size.y / self.view_size.y);
+        let target_zoom = ((spread.x + 2.0 * CAMERA_FRAMING_MARGIN) / self.view_size.x)
+            .max((spread.y + 2.0 * CAMERA_FRAMING_MARGIN) / self.view_size.y)
+            .max(1.0)
+            .min(max_zoom);
+        self.zoom += (target_zoom - self.zoom) * (CAMERA_ZOOM_RATE * dt).min(1.0);
+    }
+
+    // Position relative to the top left corner of the visible area
+    pub fn to_view(&self, p: Point) -> Point {
+        if self.scrolling() {
+            self.wrapped_delta(p) + self.visible_size() / 2.0
+        } else {
+            p
+        }
+    }
+
+    pub fn is_visible(&self, center: Point, radius: f32) -> bool {
+        let p = self.to_view(center);
+        let size = self.visible_size();
+        p.x + radius >= 0.0
+            && p.x - radius <= size.x
+            && p.y + radius >= 0.0
+            && p.y - radius <= size.y
+    }
+
+    fn wrapped_delta(&self, p: Point) -> Point {
+        (p - self.center).wrapped(self.world_size)
+    }
+}
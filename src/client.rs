@@ -0,0 +1,363 @@
+use crate::game_objects::{Asteroids, Bullets, Player, Ships, MAX_SHIPS};
+use crate::input::PlayerInput;
+use crate::net::{
+    delta_decode, dequantize_position, dequantize_velocity, Message, NetState, SnapshotHistory,
+    INPUT_REDUNDANCY, MAX_PACKET_SIZE, PROTOCOL_VERSION,
+};
+use crate::physics::apply_gravity;
+use crate::shapes::{Point, Triangle};
+use crate::world::{valid_world_size, World};
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+
+const CONNECT_ATTEMPTS: u32 = 10;
+const CONNECT_RETRY_PERIOD: Duration = Duration::from_millis(500);
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+// Other objects are drawn this far in the past, between two snapshots that already arrived
+const INTERPOLATION_DELAY: f64 = 0.1; // Seconds
+const INTERPOLATION_SNAPSHOTS: usize = 16;
+// Inputs kept for prediction while the server catches up with them
+const MAX_PENDING_INPUTS: usize = 256;
+
+struct ReceivedSnapshot {
+    tick: u32,
+    last_input: Option<u32>,
+    state: NetState,
+}
+
+// Client
+// Sends the local player's inputs and rebuilds a world to draw from the server snapshots: the
+// local ship predicted from the inputs the server hasn't simulated yet, everything else
+// interpolated a little in the past
+pub struct Client {
+    socket: UdpSocket,
+    pub world: World,
+    pub player: Option<Player>,
+    pub tick_rate: f64,
+    input_sequence: u32,
+    // Sent and not simulated by the server yet, plus the last one that was
+    pending_inputs: VecDeque<(u32, PlayerInput)>,
+    baselines: SnapshotHistory,
+    snapshots: VecDeque<ReceivedSnapshot>,
+    latest_received: Instant,
+}
+
+impl Client {
+    pub fn connect(address: &str) -> Result<Client, String> {
+        let local = if address.starts_with('[') {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(local).map_err(|e| e.to_string())?;
+        socket
+            .connect(address)
+            .map_err(|e| format!("{address}: {e}"))?;
+        socket
+            .set_read_timeout(Some(CONNECT_RETRY_PERIOD))
+            .map_err(|e| e.to_string())?;
+
+        let hello = Message::Hello {
+            version: PROTOCOL_VERSION,
+        };
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+        for _ in 0..CONNECT_ATTEMPTS {
+            socket.send(&hello.encode()).ok();
+            let Ok(count) = socket.recv(&mut buffer) else {
+                continue;
+            };
+            match Message::decode(&buffer[..count]) {
+                Some(Message::Welcome {
+                    world_size,
+                    tick_rate,
+                    tuning,
+                }) => {
+                    if !valid_world_size(world_size) {
+                        return Err(format!("{address}: the server sent an invalid world size"));
+                    }
+                    tuning
+                        .validate()
+                        .map_err(|e| format!("{address}: the server sent invalid tuning, {e}"))?;
+                    socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+                    let mut world = World::new(world_size, 0);
+                    world.set_tuning(tuning);
+                    return Ok(Client {
+                        socket,
+                        world,
+                        player: None,
+                        tick_rate,
+                        input_sequence: 0,
+                        pending_inputs: VecDeque::new(),
+                        baselines: SnapshotHistory::default(),
+                        snapshots: VecDeque::new(),
+                        latest_received: Instant::now(),
+                    });
+                }
+                Some(Message::Refused { reason }) => return Err(format!("{address}: {reason}")),
+                _ => {}
+            }
+        }
+        Err(format!("{address}: the server didn't answer"))
+    }
+
+    // Once per tick, with the controls currently held down
+    pub fn send_input(&mut self, input: PlayerInput) {
+        self.input_sequence += 1;
+        self.pending_inputs.push_back((self.input_sequence, input));
+        if self.pending_inputs.len() > MAX_PENDING_INPUTS {
+            self.pending_inputs.pop_front();
+        }
+        let skip = self.pending_inputs.len().saturating_sub(INPUT_REDUNDANCY);
+        let message = Message::Input {
+            ack: self.snapshots.back().map(|snapshot| snapshot.tick),
+            inputs: self.pending_inputs.iter().skip(skip).copied().collect(),
+        };
+        self.socket.send(&message.encode()).ok();
+    }
+
+    // Takes in the snapshots that arrived, errors out when the server went quiet
+    pub fn receive(&mut self) -> Result<(), String> {
+        self.world.events.clear();
+        let mut buffer = vec![0; MAX_PACKET_SIZE];
+        loop {
+            let count = match self.socket.recv(&mut buffer) {
+                Ok(count) => count,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            };
+            if let Some(Message::Snapshot {
+                tick,
+                baseline,
+                player,
+                last_input,
+                data,
+            }) = Message::decode(&buffer[..count])
+            {
+                self.receive_snapshot(tick, baseline, player, last_input, &data);
+            }
+        }
+        if self.latest_received.elapsed() > SERVER_TIMEOUT {
+            return Err("Lost the connection to the server".to_string());
+        }
+        Ok(())
+    }
+
+    fn receive_snapshot(
+        &mut self,
+        tick: u32,
+        baseline: Option<u32>,
+        player: Option<u8>,
+        last_input: Option<u32>,
+        data: &[u8],
+    ) {
+        // Late ones are useless, and ones without their baseline can't be decoded
+        if self.snapshots.back().is_some_and(|s| s.tick >= tick) {
+            return;
+        }
+        let baseline = match baseline {
+            Some(baseline) => match self.baselines.get(baseline) {
+                Some(bytes) => Some(bytes),
+                None => return,
+            },
+            None => None,
+        };
+        let Some(bytes) = delta_decode(data, baseline) else {
+            return;
+        };
+        let Ok(state) = bincode::deserialize::<NetState>(&bytes) else {
+            return;
+        };
+        // Only the ships this client knows about, and nothing it would crash on
+        let player = match player.map(usize::from) {
+            Some(index) if index >= MAX_SHIPS => return,
+            index => index.and_then(Player::from_index),
+        };
+        if state.validate().is_err() {
+            return;
+        }
+        self.baselines.push(tick, bytes);
+        self.latest_received = Instant::now();
+        self.player = player;
+        self.world.events.extend_from_slice(&state.events);
+        if let Some(last_input) = last_input {
+            while self
+                .pending_inputs
+                .front()
+                .is_some_and(|&(sequence, _)| sequence < last_input)
+            {
+                self.pending_inputs.pop_front();
+            }
+        }
+        if self.snapshots.len() >= INTERPOLATION_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(ReceivedSnapshot {
+            tick,
+            last_input,
+            state,
+        });
+    }
+
+    // Rebuilds the world to draw for this frame
+    pub fn update_world(&mut self, dt: f32) {
+        let Some(latest) = self.snapshots.back() else {
+            return;
+        };
+        // Where the server should be by now, minus the delay
+        let render_tick = latest.tick as f64
+            + self.latest_received.elapsed().as_secs_f64() * self.tick_rate
+            - INTERPOLATION_DELAY * self.tick_rate;
+        let newer = self
+            .snapshots
+            .iter()
+            .position(|s| s.tick as f64 >= render_tick)
+            .unwrap_or(self.snapshots.len() - 1);
+        let older = newer.saturating_sub(1);
+        let (a, b) = (&self.snapshots[older].state, &self.snapshots[newer].state);
+        let (tick_a, tick_b) = (self.snapshots[older].tick, self.snapshots[newer].tick);
+        let t = if tick_b > tick_a {
+            ((render_tick - tick_a as f64) / (tick_b - tick_a) as f64).clamp(0.0, 1.0) as f32
+        } else {
+            1.0
+        };
+
+        let size = self.world.size;
+        let world = &mut self.world;
+        world.level = b.level;
+        world.score = b.score;
+        world.level_definition.name = b.level_name.clone();
+        world.level_definition.time_limit = b.time_limit;
+        world.level_time = b.level_time;
+        *world.gravity_wells = b.gravity_wells.clone();
+
+        world.asteroids.clear();
+        for asteroid in &b.asteroids {
+            let mut position = dequantize_position(asteroid.position, size);
+            if let Some(previous) = a.asteroids.iter().find(|old| old.id == asteroid.id) {
+                position = interpolate(
+                    dequantize_position(previous.position, size),
+                    position,
+                    t,
+                    size,
+                );
+            }
+            let velocity = dequantize_velocity(asteroid.velocity);
+            if let Some(handle) =
+                world
+                    .asteroids
+                    .create(asteroid.size, asteroid.material, position, velocity)
+            {
+                world.asteroids.hp[handle.index] = asteroid.hp;
+            }
+        }
+
+        world.bullets.clear();
+        for bullet in &b.bullets {
+            let mut position = dequantize_position(bullet.position, size);
+            if let Some(previous) = a.bullets.iter().find(|old| old.id == bullet.id) {
+                position = interpolate(
+                    dequantize_position(previous.position, size),
+                    position,
+                    t,
+                    size,
+                );
+            }
+            let velocity = dequantize_velocity(bullet.velocity);
+            if let Some(handle) = world.bullets.create(position, velocity.angle()) {
+                world.bullets.velocity[handle.index] = velocity;
+            }
+        }
+
+        let mut ships = b.ships.clone();
+        for i in 0..MAX_SHIPS {
+            let same_ship = a.ships.pool.exists(i)
+                && b.ships.pool.exists(i)
+                && a.ships.pool.handle(i) == b.ships.pool.handle(i);
+            if same_ship {
+                ships.triangle[i] =
+                    interpolate_triangle(a.ships.triangle[i], b.ships.triangle[i], t, size);
+            }
+        }
+        if let Some(player) = self.player {
+            let index = player.to_index();
+            let predicted = self.predict_ships();
+            if predicted.pool.exists(index) {
+                ships.triangle[index] = predicted.triangle[index];
+                ships.velocity[index] = predicted.velocity[index];
+            }
+        }
+        let world = &mut self.world;
+        *world.ships = ships;
+
+        world.particles.update_positions(dt);
+        world.particles.emit_for_events(&world.events);
+        world.particles.emit_exhaust(&world.ships, dt);
+    }
+
+    // The newest ships from the server, moved on by the inputs it hasn't simulated yet
+    fn predict_ships(&self) -> Ships {
+        let latest = self.snapshots.back().unwrap();
+        let mut ships = latest.state.ships.clone();
+        let Some(player) = self.player else {
+            return ships;
+        };
+        let dt = (1.0 / self.tick_rate) as f32;
+        let mut previous = PlayerInput::default();
+        let mut asteroids = Asteroids::default();
+        let mut bullets = Bullets::default();
+        for &(sequence, input) in &self.pending_inputs {
+            if latest.last_input.is_some_and(|last| sequence <= last) {
+                previous = input;
+                continue;
+            }
+            input.apply(previous, &mut ships, player);
+            previous = input;
+            apply_gravity(
+                &latest.state.gravity_wells,
+                &mut asteroids,
+                &mut bullets,
+                &mut ships,
+                dt,
+            );
+            ships.update_positions(self.world.size, dt);
+        }
+        ships
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.socket.send(&Message::Bye.encode()).ok();
+    }
+}
+
+// Straight from one position to the other, unless it wrapped around the world in between
+fn interpolate(from: Point, to: Point, t: f32, world_size: Point) -> Point {
+    if wrapped(from, to, world_size) {
+        to
+    } else {
+        from + (to - from) * t
+    }
+}
+
+fn interpolate_triangle(from: Triangle, to: Triangle, t: f32, world_size: Point) -> Triangle {
+    // All the vertices jump together, or the ship would get stretched across the world
+    if wrapped(from.circumcenter(), to.circumcenter(), world_size) {
+        return to;
+    }
+    Triangle {
+        v1: from.v1 + (to.v1 - from.v1) * t,
+        v2: from.v2 + (to.v2 - from.v2) * t,
+        v3: from.v3 + (to.v3 - from.v3) * t,
+    }
+}
+
+fn wrapped(from: Point, to: Point, world_size: Point) -> bool {
+    let d = to - from;
+    d.x.abs() > world_size.x / 2.0 || d.y.abs() > world_size.y / 2.0
+}
@@ -0,0 +1,644 @@
+use crate::net::{delta_decode, delta_encode};
+use crate::world::WorldState;
+
+use serde::ser::{self, Serialize};
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// -----------------------------------------------------------------------------
+
+const STATE_LOG_MAGIC: &[u8; 8] = b"RSTRDLOG";
+// Bump together with the snapshot version, the log holds the same WorldState
+const STATE_LOG_VERSION: u32 = 1;
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// Same on every run and platform for the same state: FNV-1a over its bincode encoding, which has
+// every field of every object, the pools and the RNG in a fixed order and byte order
+pub fn state_hash(state: &WorldState) -> u64 {
+    hash_bytes(&bincode::serialize(state).expect("States always serialize"))
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// -----------------------------------------------------------------------------
+
+// StateLog
+// The state after every tick, so two runs that should have been the same can be compared later.
+// The magic bytes and version like snapshots, then for each tick: the tick and the hash, the
+// length of the state and the state delta encoded against the previous one, little endian
+pub struct StateLog {
+    file: BufWriter<File>,
+    path: String,
+    previous: Vec<u8>,
+}
+
+impl StateLog {
+    pub fn create(path: &Path) -> Result<StateLog, String> {
+        let error = |e: std::io::Error| format!("{}: {e}", path.display());
+        let mut file = BufWriter::new(File::create(path).map_err(error)?);
+        file.write_all(STATE_LOG_MAGIC).map_err(error)?;
+        file.write_all(&STATE_LOG_VERSION.to_le_bytes())
+            .map_err(error)?;
+        Ok(StateLog {
+            file,
+            path: path.display().to_string(),
+            previous: Vec::new(),
+        })
+    }
+
+    pub fn record(&mut self, tick: u32, state: &WorldState) -> Result<(), String> {
+        let bytes = bincode::serialize(state).map_err(|e| format!("{}: {e}", self.path))?;
+        let delta = delta_encode(&bytes, Some(&self.previous));
+        let mut record = Vec::with_capacity(16 + delta.len());
+        record.extend_from_slice(&tick.to_le_bytes());
+        record.extend_from_slice(&hash_bytes(&bytes).to_le_bytes());
+        record.extend_from_slice(&(delta.len() as u32).to_le_bytes());
+        record.extend_from_slice(&delta);
+        self.file
+            .write_all(&record)
+            .map_err(|e| format!("{}: {e}", self.path))?;
+        self.previous = bytes;
+        Ok(())
+    }
+}
+
+impl Drop for StateLog {
+    fn drop(&mut self) {
+        self.file.flush().ok();
+    }
+}
+
+struct LogRecord {
+    tick: u32,
+    hash: u64,
+    state: Vec<u8>,
+}
+
+fn read_state_log(path: &Path) -> Result<Vec<LogRecord>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let error = |message: &str| format!("{}: {message}", path.display());
+    let body = bytes
+        .strip_prefix(STATE_LOG_MAGIC)
+        .ok_or_else(|| error("not a state log"))?;
+    let mut cursor = body;
+    let version = take_u32(&mut cursor).ok_or_else(|| error("state log is truncated"))?;
+    if version != STATE_LOG_VERSION {
+        return Err(error(&format!(
+            "state log version {version} is not supported, expected {STATE_LOG_VERSION}"
+        )));
+    }
+    let mut records: Vec<LogRecord> = Vec::new();
+    while !cursor.is_empty() {
+        let truncated = || error("state log is truncated");
+        let tick = take_u32(&mut cursor).ok_or_else(truncated)?;
+        let hash = take_u64(&mut cursor).ok_or_else(truncated)?;
+        let length = take_u32(&mut cursor).ok_or_else(truncated)? as usize;
+        if cursor.len() < length {
+            return Err(truncated());
+        }
+        let (delta, rest) = cursor.split_at(length);
+        cursor = rest;
+        let previous = records.last().map(|record| record.state.as_slice());
+        let state = delta_decode(delta, Some(previous.unwrap_or_default()))
+            .ok_or_else(|| error(&format!("state log is corrupted at tick {tick}")))?;
+        records.push(LogRecord { tick, hash, state });
+    }
+    Ok(records)
+}
+
+fn take_u32(bytes: &mut &[u8]) -> Option<u32> {
+    let (value, rest) = bytes.split_first_chunk::<4>()?;
+    *bytes = rest;
+    Some(u32::from_le_bytes(*value))
+}
+
+fn take_u64(bytes: &mut &[u8]) -> Option<u64> {
+    let (value, rest) = bytes.split_first_chunk::<8>()?;
+    *bytes = rest;
+    Some(u64::from_le_bytes(*value))
+}
+
+// -----------------------------------------------------------------------------
+
+// Where two logs stop matching
+pub enum LogDifference {
+    // One log has ticks the other doesn't
+    Length {
+        tick: u32,
+        missing_from: usize,
+    },
+    Field {
+        tick: u32,
+        // Like asteroids.velocity[3].x, the index is the object's slot
+        field: String,
+        values: [String; 2],
+    },
+    // The hashes differ but every field prints the same in both logs
+    Unprintable {
+        tick: u32,
+    },
+}
+
+impl fmt::Display for LogDifference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogDifference::Length { tick, missing_from } => write!(
+                f,
+                "Log {} ends before tick {tick}, the logs match until then",
+                missing_from + 1
+            ),
+            LogDifference::Field {
+                tick,
+                field,
+                values,
+            } => write!(
+                f,
+                "First difference after tick {tick}: {field} is {} in log 1 and {} in log 2",
+                values[0], values[1]
+            ),
+            LogDifference::Unprintable { tick } => write!(
+                f,
+                "First difference after tick {tick}: the states hash differently, but the \
+                 difference is in bits that print the same in both logs"
+            ),
+        }
+    }
+}
+
+// Ticks are compared by hash, only the first one that differs gets looked at field by field
+pub fn diff_state_logs(paths: [&Path; 2]) -> Result<Option<LogDifference>, String> {
+    let logs = [read_state_log(paths[0])?, read_state_log(paths[1])?];
+    for (a, b) in logs[0].iter().zip(&logs[1]) {
+        if a.tick != b.tick {
+            return Err(format!(
+                "The logs don't line up, tick {} against tick {}",
+                a.tick, b.tick
+            ));
+        }
+        if a.hash == b.hash {
+            continue;
+        }
+        let fields = [decode_fields(a, paths[0])?, decode_fields(b, paths[1])?];
+        let difference = fields[0].iter().zip(&fields[1]).find(|(a, b)| a != b);
+        let (field, values) = match difference {
+            Some(((field_a, value_a), (field_b, value_b))) => {
+                // Different fields at the same place means something has more objects
+                let field = if field_a == field_b {
+                    field_a.clone()
+                } else {
+                    format!("{field_a} / {field_b}")
+                };
+                (field, [value_a.clone(), value_b.clone()])
+            }
+            // Or one of them has more values at the end
+            None if fields[0].len() != fields[1].len() => {
+                let longer = (fields[1].len() > fields[0].len()) as usize;
+                let (field, value) = &fields[longer][fields[1 - longer].len()];
+                let mut values = ["nothing".to_string(), "nothing".to_string()];
+                values[longer] = value.clone();
+                (field.clone(), values)
+            }
+            None => return Ok(Some(LogDifference::Unprintable { tick: a.tick })),
+        };
+        return Ok(Some(LogDifference::Field {
+            tick: a.tick,
+            field,
+            values,
+        }));
+    }
+    let (a, b) = (&logs[0], &logs[1]);
+    if a.len() != b.len() {
+        let missing_from = (b.len() < a.len()) as usize;
+        let tick = logs[1 - missing_from][a.len().min(b.len())].tick;
+        return Ok(Some(LogDifference::Length { tick, missing_from }));
+    }
+    Ok(None)
+}
+
+// Every tick in a state log with the state after it, to play the run back
+pub fn load_state_log(path: &Path) -> Result<Vec<(u32, WorldState)>, String> {
+    read_state_log(path)?
+        .iter()
+        .map(|record| Ok((record.tick, decode_state(record, path)?)))
+        .collect()
+}
+
+fn decode_state(record: &LogRecord, path: &Path) -> Result<WorldState, String> {
+    bincode::deserialize(&record.state)
+        .map_err(|e| format!("{}: tick {}: {e}", path.display(), record.tick))
+}
+
+fn decode_fields(record: &LogRecord, path: &Path) -> Result<Vec<(String, String)>, String> {
+    Ok(state_fields(&decode_state(record, path)?))
+}
+
+// Every value in the state with its name, in the order they are hashed
+pub fn state_fields(state: &WorldState) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    state
+        .serialize(FieldSerializer {
+            fields: &mut fields,
+            path: String::new(),
+        })
+        .expect("States always serialize");
+    fields
+}
+
+// -----------------------------------------------------------------------------
+
+// Flattens anything serializable into named values, instead of bytes
+struct FieldSerializer<'a> {
+    fields: &'a mut Vec<(String, String)>,
+    path: String,
+}
+
+struct FieldCompound<'a> {
+    fields: &'a mut Vec<(String, String)>,
+    path: String,
+    index: usize,
+}
+
+#[derive(Debug)]
+struct FieldError(String);
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+impl ser::Error for FieldError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        FieldError(message.to_string())
+    }
+}
+
+impl<'a> FieldSerializer<'a> {
+    fn leaf(self, value: impl fmt::Debug) -> Result<(), FieldError> {
+        self.fields.push((self.path, format!("{value:?}")));
+        Ok(())
+    }
+
+    // Every NaN prints the same, the bits tell them apart
+    fn nan(self, bits: impl fmt::LowerHex) -> Result<(), FieldError> {
+        self.fields.push((self.path, format!("NaN ({bits:#x})")));
+        Ok(())
+    }
+
+    fn compound(self, suffix: &str) -> FieldCompound<'a> {
+        FieldCompound {
+            fields: self.fields,
+            path: self.path + suffix,
+            index: 0,
+        }
+    }
+}
+
+impl<'a> ser::Serializer for FieldSerializer<'a> {
+    type Ok = ();
+    type Error = FieldError;
+    type SerializeSeq = FieldCompound<'a>;
+    type SerializeTuple = FieldCompound<'a>;
+    type SerializeTupleStruct = FieldCompound<'a>;
+    type SerializeTupleVariant = FieldCompound<'a>;
+    type SerializeMap = FieldCompound<'a>;
+    type SerializeStruct = FieldCompound<'a>;
+    type SerializeStructVariant = FieldCompound<'a>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_i8(self, v: i8) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_i16(self, v: i16) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_i32(self, v: i32) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_i64(self, v: i64) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_u8(self, v: u8) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_u16(self, v: u16) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_u32(self, v: u32) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_u64(self, v: u64) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_u128(self, v: u128) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    // Debug prints the shortest text that reads back as the same bits
+    fn serialize_f32(self, v: f32) -> Result<(), FieldError> {
+        if v.is_nan() {
+            return self.nan(v.to_bits());
+        }
+        self.leaf(v)
+    }
+    fn serialize_f64(self, v: f64) -> Result<(), FieldError> {
+        if v.is_nan() {
+            return self.nan(v.to_bits());
+        }
+        self.leaf(v)
+    }
+    fn serialize_char(self, v: char) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_str(self, v: &str) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), FieldError> {
+        self.leaf(v)
+    }
+    fn serialize_none(self) -> Result<(), FieldError> {
+        self.leaf(None::<()>)
+    }
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), FieldError> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<(), FieldError> {
+        self.leaf(())
+    }
+    fn serialize_unit_struct(self, name: &'static str) -> Result<(), FieldError> {
+        self.leaf(name)
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<(), FieldError> {
+        self.fields.push((self.path, variant.to_string()));
+        Ok(())
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), FieldError> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), FieldError> {
+        let path = format!("{}::{variant}", self.path);
+        value.serialize(FieldSerializer {
+            fields: self.fields,
+            path,
+        })
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<FieldCompound<'a>, FieldError> {
+        Ok(self.compound(""))
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<FieldCompound<'a>, FieldError> {
+        Ok(self.compound(""))
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<FieldCompound<'a>, FieldError> {
+        Ok(self.compound(""))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<FieldCompound<'a>, FieldError> {
+        Ok(self.compound(&format!("::{variant}")))
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<FieldCompound<'a>, FieldError> {
+        Ok(self.compound(""))
+    }
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<FieldCompound<'a>, FieldError> {
+        Ok(self.compound(""))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<FieldCompound<'a>, FieldError> {
+        Ok(self.compound(&format!("::{variant}")))
+    }
+}
+
+impl FieldCompound<'_> {
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), FieldError> {
+        let path = format!("{}[{}]", self.path, self.index);
+        self.index += 1;
+        value.serialize(FieldSerializer {
+            fields: self.fields,
+            path,
+        })
+    }
+
+    fn field<T: ?Sized + Serialize>(&mut self, key: &str, value: &T) -> Result<(), FieldError> {
+        let path = if self.path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{key}", self.path)
+        };
+        value.serialize(FieldSerializer {
+            fields: self.fields,
+            path,
+        })
+    }
+}
+
+impl ser::SerializeSeq for FieldCompound<'_> {
+    type Ok = ();
+    type Error = FieldError;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), FieldError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), FieldError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for FieldCompound<'_> {
+    type Ok = ();
+    type Error = FieldError;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), FieldError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), FieldError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for FieldCompound<'_> {
+    type Ok = ();
+    type Error = FieldError;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), FieldError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), FieldError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for FieldCompound<'_> {
+    type Ok = ();
+    type Error = FieldError;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), FieldError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), FieldError> {
+        Ok(())
+    }
+}
+
+// Keys are left out of the names, there are no maps in the state anyway
+impl ser::SerializeMap for FieldCompound<'_> {
+    type Ok = ();
+    type Error = FieldError;
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, _key: &T) -> Result<(), FieldError> {
+        Ok(())
+    }
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), FieldError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), FieldError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for FieldCompound<'_> {
+    type Ok = ();
+    type Error = FieldError;
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), FieldError> {
+        self.field(key, value)
+    }
+    fn end(self) -> Result<(), FieldError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for FieldCompound<'_> {
+    type Ok = ();
+    type Error = FieldError;
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), FieldError> {
+        self.field(key, value)
+    }
+    fn end(self) -> Result<(), FieldError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{World, DEFAULT_WORLD_SIZE};
+
+    use std::path::PathBuf;
+
+    fn played_state(seed: u64) -> WorldState {
+        let mut world = World::new(DEFAULT_WORLD_SIZE, seed);
+        for _ in 0..60 {
+            world.update(1.0 / 60.0);
+        }
+        world.save_state()
+    }
+
+    fn live_asteroid(state: &WorldState) -> usize {
+        let pool = &state.asteroids.pool;
+        (0..pool.capacity()).find(|&i| pool.exists(i)).unwrap()
+    }
+
+    // Two one tick logs, the second with the state edited
+    fn diff_edited(name: &str, edit: impl FnOnce(&mut WorldState)) -> Option<LogDifference> {
+        let state = played_state(5);
+        let mut edited = state.clone();
+        edit(&mut edited);
+        diff_states(name, [state, edited])
+    }
+
+    fn diff_states(name: &str, states: [WorldState; 2]) -> Option<LogDifference> {
+        let dir = std::env::temp_dir();
+        let paths: [PathBuf; 2] =
+            [1, 2].map(|n| dir.join(format!("rusteroids-{name}-{}-{n}", std::process::id())));
+        for (path, state) in paths.iter().zip(&states) {
+            StateLog::create(path).unwrap().record(1, state).unwrap();
+        }
+        let difference = diff_state_logs([&paths[0], &paths[1]]).unwrap();
+        for path in &paths {
+            fs::remove_file(path).ok();
+        }
+        difference
+    }
+
+    #[test]
+    fn equal_states_hash_equally() {
+        let state = played_state(5);
+        assert_eq!(state_hash(&state), state_hash(&state.clone()));
+        assert_eq!(state_hash(&state), state_hash(&played_state(5)));
+        assert_ne!(state_hash(&state), state_hash(&played_state(6)));
+    }
+
+    #[test]
+    fn a_changed_field_is_reported_by_its_path() {
+        assert!(diff_edited("same", |_| {}).is_none());
+        let mut index = 0;
+        let difference = diff_edited("velocity", |state| {
+            index = live_asteroid(state);
+            state.asteroids.velocity[index].x += 1.0;
+        });
+        let Some(LogDifference::Field { tick, field, .. }) = difference else {
+            panic!("expected a field difference");
+        };
+        assert_eq!(tick, 1);
+        assert_eq!(field, format!("asteroids.velocity[{index}].x"));
+    }
+
+    #[test]
+    fn nans_with_different_bits_are_told_apart() {
+        let mut states = [played_state(5), played_state(5)];
+        states[0].level_time = f32::NAN;
+        states[1].level_time = f32::from_bits(f32::NAN.to_bits() + 1);
+        let difference = diff_states("nan", states);
+        let Some(LogDifference::Field { field, values, .. }) = difference else {
+            panic!("expected a field difference");
+        };
+        assert_eq!(field, "level_time");
+        assert!(values.iter().all(|value| value.starts_with("NaN (")));
+        assert_ne!(values[0], values[1]);
+    }
+}
@@ -0,0 +1,363 @@
+use crate::events::GameEvent;
+use crate::game_objects::Player;
+use crate::input::PlayerInput;
+use crate::levels::Campaign;
+use crate::sensors::{Sensors, FEATURES_PER_RAY};
+use crate::shapes::Point;
+use crate::tuning::Tuning;
+use crate::world::{World, DEFAULT_WORLD_SIZE};
+
+use serde::{Deserialize, Serialize};
+
+use std::f32::consts::TAU;
+
+// -----------------------------------------------------------------------------
+
+// Every combination of thrust, turning left, right or not at all, and firing
+pub const DISCRETE_ACTIONS: usize = 12;
+// Continuous turn values closer to 0 than this don't turn
+const TURN_DEAD_ZONE: f32 = 1.0 / 3.0;
+pub const SHIP_FEATURES: usize = 10;
+pub const ASTEROID_FEATURES: usize = 6;
+pub const OBSERVED_ASTEROIDS: usize = 8;
+// Without the sensors, those add FEATURES_PER_RAY for each ray
+pub const OBSERVATION_SIZE: usize = SHIP_FEATURES + ASTEROID_FEATURES * OBSERVED_ASTEROIDS;
+const SHIP_MAX_HP: f32 = 100.0;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Action {
+    // From 0 to DISCRETE_ACTIONS - 1: fire is the lowest bit, then turning (none, left, right)
+    // and thrust for the upper half
+    Discrete(usize),
+    // Each from -1 to 1, thrust and fire are on above 0 and turn is left below the dead zone and
+    // right above it
+    Continuous { thrust: f32, turn: f32, fire: f32 },
+}
+
+impl Action {
+    // Out of range discrete actions do nothing
+    pub fn to_input(self) -> PlayerInput {
+        match self {
+            Action::Discrete(action) if action < DISCRETE_ACTIONS => PlayerInput {
+                thrust: action / 6 == 1,
+                left: (action / 2) % 3 == 1,
+                right: (action / 2) % 3 == 2,
+                fire: action % 2 == 1,
+            },
+            Action::Discrete(_) => PlayerInput::default(),
+            Action::Continuous { thrust, turn, fire } => PlayerInput {
+                thrust: thrust > 0.0,
+                left: turn < -TURN_DEAD_ZONE,
+                right: turn > TURN_DEAD_ZONE,
+                fire: fire > 0.0,
+            },
+        }
+    }
+}
+
+// Observation
+// What the agent sees, scaled to roughly -1 to 1: positions and velocities are fractions of the
+// world size (per second), and everything else is relative to the ship
+#[derive(Clone, Default, Debug)]
+pub struct Observation {
+    // Alive, position, velocity, heading as cosine and sine, angular velocity in turns per
+    // second, HP and whether the gun can fire
+    pub ship: [f32; SHIP_FEATURES],
+    // Nearest asteroids first, by distance to their surface: present, offset, velocity relative
+    // to the ship and radius. All zeros when there are fewer
+    pub asteroids: [[f32; ASTEROID_FEATURES]; OBSERVED_ASTEROIDS],
+    // From the sensors, empty without them
+    pub rays: Vec<f32>,
+}
+
+impl Observation {
+    // All of the features in one vector, ship first and rays last
+    pub fn to_vec(&self) -> Vec<f32> {
+        let mut features = Vec::with_capacity(OBSERVATION_SIZE + self.rays.len());
+        features.extend_from_slice(&self.ship);
+        features.extend(self.asteroids.iter().flatten());
+        features.extend_from_slice(&self.rays);
+        features
+    }
+
+    // Like to_vec, into a slice of exactly that length
+    pub fn copy_to(&self, features: &mut [f32]) {
+        let (ship, rest) = features.split_at_mut(SHIP_FEATURES);
+        ship.copy_from_slice(&self.ship);
+        let (asteroids, rays) = rest.split_at_mut(ASTEROID_FEATURES * OBSERVED_ASTEROIDS);
+        for (features, asteroid) in asteroids.chunks_mut(ASTEROID_FEATURES).zip(&self.asteroids) {
+            features.copy_from_slice(asteroid);
+        }
+        rays.copy_from_slice(&self.rays);
+    }
+}
+
+// How much every step is worth, for shaping the reward
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RewardWeights {
+    // Per point scored
+    pub score: f32,
+    // Per second alive
+    pub survival: f32,
+    // Per bullet that hit something, together with shot this rewards accuracy
+    pub hit: f32,
+    // Per bullet fired
+    pub shot: f32,
+    // Per HP lost
+    pub damage: f32,
+    // When the ship is destroyed
+    pub death: f32,
+    // Per level cleared
+    pub level: f32,
+}
+
+impl Default for RewardWeights {
+    fn default() -> Self {
+        RewardWeights {
+            score: 0.01,
+            survival: 0.1,
+            hit: 0.05,
+            shot: -0.02,
+            damage: -0.01,
+            death: -1.0,
+            level: 1.0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct EnvConfig {
+    pub world_size: Point,
+    pub tuning: Tuning,
+    pub campaign: Campaign,
+    pub rewards: RewardWeights,
+    // Frames simulated for every step, all with the same action
+    pub frame_skip: u32,
+    // Steps before an episode is cut short, None to only end it when the ship is destroyed
+    pub max_steps: Option<u32>,
+    // Rays cast around the ship for the observation, None to leave them out
+    pub sensors: Option<Sensors>,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        EnvConfig {
+            world_size: DEFAULT_WORLD_SIZE,
+            tuning: Tuning::default(),
+            campaign: Campaign::default(),
+            rewards: RewardWeights::default(),
+            frame_skip: 4,
+            max_steps: Some(10_000),
+            sensors: Some(Sensors::default()),
+        }
+    }
+}
+
+// Counted since the episode started
+#[derive(Copy, Clone, Default, Debug)]
+pub struct StepInfo {
+    pub steps: u32,
+    pub score: u32,
+    pub level: i32,
+    pub hp: i8,
+    pub shots: u32,
+    // Deflected bullets can hit more than once
+    pub hits: u32,
+    // The episode ended because of max_steps, not because the ship was destroyed
+    pub truncated: bool,
+}
+
+impl EnvConfig {
+    // Length of Observation::to_vec
+    pub fn observation_size(&self) -> usize {
+        let rays = self.sensors.map_or(0, |sensors| sensors.rays);
+        OBSERVATION_SIZE + rays * FEATURES_PER_RAY
+    }
+}
+
+impl StepInfo {
+    pub fn accuracy(&self) -> f32 {
+        if self.shots == 0 {
+            0.0
+        } else {
+            self.hits as f32 / self.shots as f32
+        }
+    }
+}
+
+// Env
+// The game as a reinforcement learning environment, gym style: one player, fixed time steps and
+// no rendering. An episode is one life of the ship
+pub struct Env {
+    pub config: EnvConfig,
+    world: World,
+    input: PlayerInput,
+    info: StepInfo,
+    done: bool,
+}
+
+impl Env {
+    pub fn new(config: EnvConfig) -> Env {
+        let mut env = Env {
+            world: World::new(config.world_size, 0),
+            config,
+            input: PlayerInput::default(),
+            info: StepInfo::default(),
+            done: false,
+        };
+        env.reset(0);
+        env
+    }
+
+    // Starts a new episode, the same seed and actions always play out the same
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.world = World::new(self.config.world_size, seed);
+        self.world.campaign = self.config.campaign.clone();
+        self.world.set_tuning(self.config.tuning);
+        // The first level starts on the first update
+        self.world.update(self.dt());
+        self.world.events.clear();
+        self.input = PlayerInput::default();
+        self.info = StepInfo {
+            level: self.world.level,
+            hp: self.world.ships.hp[Player::PLAYER1.to_index()],
+            ..Default::default()
+        };
+        self.done = false;
+        self.observe()
+    }
+
+    // Steps after the end of an episode do nothing until the next reset
+    pub fn step(&mut self, action: Action) -> (Observation, f32, bool, StepInfo) {
+        if self.done {
+            return (self.observe(), 0.0, true, self.info);
+        }
+        let input = action.to_input();
+        input.apply(self.input, &mut self.world.ships, Player::PLAYER1);
+        self.input = input;
+
+        let rewards = self.config.rewards;
+        let dt = self.dt();
+        let mut reward = 0.0;
+        for _ in 0..self.config.frame_skip.max(1) {
+            let (score, level) = (self.world.score, self.world.level);
+            self.world.update(dt);
+            reward += rewards.survival * dt;
+            for event in &self.world.events {
+                match *event {
+                    GameEvent::BulletFired { .. } => {
+                        self.info.shots += 1;
+                        reward += rewards.shot;
+                    }
+                    GameEvent::BulletHit { .. } => {
+                        self.info.hits += 1;
+                        reward += rewards.hit;
+                    }
+                    GameEvent::ShipDamaged { damage, .. } => {
+                        reward += rewards.damage * damage as f32;
+                    }
+                    GameEvent::ShipDestroyed { .. } => {
+                        reward += rewards.death;
+                        self.done = true;
+                    }
+                    _ => {}
+                }
+            }
+            reward += rewards.score * self.world.score.saturating_sub(score) as f32;
+            reward += rewards.level * (self.world.level - level).max(0) as f32;
+            if self.done {
+                break;
+            }
+        }
+
+        let index = Player::PLAYER1.to_index();
+        self.info.steps += 1;
+        self.info.score = self.world.score;
+        self.info.level = self.world.level;
+        self.info.hp = if self.world.ships.pool.exists(index) {
+            self.world.ships.hp[index]
+        } else {
+            0
+        };
+        if !self.done
+            && self
+                .config
+                .max_steps
+                .is_some_and(|max| self.info.steps >= max)
+        {
+            self.info.truncated = true;
+            self.done = true;
+        }
+        (self.observe(), reward, self.done, self.info)
+    }
+
+    // Length of Observation::to_vec
+    pub fn observation_size(&self) -> usize {
+        self.config.observation_size()
+    }
+
+    pub fn observe(&self) -> Observation {
+        let world = &self.world;
+        let size = world.size;
+        let ships = &world.ships;
+        let index = Player::PLAYER1.to_index();
+        let mut observation = Observation {
+            rays: self
+                .config
+                .sensors
+                .map_or(Vec::new(), |sensors| sensors.features(world, index)),
+            ..Default::default()
+        };
+        if !ships.pool.exists(index) {
+            return observation;
+        }
+        let position = ships.triangle[index].circumcenter();
+        let velocity = ships.velocity[index];
+        let direction = ships.triangle[index].direction();
+        observation.ship = [
+            1.0,
+            position.x / size.x,
+            position.y / size.y,
+            velocity.x / size.x,
+            velocity.y / size.y,
+            direction.x,
+            direction.y,
+            ships.angular_velocity[index] / TAU,
+            ships.hp[index] as f32 / SHIP_MAX_HP,
+            (ships.gun_cooldown(index) <= 0.0) as u8 as f32,
+        ];
+
+        let asteroids = &world.asteroids;
+        let mut nearest: Vec<(f32, usize, Point)> = (0..asteroids.pool.capacity())
+            .filter(|&i| asteroids.pool.exists(i))
+            .map(|i| {
+                let circle = &asteroids.circle[i];
+                let offset = (circle.center - position).wrapped(size);
+                (offset.magnitude() - circle.radius, i, offset)
+            })
+            .collect();
+        nearest.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (features, &(_, i, offset)) in observation.asteroids.iter_mut().zip(&nearest) {
+            let relative_velocity = asteroids.velocity[i] - velocity;
+            *features = [
+                1.0,
+                offset.x / size.x,
+                offset.y / size.y,
+                relative_velocity.x / size.x,
+                relative_velocity.y / size.y,
+                asteroids.circle[i].radius / size.y,
+            ];
+        }
+        observation
+    }
+
+    // For drawing it, or looking at anything the observation leaves out
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    fn dt(&self) -> f32 {
+        (1.0 / self.world.tuning.frame_rate) as f32
+    }
+}
@@ -0,0 +1,72 @@
+use crate::game_objects::AsteroidMaterial;
+use crate::shapes::{Point, Triangle};
+
+use serde::{Deserialize, Serialize};
+
+// -----------------------------------------------------------------------------
+
+// Things that happened during a frame, for the systems that only react to the simulation
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub enum GameEvent {
+    BulletFired {
+        position: Point,
+    },
+    BulletHit {
+        position: Point,
+        normal: Point,
+    },
+    AsteroidDestroyed {
+        position: Point,
+        velocity: Point,
+        radius: f32,
+        material: AsteroidMaterial,
+    },
+    ShipDamaged {
+        position: Point,
+        damage: i8,
+        source: DamageSource,
+    },
+    ShipDestroyed {
+        triangle: Triangle,
+        velocity: Point,
+        cause: DamageSource,
+    },
+    // All the ships were lost, the world starts over right after this
+    GameOver {
+        score: u32,
+        level: i32,
+    },
+}
+
+// What hurt or destroyed a ship
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DamageSource {
+    ASTEROID,
+    PLANET,
+    BLACKHOLE,
+    // The level's time limit ran out
+    TIMEOUT,
+    // Its player left the server
+    DISCONNECT,
+}
+
+impl DamageSource {
+    pub const ALL: [DamageSource; 5] = [
+        DamageSource::ASTEROID,
+        DamageSource::PLANET,
+        DamageSource::BLACKHOLE,
+        DamageSource::TIMEOUT,
+        DamageSource::DISCONNECT,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DamageSource::ASTEROID => "asteroid",
+            DamageSource::PLANET => "planet",
+            DamageSource::BLACKHOLE => "blackhole",
+            DamageSource::TIMEOUT => "timeout",
+            DamageSource::DISCONNECT => "disconnect",
+        }
+    }
+}
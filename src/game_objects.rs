@@ -1,9 +1,11 @@
+use crate::events::GameEvent;
+use crate::pool::{Handle, OverflowPolicy, Pool};
 use crate::shapes::{Circle, Line, Point, Triangle};
 
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use std::f32::consts::PI;
-use std::time::Instant;
 
 // -----------------------------------------------------------------------------
 
@@ -14,37 +16,138 @@ const WRAPAROUND_OFFSET_OFFSET: f32 = 1.0;
 // -----------------------------------------------------------------------------
 
 pub const MAX_ASTEROIDS: usize = 52;
+const ASTEROIDS_MAX_CAPACITY: usize = MAX_ASTEROIDS * 4;
 pub const ASTEROID_RADIUS_SMALL: f32 = 2.0;
 pub const ASTEROID_RADIUS_MEDIUM: f32 = 4.0;
 pub const ASTEROID_RADIUS_LARGE: f32 = 6.0;
 const ASTEROID_MIN_SPEED: f32 = 10.0;
-pub const ASTEROID_MAX_SPEED_LOWER_LIMIT: f32 = 20.0;
-pub const ASTEROID_MAX_SPEED_HIGHER_LIMIT: f32 = 40.0;
+const ASTEROID_MAX_SPEED_LOWER_LIMIT: f32 = 20.0;
+const ASTEROID_MAX_SPEED_HIGHER_LIMIT: f32 = 40.0;
 const ASTEROID_HEALTH_SMALL: i8 = 25;
 const ASTEROID_HEALTH_MEDIUM: i8 = 50;
 const ASTEROID_HEALTH_LARGE: i8 = 75;
+const ASTEROID_HEALTH_ICE_SMALL: i8 = 25;
+const ASTEROID_HEALTH_ICE_MEDIUM: i8 = 25;
+const ASTEROID_HEALTH_ICE_LARGE: i8 = 25;
+const ASTEROID_HEALTH_METAL_SMALL: i8 = 50;
+const ASTEROID_HEALTH_METAL_MEDIUM: i8 = 100;
+const ASTEROID_HEALTH_METAL_LARGE: i8 = 125;
+const ASTEROID_HEALTH_EXPLOSIVE_SMALL: i8 = 25;
+const ASTEROID_HEALTH_EXPLOSIVE_MEDIUM: i8 = 25;
+const ASTEROID_HEALTH_EXPLOSIVE_LARGE: i8 = 50;
+const ASTEROID_SPEED_MULTIPLIER_ICE: f32 = 1.25;
+const ASTEROID_SPEED_MULTIPLIER_METAL: f32 = 0.6;
+const ASTEROID_DENSITY_METAL: f32 = 3.0;
+// Smaller ones are harder to hit
+const ASTEROID_POINTS_SMALL: u32 = 100;
+const ASTEROID_POINTS_MEDIUM: u32 = 50;
+const ASTEROID_POINTS_LARGE: u32 = 20;
+// Weights for [ROCK, ICE, METAL, EXPLOSIVE], the last row is used for all the levels after it
+const ASTEROID_MATERIAL_SPAWN_TABLE: [[u32; 4]; 6] = [
+    [100, 0, 0, 0],
+    [100, 0, 0, 0],
+    [70, 30, 0, 0],
+    [60, 25, 15, 0],
+    [50, 20, 20, 10],
+    [40, 20, 20, 20],
+];
+
+// Asteroid numbers that can be changed from the tuning file, defaults to the constants above
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AsteroidTuning {
+    pub min_speed: f32,
+    pub max_speed_lower_limit: f32,
+    pub max_speed_higher_limit: f32,
+    pub speed_multiplier_ice: f32,
+    pub speed_multiplier_metal: f32,
+    pub density_metal: f32,
+    pub health: AsteroidHealth,
+}
+
+impl Default for AsteroidTuning {
+    fn default() -> Self {
+        AsteroidTuning {
+            min_speed: ASTEROID_MIN_SPEED,
+            max_speed_lower_limit: ASTEROID_MAX_SPEED_LOWER_LIMIT,
+            max_speed_higher_limit: ASTEROID_MAX_SPEED_HIGHER_LIMIT,
+            speed_multiplier_ice: ASTEROID_SPEED_MULTIPLIER_ICE,
+            speed_multiplier_metal: ASTEROID_SPEED_MULTIPLIER_METAL,
+            density_metal: ASTEROID_DENSITY_METAL,
+            health: Default::default(),
+        }
+    }
+}
 
+// [SMALL, MEDIUM, LARGE] for each material
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AsteroidHealth {
+    pub rock: [i8; 3],
+    pub ice: [i8; 3],
+    pub metal: [i8; 3],
+    pub explosive: [i8; 3],
+}
+
+impl Default for AsteroidHealth {
+    fn default() -> Self {
+        AsteroidHealth {
+            rock: [
+                ASTEROID_HEALTH_SMALL,
+                ASTEROID_HEALTH_MEDIUM,
+                ASTEROID_HEALTH_LARGE,
+            ],
+            ice: [
+                ASTEROID_HEALTH_ICE_SMALL,
+                ASTEROID_HEALTH_ICE_MEDIUM,
+                ASTEROID_HEALTH_ICE_LARGE,
+            ],
+            metal: [
+                ASTEROID_HEALTH_METAL_SMALL,
+                ASTEROID_HEALTH_METAL_MEDIUM,
+                ASTEROID_HEALTH_METAL_LARGE,
+            ],
+            explosive: [
+                ASTEROID_HEALTH_EXPLOSIVE_SMALL,
+                ASTEROID_HEALTH_EXPLOSIVE_MEDIUM,
+                ASTEROID_HEALTH_EXPLOSIVE_LARGE,
+            ],
+        }
+    }
+}
+
+impl AsteroidHealth {
+    pub fn all(&self) -> [[i8; 3]; 4] {
+        [self.rock, self.ice, self.metal, self.explosive]
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Asteroids {
-    pub exists: [bool; MAX_ASTEROIDS],
-    pub hp: [i8; MAX_ASTEROIDS],
-    pub circle: [Circle; MAX_ASTEROIDS],
-    pub velocity: [Point; MAX_ASTEROIDS],
-    pub max_speed: f32,
+    pub pool: Pool,
+    pub hp: Vec<i8>,
+    pub circle: Vec<Circle>,
+    pub velocity: Vec<Point>,
+    pub material: Vec<AsteroidMaterial>,
+    pub tuning: AsteroidTuning,
 }
 
 impl Default for Asteroids {
     fn default() -> Self {
         Asteroids {
-            exists: [Default::default(); MAX_ASTEROIDS],
-            hp: [Default::default(); MAX_ASTEROIDS],
-            circle: [Default::default(); MAX_ASTEROIDS],
-            velocity: [Default::default(); MAX_ASTEROIDS],
-            max_speed: ASTEROID_MAX_SPEED_LOWER_LIMIT,
+            // Grow when a lot of asteroids split at once, drop the new pieces if even that is not enough
+            pool: Pool::new(MAX_ASTEROIDS, ASTEROIDS_MAX_CAPACITY, OverflowPolicy::DROP),
+            hp: vec![Default::default(); MAX_ASTEROIDS],
+            circle: vec![Default::default(); MAX_ASTEROIDS],
+            velocity: vec![Default::default(); MAX_ASTEROIDS],
+            material: vec![Default::default(); MAX_ASTEROIDS],
+            tuning: Default::default(),
         }
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum AsteroidSize {
     SMALL,
     MEDIUM,
@@ -63,43 +166,159 @@ impl AsteroidSize {
             Err(format!("Invalid radius {radius}"))
         }
     }
+
+    pub fn radius(&self) -> f32 {
+        match self {
+            AsteroidSize::SMALL => ASTEROID_RADIUS_SMALL,
+            AsteroidSize::MEDIUM => ASTEROID_RADIUS_MEDIUM,
+            AsteroidSize::LARGE => ASTEROID_RADIUS_LARGE,
+        }
+    }
+
+    pub fn to_index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn points(&self) -> u32 {
+        match self {
+            AsteroidSize::SMALL => ASTEROID_POINTS_SMALL,
+            AsteroidSize::MEDIUM => ASTEROID_POINTS_MEDIUM,
+            AsteroidSize::LARGE => ASTEROID_POINTS_LARGE,
+        }
+    }
+
+    pub fn smaller(&self) -> Option<AsteroidSize> {
+        match self {
+            AsteroidSize::SMALL => None,
+            AsteroidSize::MEDIUM => Some(AsteroidSize::SMALL),
+            AsteroidSize::LARGE => Some(AsteroidSize::MEDIUM),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AsteroidMaterial {
+    #[default]
+    ROCK,
+    // Low HP, shatters straight into small pieces
+    ICE,
+    // High HP, heavy, deflects bullets at glancing angles
+    METAL,
+    // Damages its neighbours when destroyed
+    EXPLOSIVE,
+}
+
+impl AsteroidMaterial {
+    const ALL: [AsteroidMaterial; 4] = [
+        AsteroidMaterial::ROCK,
+        AsteroidMaterial::ICE,
+        AsteroidMaterial::METAL,
+        AsteroidMaterial::EXPLOSIVE,
+    ];
+
+    pub fn random_for_level(level: i32, rng: &mut impl Rng) -> AsteroidMaterial {
+        let row = (level.max(0) as usize).min(ASTEROID_MATERIAL_SPAWN_TABLE.len() - 1);
+        let weights = ASTEROID_MATERIAL_SPAWN_TABLE[row];
+        let mut pick = rng.gen_range(0..weights.iter().sum::<u32>());
+        for (material, weight) in Self::ALL.iter().zip(weights) {
+            if pick < weight {
+                return *material;
+            }
+            pick -= weight;
+        }
+        AsteroidMaterial::ROCK
+    }
+
+    pub fn health(&self, size: AsteroidSize, tuning: &AsteroidTuning) -> i8 {
+        tuning.health.all()[*self as usize][size.to_index()]
+    }
+
+    pub fn speed_multiplier(&self, tuning: &AsteroidTuning) -> f32 {
+        match self {
+            AsteroidMaterial::ICE => tuning.speed_multiplier_ice,
+            AsteroidMaterial::METAL => tuning.speed_multiplier_metal,
+            _ => 1.0,
+        }
+    }
+
+    pub fn density(&self, tuning: &AsteroidTuning) -> f32 {
+        match self {
+            AsteroidMaterial::METAL => tuning.density_metal,
+            _ => 1.0,
+        }
+    }
 }
 
 impl Asteroids {
+    // For asteroids read from files, which may have been cut short or edited by hand
+    pub fn validate(&self) -> Result<(), String> {
+        self.pool.validate()?;
+        let capacity = self.pool.capacity();
+        let lengths = [
+            self.hp.len(),
+            self.circle.len(),
+            self.velocity.len(),
+            self.material.len(),
+        ];
+        if lengths.iter().any(|&len| len != capacity) {
+            return Err("arrays don't match the pool capacity".to_string());
+        }
+        for i in (0..capacity).filter(|&i| self.pool.exists(i)) {
+            let circle = &self.circle[i];
+            if AsteroidSize::from_radius(circle.radius).is_err() {
+                return Err(format!(
+                    "asteroid {i} has an invalid radius {}",
+                    circle.radius
+                ));
+            }
+            if !(circle.center.is_finite() && self.velocity[i].is_finite()) {
+                return Err(format!("asteroid {i} has an invalid position or velocity"));
+            }
+        }
+        Ok(())
+    }
+
     pub fn create(
         &mut self,
         size: AsteroidSize,
+        material: AsteroidMaterial,
         position: Point,
         velocity: Point,
-    ) -> Result<usize, String> {
-        // Create it in the fist position where exists is false
-        if let Some(index) = self.exists.iter().position(|&e| !e) {
-            self.exists[index] = true;
-            self.circle[index] = Circle {
-                center: position,
-                radius: match size {
-                    AsteroidSize::SMALL => ASTEROID_RADIUS_SMALL,
-                    AsteroidSize::MEDIUM => ASTEROID_RADIUS_MEDIUM,
-                    AsteroidSize::LARGE => ASTEROID_RADIUS_LARGE,
-                },
-            };
-            self.velocity[index] = velocity;
-            self.hp[index] = match size {
-                AsteroidSize::SMALL => ASTEROID_HEALTH_SMALL,
-                AsteroidSize::MEDIUM => ASTEROID_HEALTH_MEDIUM,
-                AsteroidSize::LARGE => ASTEROID_HEALTH_LARGE,
-            };
-            Ok(index)
-        } else {
-            Err("No space left to create asteroid.".to_string())
-        }
+    ) -> Option<Handle> {
+        let handle = self.pool.allocate()?;
+        self.fit_to_pool();
+        let index = handle.index;
+        self.circle[index] = Circle {
+            center: position,
+            radius: size.radius(),
+        };
+        self.velocity[index] = velocity;
+        self.material[index] = material;
+        self.hp[index] = material.health(size, &self.tuning);
+        Some(handle)
     }
 
-    pub fn create_at_border(&mut self, max_coords: Point) -> Result<usize, String> {
+    fn fit_to_pool(&mut self) {
+        let capacity = self.pool.capacity();
+        self.hp.resize(capacity, Default::default());
+        self.circle.resize(capacity, Default::default());
+        self.velocity.resize(capacity, Default::default());
+        self.material.resize(capacity, Default::default());
+    }
+
+    pub fn create_at_border(
+        &mut self,
+        max_coords: Point,
+        size: AsteroidSize,
+        material: AsteroidMaterial,
+        speed: f32,
+        rng: &mut impl Rng,
+    ) -> Option<Handle> {
         // Create it somewhere right at the border of the game canvas
-        let border = rand::thread_rng().gen_range(0..=3);
-        let mut x = rand::random::<f32>() * max_coords.x;
-        let mut y = rand::random::<f32>() * max_coords.y;
+        let border = rng.gen_range(0..=3);
+        let mut x = rng.gen::<f32>() * max_coords.x;
+        let mut y = rng.gen::<f32>() * max_coords.y;
         if border == 0 {
             x = -ASTEROID_RADIUS_LARGE;
         } else if border == 1 {
@@ -109,36 +328,41 @@ impl Asteroids {
         } else if border == 3 {
             y = max_coords.y + ASTEROID_RADIUS_LARGE;
         }
-        let speed =
-            ASTEROID_MIN_SPEED + rand::random::<f32>() * (self.max_speed - ASTEROID_MIN_SPEED);
-        let angle = rand::random::<f32>() * 2.0 * PI;
+        let angle = rng.gen::<f32>() * 2.0 * PI;
         self.create(
-            AsteroidSize::LARGE,
+            size,
+            material,
             Point { x, y },
             Point::from_polar(speed, angle),
         )
     }
 
     pub fn update_positions(&mut self, max_coords: Point, dt: f32) {
-        for i in 0..MAX_ASTEROIDS {
-            if !self.exists[i] {
+        for i in 0..self.pool.capacity() {
+            if !self.pool.exists(i) {
                 continue;
             }
+            let wraparound_offset = self.circle[i].radius * WRAPAROUND_OFFSET_OFFSET;
             self.circle[i].update_position_wraparound(
                 self.velocity[i],
                 max_coords,
-                self.circle[i].radius * WRAPAROUND_OFFSET_OFFSET,
+                wraparound_offset,
                 dt,
             );
         }
     }
 
     pub fn none_exist(&self) -> bool {
-        !self.exists.iter().any(|&e| e)
+        self.pool.none_exist()
     }
 
     pub fn clear(&mut self) {
-        self.exists.iter_mut().for_each(|e| *e = false)
+        self.pool.clear()
+    }
+
+    pub fn mass(&self, index: usize) -> f32 {
+        let radius = self.circle[index].radius;
+        radius * radius * radius * self.material[index].density(&self.tuning)
     }
 }
 
@@ -149,54 +373,116 @@ impl Asteroids {
 pub const MAX_BULLETS: usize = 32;
 const BULLET_SPEED: f32 = 200.0;
 const BULLET_LENGTH: f32 = 0.75;
-pub const BULLET_DAMAGE: i8 = 25;
+const BULLET_DAMAGE: i8 = 25;
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BulletTuning {
+    pub speed: f32,
+    pub length: f32,
+    pub damage: i8,
+}
+
+impl Default for BulletTuning {
+    fn default() -> Self {
+        BulletTuning {
+            speed: BULLET_SPEED,
+            length: BULLET_LENGTH,
+            damage: BULLET_DAMAGE,
+        }
+    }
+}
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Bullets {
-    pub exists: [bool; MAX_BULLETS],
-    pub line_segment: [Line; MAX_BULLETS],
-    pub velocity: [Point; MAX_BULLETS],
+    pub pool: Pool,
+    pub line_segment: Vec<Line>,
+    pub velocity: Vec<Point>,
+    pub tuning: BulletTuning,
 }
 
 impl Default for Bullets {
     fn default() -> Self {
         Bullets {
-            exists: [Default::default(); MAX_BULLETS],
-            line_segment: [Default::default(); MAX_BULLETS],
-            velocity: [Default::default(); MAX_BULLETS],
+            // Firing with all the bullets in flight makes the oldest one disappear
+            pool: Pool::new(MAX_BULLETS, MAX_BULLETS, OverflowPolicy::RECYCLE),
+            line_segment: vec![Default::default(); MAX_BULLETS],
+            velocity: vec![Default::default(); MAX_BULLETS],
+            tuning: Default::default(),
         }
     }
 }
 
 impl Bullets {
+    pub fn validate(&self) -> Result<(), String> {
+        self.pool.validate()?;
+        let capacity = self.pool.capacity();
+        if self.line_segment.len() != capacity || self.velocity.len() != capacity {
+            return Err("arrays don't match the pool capacity".to_string());
+        }
+        for i in (0..capacity).filter(|&i| self.pool.exists(i)) {
+            let line = &self.line_segment[i];
+            if !(line.p1.is_finite() && line.p2.is_finite() && self.velocity[i].is_finite()) {
+                return Err(format!("bullet {i} has an invalid position or velocity"));
+            }
+        }
+        Ok(())
+    }
+
     pub fn clear(&mut self) {
-        self.exists.iter_mut().for_each(|e| *e = false)
+        self.pool.clear()
     }
 
-    pub fn create(&mut self, position: Point, angle: f32) -> Result<usize, String> {
-        // Create it in the fist position where exists is false
-        if let Some(index) = self.exists.iter().position(|&e| !e) {
-            self.exists[index] = true;
-            self.line_segment[index] = Line {
-                p1: position + Point::from_polar(BULLET_LENGTH, angle),
-                p2: position,
-            };
-            self.velocity[index] = Point::from_polar(BULLET_SPEED, angle);
-            Ok(index)
-        } else {
-            Err("No space left to create bullet.".to_string())
-        }
+    pub fn create(&mut self, position: Point, angle: f32) -> Option<Handle> {
+        let handle = self.pool.allocate()?;
+        self.fit_to_pool();
+        let index = handle.index;
+        self.line_segment[index] = Line {
+            p1: position + Point::from_polar(self.tuning.length, angle),
+            p2: position,
+        };
+        self.velocity[index] = Point::from_polar(self.tuning.speed, angle);
+        Some(handle)
+    }
+
+    fn fit_to_pool(&mut self) {
+        let capacity = self.pool.capacity();
+        self.line_segment.resize(capacity, Default::default());
+        self.velocity.resize(capacity, Default::default());
+    }
+
+    pub fn accelerate(&mut self, index: usize, delta_velocity: Point) {
+        // Keep the bullet pointing where it's going so curved shots look right
+        self.velocity[index] += delta_velocity;
+        let angle = self.velocity[index].angle();
+        self.line_segment[index].p1 =
+            self.line_segment[index].p2 + Point::from_polar(self.tuning.length, angle);
+    }
+
+    pub fn deflect(&mut self, index: usize, contact: Point, normal: Point) {
+        let velocity = self.velocity[index];
+        // Only reflect the part of the velocity heading into the surface
+        self.velocity[index] = velocity - 2.0 * velocity.dot(normal).min(0.0) * normal;
+        let angle = self.velocity[index].angle();
+        self.line_segment[index] = Line {
+            p1: contact + Point::from_polar(self.tuning.length, angle),
+            p2: contact,
+        };
     }
 
     pub fn update_positions(&mut self, max_coords: Point, dt: f32) {
-        for i in 0..MAX_BULLETS {
-            if !self.exists[i] {
+        for i in 0..self.pool.capacity() {
+            if !self.pool.exists(i) {
                 continue;
             }
             self.line_segment[i].update_position(self.velocity[i], dt);
-            self.exists[i] = !(self.line_segment[i].p2.x < 0.0
+            if self.line_segment[i].p2.x < 0.0
                 || self.line_segment[i].p2.x > max_coords.x
                 || self.line_segment[i].p2.y < 0.0
-                || self.line_segment[i].p2.y > max_coords.y);
+                || self.line_segment[i].p2.y > max_coords.y
+            {
+                self.pool.free(i);
+            }
         }
     }
 }
@@ -208,7 +494,7 @@ impl Bullets {
 pub const MAX_SHIPS: usize = 2;
 const SHIP_WIDTH: f32 = 4.0;
 const SHIP_LENGTH: f32 = 5.0;
-pub const SHIP_MASS: f32 = 0.5;
+const SHIP_MASS: f32 = 0.5;
 const SHIP_SPEED_MAX: f32 = 100.0;
 const SHIP_ACCELERATION_LEVEL1: f32 = 15.0;
 const SHIP_ACCELERATION_LEVEL2: f32 = 30.0;
@@ -218,11 +504,11 @@ const SHIP_MAX_ANGULAR_VELOCITY: f32 = PI * 1.5;
 const SHIP_ANGULAR_ACCELERATION_LEVEL1: f32 = PI * 8.0;
 const SHIP_ANGULAR_ACCELERATION_LEVEL2: f32 = PI * 8.0;
 const SHIP_ANGULAR_ACCELERATION_LEVEL3: f32 = PI * 8.0;
-const SHIP_GUN_FIRE_RATE_NS_LEVEL1: u128 = 400000000;
-const SHIP_GUN_FIRE_RATE_NS_LEVEL2: u128 = 300000000;
-const SHIP_GUN_FIRE_RATE_NS_LEVEL3: u128 = 200000000;
+const SHIP_GUN_FIRE_PERIOD_LEVEL1: f32 = 0.4; // Seconds
+const SHIP_GUN_FIRE_PERIOD_LEVEL2: f32 = 0.3;
+const SHIP_GUN_FIRE_PERIOD_LEVEL3: f32 = 0.2;
 
-#[derive(Copy, Clone, Default)]
+#[derive(Copy, Clone, Default, Serialize, Deserialize)]
 pub enum UpgradeLevel {
     #[default]
     LEVEL1,
@@ -230,8 +516,54 @@ pub enum UpgradeLevel {
     LEVEL3,
 }
 
+impl UpgradeLevel {
+    pub fn to_index(&self) -> usize {
+        *self as usize
+    }
+}
+
+// Per upgrade level arrays go [LEVEL1, LEVEL2, LEVEL3]
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShipTuning {
+    pub mass: f32,
+    pub max_speed: f32,
+    pub acceleration: [f32; 3],
+    pub deacceleration: f32,
+    pub max_angular_velocity: f32,
+    pub angular_acceleration: [f32; 3],
+    pub gun_fire_period: [f32; 3],
+}
+
+impl Default for ShipTuning {
+    fn default() -> Self {
+        ShipTuning {
+            mass: SHIP_MASS,
+            max_speed: SHIP_SPEED_MAX,
+            acceleration: [
+                SHIP_ACCELERATION_LEVEL1,
+                SHIP_ACCELERATION_LEVEL2,
+                SHIP_ACCELERATION_LEVEL3,
+            ],
+            deacceleration: SHIP_DEACCELERATION,
+            max_angular_velocity: SHIP_MAX_ANGULAR_VELOCITY,
+            angular_acceleration: [
+                SHIP_ANGULAR_ACCELERATION_LEVEL1,
+                SHIP_ANGULAR_ACCELERATION_LEVEL2,
+                SHIP_ANGULAR_ACCELERATION_LEVEL3,
+            ],
+            gun_fire_period: [
+                SHIP_GUN_FIRE_PERIOD_LEVEL1,
+                SHIP_GUN_FIRE_PERIOD_LEVEL2,
+                SHIP_GUN_FIRE_PERIOD_LEVEL3,
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Ships {
-    pub exists: [bool; MAX_SHIPS],
+    pub pool: Pool,
     pub hp: [i8; MAX_SHIPS],
     pub triangle: [Triangle; MAX_SHIPS],
     pub velocity: [Point; MAX_SHIPS],
@@ -245,7 +577,9 @@ pub struct Ships {
     pub gun_auto: [bool; MAX_SHIPS],
     gun_trigger_pressed: [bool; MAX_SHIPS],
     gun_trigger_released: [bool; MAX_SHIPS],
-    gun_last_fired_t: [Instant; MAX_SHIPS],
+    // Seconds of simulation left before the gun can fire again
+    gun_cooldown: [f32; MAX_SHIPS],
+    pub tuning: ShipTuning,
     // laser? dmg 25 per [unit of time], ends at edge. rate of heating (fast), rate of cooling (slow), rate of cooling after overheating (slower)
     // bomb? -> radius? damage -> 100, ends at edge. replenish rate (slow). speed (slower than bullet)?
     // shield -> hp? replenish rate? deplenish rate?
@@ -254,7 +588,8 @@ pub struct Ships {
 impl Default for Ships {
     fn default() -> Self {
         Ships {
-            exists: [Default::default(); MAX_SHIPS],
+            // Slots are tied to players, so the pool never grows
+            pool: Pool::new(MAX_SHIPS, MAX_SHIPS, OverflowPolicy::DROP),
             hp: [Default::default(); MAX_SHIPS],
             triangle: [Default::default(); MAX_SHIPS],
             velocity: [Default::default(); MAX_SHIPS],
@@ -265,10 +600,11 @@ impl Default for Ships {
             side_thrusters_level: [Default::default(); MAX_SHIPS],
             side_thruster_pressed: [Default::default(); MAX_SHIPS],
             gun_level: [Default::default(); MAX_SHIPS],
-            gun_last_fired_t: [Instant::now(); MAX_SHIPS],
+            gun_cooldown: [Default::default(); MAX_SHIPS],
             gun_trigger_pressed: [Default::default(); MAX_SHIPS],
             gun_trigger_released: [Default::default(); MAX_SHIPS],
             gun_auto: [Default::default(); MAX_SHIPS],
+            tuning: Default::default(),
         }
     }
 }
@@ -286,44 +622,66 @@ impl RotationDirection {
 }
 
 impl Ships {
-    pub fn none_exist(&self) -> bool {
-        !self.exists.iter().any(|&e| e)
-    }
-
-    pub fn create(&mut self, position: Point) -> Result<usize, String> {
-        // Create it in the fist position where exists is false
-        if let Some(index) = self.exists.iter().position(|&e| !e) {
-            self.exists[index] = true;
-            self.hp[index] = 100;
-            self.triangle[index] = Triangle {
-                v1: Point {
-                    x: SHIP_WIDTH / 2.0,
-                    y: -SHIP_LENGTH,
-                },
-                v2: Point {
-                    x: SHIP_WIDTH,
-                    y: 0.0,
-                },
-                v3: Point { x: 0.0, y: 0.0 },
-            };
-            let delta = position - self.triangle[index].circumcenter();
-            self.triangle[index].update_position(delta, 1.0);
-            self.velocity[index] = Point { x: 0.0, y: 0.0 };
-            self.acceleration[index] = 0.0;
-            self.back_thruster_level[index] = UpgradeLevel::LEVEL1;
-            self.angular_velocity[index] = 0.0;
-            self.angular_acceleration[index] = 0.0;
-            self.side_thrusters_level[index] = UpgradeLevel::LEVEL1;
-            self.side_thruster_pressed[index] = [false, false];
-            self.gun_level[index] = UpgradeLevel::LEVEL1;
-            self.gun_last_fired_t[index] = Instant::now();
-            self.gun_trigger_pressed[index] = false;
-            self.gun_trigger_released[index] = true;
-            self.gun_auto[index] = false;
-            Ok(index)
-        } else {
-            Err("No space left to create ship.".to_string())
+    // For ships read from files or the network
+    pub fn validate(&self) -> Result<(), String> {
+        self.pool.validate()?;
+        if self.pool.capacity() != MAX_SHIPS {
+            return Err(format!("pool capacity must be {MAX_SHIPS}"));
+        }
+        for i in (0..MAX_SHIPS).filter(|&i| self.pool.exists(i)) {
+            let triangle = &self.triangle[i];
+            let finite = [triangle.v1, triangle.v2, triangle.v3, self.velocity[i]]
+                .iter()
+                .all(Point::is_finite);
+            if !(finite && self.angular_velocity[i].is_finite()) {
+                return Err(format!("ship {i} has an invalid position or velocity"));
+            }
         }
+        Ok(())
+    }
+
+    pub fn none_exist(&self) -> bool {
+        self.pool.none_exist()
+    }
+
+    pub fn create(&mut self, position: Point) -> Option<Handle> {
+        let handle = self.pool.allocate()?;
+        let index = handle.index;
+        self.hp[index] = 100;
+        self.triangle[index] = Triangle {
+            v1: Point {
+                x: SHIP_WIDTH / 2.0,
+                y: -SHIP_LENGTH,
+            },
+            v2: Point {
+                x: SHIP_WIDTH,
+                y: 0.0,
+            },
+            v3: Point { x: 0.0, y: 0.0 },
+        };
+        let delta = position - self.triangle[index].circumcenter();
+        self.triangle[index].update_position(delta, 1.0);
+        self.velocity[index] = Point { x: 0.0, y: 0.0 };
+        self.acceleration[index] = 0.0;
+        self.back_thruster_level[index] = UpgradeLevel::LEVEL1;
+        self.angular_velocity[index] = 0.0;
+        self.angular_acceleration[index] = 0.0;
+        self.side_thrusters_level[index] = UpgradeLevel::LEVEL1;
+        self.side_thruster_pressed[index] = [false, false];
+        self.gun_level[index] = UpgradeLevel::LEVEL1;
+        self.gun_cooldown[index] = self.tuning.gun_fire_period[UpgradeLevel::LEVEL1.to_index()];
+        self.gun_trigger_pressed[index] = false;
+        self.gun_trigger_released[index] = true;
+        self.gun_auto[index] = false;
+        Some(handle)
+    }
+
+    pub fn acceleration(&self, index: usize) -> f32 {
+        self.acceleration[index]
+    }
+
+    pub fn gun_cooldown(&self, index: usize) -> f32 {
+        self.gun_cooldown[index]
     }
 
     pub fn gun_trigger_pressed(&mut self, player: Player) {
@@ -342,11 +700,8 @@ impl Ships {
 
     pub fn accelerator_pressed(&mut self, player: Player) {
         let index = player.to_index();
-        self.acceleration[index] = match self.back_thruster_level[index] {
-            UpgradeLevel::LEVEL1 => SHIP_ACCELERATION_LEVEL1,
-            UpgradeLevel::LEVEL2 => SHIP_ACCELERATION_LEVEL2,
-            UpgradeLevel::LEVEL3 => SHIP_ACCELERATION_LEVEL3,
-        }
+        self.acceleration[index] =
+            self.tuning.acceleration[self.back_thruster_level[index].to_index()];
     }
 
     pub fn accelerator_released(&mut self, player: Player) {
@@ -358,14 +713,12 @@ impl Ships {
         let index = player.to_index();
         let dir_index = direction.to_index();
         if !self.side_thruster_pressed[index][dir_index] {
-            self.angular_acceleration[index] += match self.side_thrusters_level[index] {
-                UpgradeLevel::LEVEL1 => SHIP_ANGULAR_ACCELERATION_LEVEL1,
-                UpgradeLevel::LEVEL2 => SHIP_ANGULAR_ACCELERATION_LEVEL2,
-                UpgradeLevel::LEVEL3 => SHIP_ANGULAR_ACCELERATION_LEVEL3,
-            } * match direction {
-                RotationDirection::COUNTERCLOCKWISE => -1.0,
-                RotationDirection::CLOCKWISE => 1.0,
-            };
+            self.angular_acceleration[index] += self.tuning.angular_acceleration
+                [self.side_thrusters_level[index].to_index()]
+                * match direction {
+                    RotationDirection::COUNTERCLOCKWISE => -1.0,
+                    RotationDirection::CLOCKWISE => 1.0,
+                };
             self.side_thruster_pressed[index][dir_index] = true;
         }
     }
@@ -373,14 +726,12 @@ impl Ships {
     pub fn side_thruster_released(&mut self, player: Player, direction: RotationDirection) {
         let index = player.to_index();
         let dir_index = direction.to_index();
-        self.angular_acceleration[index] -= match self.side_thrusters_level[index] {
-            UpgradeLevel::LEVEL1 => SHIP_ANGULAR_ACCELERATION_LEVEL1,
-            UpgradeLevel::LEVEL2 => SHIP_ANGULAR_ACCELERATION_LEVEL2,
-            UpgradeLevel::LEVEL3 => SHIP_ANGULAR_ACCELERATION_LEVEL3,
-        } * match direction {
-            RotationDirection::COUNTERCLOCKWISE => -1.0,
-            RotationDirection::CLOCKWISE => 1.0,
-        };
+        self.angular_acceleration[index] -= self.tuning.angular_acceleration
+            [self.side_thrusters_level[index].to_index()]
+            * match direction {
+                RotationDirection::COUNTERCLOCKWISE => -1.0,
+                RotationDirection::CLOCKWISE => 1.0,
+            };
         self.side_thruster_pressed[index][dir_index] = false;
         if self.side_thruster_pressed[index].iter().all(|&x| !x) {
             self.angular_acceleration[index] = 0.0;
@@ -389,18 +740,14 @@ impl Ships {
 
     pub fn update_positions(&mut self, max_coords: Point, dt: f32) {
         for i in 0..MAX_SHIPS {
-            if !self.exists[i] {
+            if !self.pool.exists(i) {
                 continue;
             }
             // Update angular velocity
             if self.angular_acceleration[i] == 0.0 && self.angular_velocity[i] != 0.0 {
                 let prev_signum = self.angular_velocity[i].signum();
                 self.angular_velocity[i] -= self.angular_velocity[i].signum()
-                    * match self.side_thrusters_level[i] {
-                        UpgradeLevel::LEVEL1 => SHIP_ANGULAR_ACCELERATION_LEVEL1,
-                        UpgradeLevel::LEVEL2 => SHIP_ANGULAR_ACCELERATION_LEVEL2,
-                        UpgradeLevel::LEVEL3 => SHIP_ANGULAR_ACCELERATION_LEVEL3,
-                    }
+                    * self.tuning.angular_acceleration[self.side_thrusters_level[i].to_index()]
                     * dt;
                 if prev_signum != self.angular_velocity[i].signum() {
                     self.angular_velocity[i] = 0.0;
@@ -408,21 +755,21 @@ impl Ships {
             } else {
                 self.angular_velocity[i] += self.angular_acceleration[i] * dt;
             }
-            if self.angular_velocity[i].abs() > SHIP_MAX_ANGULAR_VELOCITY {
-                self.angular_velocity[i] =
-                    SHIP_MAX_ANGULAR_VELOCITY * self.angular_velocity[i].signum();
+            let max_angular_velocity = self.tuning.max_angular_velocity;
+            if self.angular_velocity[i].abs() > max_angular_velocity {
+                self.angular_velocity[i] = max_angular_velocity * self.angular_velocity[i].signum();
             }
             // Update angle
             self.triangle[i].rotate_around_circumcenter(self.angular_velocity[i], dt);
             // Update velocity
             if self.acceleration[i] == 0.0 && self.velocity[i].magnitude_squared() != 0.0 {
-                self.velocity[i] -= self.velocity[i].normalized() * SHIP_DEACCELERATION * dt;
+                self.velocity[i] -= self.velocity[i].normalized() * self.tuning.deacceleration * dt;
             } else {
                 self.velocity[i] += self.triangle[i].direction() * self.acceleration[i] * dt;
             }
             let speed = self.velocity[i].magnitude();
-            if speed > SHIP_SPEED_MAX {
-                self.velocity[i] *= SHIP_SPEED_MAX / speed;
+            if speed > self.tuning.max_speed {
+                self.velocity[i] *= self.tuning.max_speed / speed;
             }
             // Update position
             let wraparound_offset = self.triangle[i].circumradius() * WRAPAROUND_OFFSET_OFFSET;
@@ -435,29 +782,129 @@ impl Ships {
         }
     }
 
-    pub fn update_shooting(&mut self, bullets: &mut Bullets) {
+    pub fn update_shooting(&mut self, bullets: &mut Bullets, events: &mut Vec<GameEvent>, dt: f32) {
         for i in 0..MAX_SHIPS {
-            if self.exists[i]
-                && self.gun_trigger_pressed[i]
-                && self.gun_last_fired_t[i].elapsed().as_nanos()
-                    >= match self.gun_level[i] {
-                        UpgradeLevel::LEVEL1 => SHIP_GUN_FIRE_RATE_NS_LEVEL1,
-                        UpgradeLevel::LEVEL2 => SHIP_GUN_FIRE_RATE_NS_LEVEL2,
-                        UpgradeLevel::LEVEL3 => SHIP_GUN_FIRE_RATE_NS_LEVEL3,
-                    }
-            {
-                bullets
+            self.gun_cooldown[i] = (self.gun_cooldown[i] - dt).max(0.0);
+            if self.pool.exists(i) && self.gun_trigger_pressed[i] && self.gun_cooldown[i] == 0.0 {
+                if bullets
                     .create(self.triangle[i].v1, self.triangle[i].angle())
-                    .unwrap();
+                    .is_some()
+                {
+                    events.push(GameEvent::BulletFired {
+                        position: self.triangle[i].v1,
+                    });
+                }
                 if !self.gun_auto[i] {
                     self.gun_trigger_pressed[i] = false;
                 }
-                self.gun_last_fired_t[i] = Instant::now();
+                self.gun_cooldown[i] = self.tuning.gun_fire_period[self.gun_level[i].to_index()];
             }
         }
     }
 }
 
+// -----------------------------------------------------------------------------
+// Gravity well
+// -----------------------------------------------------------------------------
+
+pub const MAX_GRAVITY_WELLS: usize = 4;
+const PLANET_RADIUS: f32 = 8.0;
+const PLANET_GRAVITATIONAL_PARAMETER: f32 = 4000.0;
+const BLACKHOLE_RADIUS: f32 = 2.0;
+const BLACKHOLE_KILL_RADIUS: f32 = 3.0;
+const BLACKHOLE_GRAVITATIONAL_PARAMETER: f32 = 8000.0;
+
+#[derive(Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GravityWellKind {
+    #[default]
+    PLANET,
+    BLACKHOLE,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GravityWells {
+    pub pool: Pool,
+    pub kind: [GravityWellKind; MAX_GRAVITY_WELLS],
+    pub circle: [Circle; MAX_GRAVITY_WELLS],
+    // Gravitational constant times mass
+    pub gravitational_parameter: [f32; MAX_GRAVITY_WELLS],
+    // Anything closer than this to the center is destroyed, 0 for solid bodies
+    pub kill_radius: [f32; MAX_GRAVITY_WELLS],
+}
+
+impl Default for GravityWells {
+    fn default() -> Self {
+        GravityWells {
+            pool: Pool::new(MAX_GRAVITY_WELLS, MAX_GRAVITY_WELLS, OverflowPolicy::DROP),
+            kind: [Default::default(); MAX_GRAVITY_WELLS],
+            circle: [Default::default(); MAX_GRAVITY_WELLS],
+            gravitational_parameter: [Default::default(); MAX_GRAVITY_WELLS],
+            kill_radius: [Default::default(); MAX_GRAVITY_WELLS],
+        }
+    }
+}
+
+impl GravityWells {
+    // For gravity wells read from files or the network
+    pub fn validate(&self) -> Result<(), String> {
+        self.pool.validate()?;
+        if self.pool.capacity() != MAX_GRAVITY_WELLS {
+            return Err(format!("pool capacity must be {MAX_GRAVITY_WELLS}"));
+        }
+        for i in (0..MAX_GRAVITY_WELLS).filter(|&i| self.pool.exists(i)) {
+            let finite = self.circle[i].center.is_finite()
+                && self.circle[i].radius.is_finite()
+                && self.gravitational_parameter[i].is_finite()
+                && self.kill_radius[i].is_finite();
+            if !finite {
+                return Err(format!("gravity well {i} has an invalid position or size"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create(&mut self, kind: GravityWellKind, position: Point) -> Option<Handle> {
+        let handle = self.pool.allocate()?;
+        let index = handle.index;
+        self.kind[index] = kind;
+        let (radius, gravitational_parameter, kill_radius) = match kind {
+            GravityWellKind::PLANET => (PLANET_RADIUS, PLANET_GRAVITATIONAL_PARAMETER, 0.0),
+            GravityWellKind::BLACKHOLE => (
+                BLACKHOLE_RADIUS,
+                BLACKHOLE_GRAVITATIONAL_PARAMETER,
+                BLACKHOLE_KILL_RADIUS,
+            ),
+        };
+        self.circle[index] = Circle {
+            center: position,
+            radius,
+        };
+        self.gravitational_parameter[index] = gravitational_parameter;
+        self.kill_radius[index] = kill_radius;
+        Some(handle)
+    }
+
+    pub fn acceleration_at(&self, position: Point) -> Point {
+        let mut acceleration = Point::default();
+        for i in 0..MAX_GRAVITY_WELLS {
+            if !self.pool.exists(i) {
+                continue;
+            }
+            let d = self.circle[i].center - position;
+            // Inside the body the pull doesn't keep growing
+            let distance = d.magnitude().max(self.circle[i].radius);
+            acceleration +=
+                d * (self.gravitational_parameter[i] / (distance * distance * distance));
+        }
+        acceleration
+    }
+
+    pub fn clear(&mut self) {
+        self.pool.clear()
+    }
+}
+
 // -----------------------------------------------------------------------------
 
 // Enemy ships. Behavior? Patterns? -> random? seek? combines? pacman? weapons? shields? evade? how intelligent? up to how many at the same time?
@@ -476,4 +923,14 @@ impl Player {
     pub fn to_index(&self) -> usize {
         *self as usize
     }
+
+    pub fn from_index(index: usize) -> Option<Player> {
+        match index {
+            0 => Some(Player::PLAYER1),
+            1 => Some(Player::PLAYER2),
+            2 => Some(Player::PLAYER3),
+            3 => Some(Player::PLAYER4),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// -----------------------------------------------------------------------------
+
+pub const MAX_HIGH_SCORES_PER_MODE: usize = 10;
+pub const MAX_NAME_LENGTH: usize = 12;
+const HIGH_SCORES_FILE: &str = "highscores.toml";
+const DEFAULT_NAME: &str = "PLAYER";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HighScore {
+    // Scores are only compared within the same mode
+    pub mode: String,
+    pub name: String,
+    pub score: u32,
+    pub level: i32,
+    // Seconds since the Unix epoch
+    #[serde(default)]
+    pub date: u64,
+    // State log the run was written to, when there is one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay: Option<PathBuf>,
+}
+
+impl HighScore {
+    pub fn new(
+        mode: &str,
+        name: &str,
+        score: u32,
+        level: i32,
+        replay: Option<PathBuf>,
+    ) -> HighScore {
+        HighScore {
+            mode: mode.to_string(),
+            name: clean_name(name),
+            score,
+            level,
+            date: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            replay,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HighScoresFile<'a> {
+    #[serde(rename = "entry")]
+    entries: &'a [HighScore],
+}
+
+// HighScores
+// The best runs of each game mode, kept in the user data directory
+pub struct HighScores {
+    path: PathBuf,
+    entries: Vec<HighScore>,
+}
+
+impl HighScores {
+    // $XDG_DATA_HOME/rusteroids on Linux, the usual data directory elsewhere
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("rusteroids").join(HIGH_SCORES_FILE))
+    }
+
+    // Never fails: broken entries are skipped, and a file that can't be read at all is moved
+    // aside so it doesn't get overwritten
+    pub fn load(path: &Path) -> HighScores {
+        let mut high_scores = HighScores {
+            path: path.to_path_buf(),
+            entries: Vec::new(),
+        };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return high_scores,
+            Err(error) => {
+                eprintln!(
+                    "{}: {error}, starting a new high score table",
+                    path.display()
+                );
+                high_scores.back_up();
+                return high_scores;
+            }
+        };
+        let table: toml::Table = match toml::from_str(&text) {
+            Ok(table) => table,
+            Err(error) => {
+                eprintln!(
+                    "{}: {error}, starting a new high score table",
+                    path.display()
+                );
+                high_scores.back_up();
+                return high_scores;
+            }
+        };
+
+        let entries = match table.get("entry") {
+            Some(toml::Value::Array(entries)) => entries.as_slice(),
+            _ => &[],
+        };
+        let mut skipped = 0;
+        for entry in entries {
+            match entry.clone().try_into::<HighScore>() {
+                Ok(mut entry) => {
+                    entry.name = clean_name(&entry.name);
+                    high_scores.entries.push(entry);
+                }
+                Err(_) => skipped += 1,
+            }
+        }
+        if skipped > 0 {
+            eprintln!("{}: skipped {skipped} broken entries", path.display());
+        }
+        high_scores.sort();
+        high_scores
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = &self.path;
+        let text = toml::to_string(&HighScoresFile {
+            entries: &self.entries,
+        })
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+        }
+        // Written next to it and then renamed, so a crash never leaves half a file
+        let temporary = path.with_extension("toml.tmp");
+        fs::write(&temporary, text)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    // Best first
+    pub fn top<'a>(&'a self, mode: &'a str) -> impl Iterator<Item = &'a HighScore> {
+        self.entries.iter().filter(move |entry| entry.mode == mode)
+    }
+
+    pub fn qualifies(&self, mode: &str, score: u32) -> bool {
+        let mut top = self.top(mode);
+        score > 0
+            && top
+                .nth(MAX_HIGH_SCORES_PER_MODE - 1)
+                .is_none_or(|last| score > last.score)
+    }
+
+    // Position in the table for the mode, None when it didn't make it
+    pub fn insert(&mut self, entry: HighScore) -> Option<usize> {
+        if !self.qualifies(&entry.mode, entry.score) {
+            return None;
+        }
+        // Ties go to the older entries
+        let rank = self
+            .top(&entry.mode)
+            .filter(|e| e.score >= entry.score)
+            .count();
+        self.entries.push(entry);
+        self.sort();
+        Some(rank)
+    }
+
+    // Highest scores first, older ones first on ties, and only the top of each mode kept
+    fn sort(&mut self) {
+        self.entries
+            .sort_by(|a, b| b.score.cmp(&a.score).then(a.date.cmp(&b.date)));
+        let mut kept = Vec::with_capacity(self.entries.len());
+        for entry in self.entries.drain(..) {
+            let count = kept
+                .iter()
+                .filter(|e: &&HighScore| e.mode == entry.mode)
+                .count();
+            if count < MAX_HIGH_SCORES_PER_MODE {
+                kept.push(entry);
+            }
+        }
+        self.entries = kept;
+    }
+
+    fn back_up(&self) {
+        let backup = self.path.with_extension("toml.bak");
+        if fs::rename(&self.path, &backup).is_ok() {
+            eprintln!("The old table was moved to {}", backup.display());
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// Name typed in after a game over, for a score that made it into the table
+pub struct NameEntry {
+    pub score: u32,
+    pub level: i32,
+    pub name: String,
+}
+
+impl NameEntry {
+    pub fn new(score: u32, level: i32, name: &str) -> NameEntry {
+        NameEntry {
+            score,
+            level,
+            name: clean_name(name),
+        }
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        self.name = clean_name(&(self.name.clone() + text));
+    }
+
+    pub fn backspace(&mut self) {
+        self.name.pop();
+    }
+
+    pub fn finish(self, mode: &str, replay: Option<PathBuf>) -> HighScore {
+        let name = if self.name.trim().is_empty() {
+            DEFAULT_NAME.to_string()
+        } else {
+            self.name.trim().to_string()
+        };
+        HighScore::new(mode, &name, self.score, self.level, replay)
+    }
+}
+
+// The user's login, for when there's no way to type a name in
+pub fn default_name() -> String {
+    ["USER", "USERNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| DEFAULT_NAME.to_string())
+}
+
+// Only characters the HUD font can draw, and not too many of them
+fn clean_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_graphic() || *c == ' ')
+        .take(MAX_NAME_LENGTH)
+        .collect()
+}
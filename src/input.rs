@@ -0,0 +1,52 @@
+use crate::game_objects::{Player, RotationDirection, Ships};
+
+use serde::{Deserialize, Serialize};
+
+// -----------------------------------------------------------------------------
+
+// PlayerInput
+// The controls held down by a player during one tick, whoever (or whatever) is pressing them
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub thrust: bool,
+    pub left: bool,
+    pub right: bool,
+    pub fire: bool,
+}
+
+impl PlayerInput {
+    // Presses and releases whatever changed since the previous input, like the keyboard does
+    pub fn apply(&self, previous: PlayerInput, ships: &mut Ships, player: Player) {
+        if self.thrust != previous.thrust {
+            if self.thrust {
+                ships.accelerator_pressed(player);
+            } else {
+                ships.accelerator_released(player);
+            }
+        }
+        let sides = [
+            (
+                self.left,
+                previous.left,
+                RotationDirection::COUNTERCLOCKWISE,
+            ),
+            (self.right, previous.right, RotationDirection::CLOCKWISE),
+        ];
+        for (pressed, was_pressed, direction) in sides {
+            if pressed != was_pressed {
+                if pressed {
+                    ships.side_thruster_pressed(player, direction);
+                } else {
+                    ships.side_thruster_released(player, direction);
+                }
+            }
+        }
+        if self.fire != previous.fire {
+            if self.fire {
+                ships.gun_trigger_pressed(player);
+            } else {
+                ships.gun_trigger_released(player);
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::shapes::{Circle, Point};
+
+// -----------------------------------------------------------------------------
+
+// Intercept
+// Where and when a shot meets a moving target, and which way to fire it
+#[derive(Copy, Clone)]
+pub struct Intercept {
+    // Seconds after firing
+    pub time: f32,
+    pub angle: f32,
+    // Where the target's center will be, always inside the world
+    pub point: Point,
+    // Radians the angle can be off by and still hit the target's circle
+    pub tolerance: f32,
+}
+
+// The earliest intercept of a target moving in a straight line, with the shot also getting the
+// shooter's velocity (zero for bullets, which don't). Shots don't wrap around the edges of the
+// world but targets do, so every copy of the target across the edges is tried and only meeting
+// points inside the world count
+pub fn solve_intercept(
+    shooter: Point,
+    shooter_velocity: Point,
+    speed: f32,
+    target: &Circle,
+    target_velocity: Point,
+    world_size: Point,
+) -> Option<Intercept> {
+    let relative_velocity = target_velocity - shooter_velocity;
+    let mut best: Option<Intercept> = None;
+    for x in -1..=1 {
+        for y in -1..=1 {
+            let image = target.center
+                + Point {
+                    x: x as f32 * world_size.x,
+                    y: y as f32 * world_size.y,
+                };
+            let offset = image - shooter;
+            let Some(time) = lead_time(offset, relative_velocity, speed) else {
+                continue;
+            };
+            let point = image + target_velocity * time;
+            let inside =
+                (0.0..=world_size.x).contains(&point.x) && (0.0..=world_size.y).contains(&point.y);
+            if !inside || best.is_some_and(|best| best.time <= time) {
+                continue;
+            }
+            let aim = offset + relative_velocity * time;
+            let distance = aim.magnitude();
+            best = Some(Intercept {
+                time,
+                angle: aim.angle(),
+                point,
+                tolerance: target.radius.atan2(distance),
+            });
+        }
+    }
+    best
+}
+
+// The earliest time that |offset + velocity * t| = speed * t, for a target at offset moving at
+// velocity and a shot going out at speed
+pub fn lead_time(offset: Point, velocity: Point, speed: f32) -> Option<f32> {
+    let a = velocity.magnitude_squared() - speed * speed;
+    let b = 2.0 * offset.dot(velocity);
+    let c = offset.magnitude_squared();
+    // As fast as the shot, only catches up when it's coming closer
+    if a.abs() < f32::EPSILON {
+        return (b < 0.0).then(|| -c / b);
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    [(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)]
+        .into_iter()
+        .filter(|&t| t >= 0.0)
+        .min_by(f32::total_cmp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORLD: Point = Point { x: 100.0, y: 100.0 };
+
+    fn point(x: f32, y: f32) -> Point {
+        Point { x, y }
+    }
+
+    fn target(x: f32, y: f32) -> Circle {
+        Circle {
+            center: point(x, y),
+            radius: 2.0,
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn stationary_target() {
+        let shot = solve_intercept(
+            point(10.0, 50.0),
+            Point::default(),
+            100.0,
+            &target(30.0, 50.0),
+            Point::default(),
+            WORLD,
+        )
+        .unwrap();
+        assert!(close(shot.time, 0.2));
+        assert!(close(shot.angle, 0.0));
+        assert!(close(shot.point.x, 30.0) && close(shot.point.y, 50.0));
+        assert!(close(shot.tolerance, 2.0f32.atan2(20.0)));
+    }
+
+    #[test]
+    fn target_crossing_an_edge() {
+        // Going out the left edge, so its copy past the right edge comes in towards the shooter
+        let shot = solve_intercept(
+            point(80.0, 50.0),
+            Point::default(),
+            100.0,
+            &target(2.0, 50.0),
+            point(-60.0, 0.0),
+            WORLD,
+        )
+        .unwrap();
+        assert!(close(shot.time, 22.0 / 160.0));
+        assert!(close(shot.angle, 0.0));
+        assert!(close(shot.point.x, 102.0 - 60.0 * 22.0 / 160.0));
+        assert!(close(shot.point.y, 50.0));
+    }
+
+    #[test]
+    fn target_as_fast_as_the_shot() {
+        assert_eq!(lead_time(point(20.0, 0.0), point(100.0, 0.0), 100.0), None);
+        assert!(
+            lead_time(point(20.0, 0.0), point(-100.0, 0.0), 100.0).is_some_and(|t| close(t, 0.1))
+        );
+        let shot = solve_intercept(
+            point(10.0, 50.0),
+            Point::default(),
+            100.0,
+            &target(30.0, 50.0),
+            point(100.0, 0.0),
+            WORLD,
+        );
+        assert!(shot.is_none());
+    }
+
+    #[test]
+    fn target_outrunning_the_shot() {
+        assert_eq!(lead_time(point(20.0, 0.0), point(150.0, 0.0), 100.0), None);
+        let shot = solve_intercept(
+            point(10.0, 50.0),
+            Point::default(),
+            100.0,
+            &target(30.0, 50.0),
+            point(150.0, 0.0),
+            WORLD,
+        );
+        assert!(shot.is_none());
+    }
+}
@@ -54,6 +54,54 @@ pub fn circles_intersect(circle1: Circle, circle2: Circle) -> bool {
     d.magnitude_squared() <= sum_of_radii * sum_of_radii
 }
 
+// Rays start at the origin and go on forever in the direction, which has to be normalized. These
+// return how far along the ray the first hit is, 0 when the origin is already inside
+
+pub fn ray_circle_intersect(origin: Point, direction: Point, circle: &Circle) -> Option<f32> {
+    let to_center = circle.center - origin;
+    let c = to_center.magnitude_squared() - circle.radius * circle.radius;
+    if c <= 0.0 {
+        return Some(0.0);
+    }
+    let b = to_center.dot(direction);
+    let discriminant = b * b - c;
+    if b <= 0.0 || discriminant < 0.0 {
+        return None;
+    }
+    Some(b - discriminant.sqrt())
+}
+
+pub fn ray_line_segment_intersect(
+    origin: Point,
+    direction: Point,
+    line_segment: &Line,
+) -> Option<f32> {
+    let ab = line_segment.p2 - line_segment.p1;
+    let denominator = direction.x * ab.y - direction.y * ab.x;
+    // Parallel, grazing it counts as a miss
+    if denominator.abs() < f32::EPSILON {
+        return None;
+    }
+    let ao = line_segment.p1 - origin;
+    let distance = (ao.x * ab.y - ao.y * ab.x) / denominator;
+    let along_segment = (ao.x * direction.y - ao.y * direction.x) / denominator;
+    (distance >= 0.0 && (0.0..=1.0).contains(&along_segment)).then_some(distance)
+}
+
+pub fn ray_triangle_intersect(origin: Point, direction: Point, triangle: &Triangle) -> Option<f32> {
+    if point_in_triangle(triangle, origin) {
+        return Some(0.0);
+    }
+    [
+        (triangle.v1, triangle.v2),
+        (triangle.v2, triangle.v3),
+        (triangle.v3, triangle.v1),
+    ]
+    .iter()
+    .filter_map(|&(p1, p2)| ray_line_segment_intersect(origin, direction, &Line { p1, p2 }))
+    .min_by(f32::total_cmp)
+}
+
 fn point_in_circle(p: Point, circle: &Circle) -> bool {
     let d = p - circle.center;
     d.magnitude_squared() <= circle.radius * circle.radius
@@ -0,0 +1,277 @@
+use crate::game_objects::{
+    AsteroidMaterial, AsteroidSize, AsteroidTuning, Asteroids, GravityWellKind, GravityWells,
+};
+use crate::shapes::Point;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use std::f32::consts::PI;
+use std::fs;
+use std::path::Path;
+
+// -----------------------------------------------------------------------------
+
+const DEFAULT_CAMPAIGN: &str = include_str!("../levels/campaign.toml");
+// Procedural levels, used after the last level of the campaign
+const STARTING_ASTEROIDS_COUNT: u32 = 3;
+const SPEED_UP_LEVELS: i32 = 10; // Levels for the max speed to go from the lower to the higher limit
+const SPEED_GROWTH_AFTER: f32 = 0.02; // Fraction of the higher limit added per level after those
+const MAX_SPEED_MULTIPLIER: f32 = 2.0;
+const MAX_EXTRA_MEDIUM_ASTEROIDS: u32 = 10;
+const PLANETS_FROM_LEVEL: i32 = 4;
+const BLACKHOLES_FROM_LEVEL: i32 = 7;
+const WAVES_FROM_LEVEL: i32 = 10;
+const WAVE_DELAY: f32 = 30.0; // Seconds
+
+// A set of asteroids spawned together
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AsteroidGroup {
+    pub count: u32,
+    #[serde(default = "default_size")]
+    pub size: AsteroidSize,
+    // Picked at random with the odds for the level when left out
+    pub material: Option<AsteroidMaterial>,
+    // [min, max], the usual speeds for the level when left out
+    pub speed: Option<[f32; 2]>,
+    // Fraction of the world size, at the border of the world when left out
+    pub position: Option<[f32; 2]>,
+}
+
+fn default_size() -> AsteroidSize {
+    AsteroidSize::LARGE
+}
+
+impl AsteroidGroup {
+    fn at_border(size: AsteroidSize, count: u32) -> AsteroidGroup {
+        AsteroidGroup {
+            count,
+            size,
+            material: None,
+            speed: None,
+            position: None,
+        }
+    }
+}
+
+// Asteroids that arrive while the level is being played, or as soon as the others are cleared
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Wave {
+    // Seconds since the level started
+    pub delay: f32,
+    pub asteroids: Vec<AsteroidGroup>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hazard {
+    pub kind: GravityWellKind,
+    // Fraction of the world size
+    pub position: [f32; 2],
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelDefinition {
+    #[serde(default)]
+    pub name: String,
+    // Seconds to clear the level before the ships are lost
+    pub time_limit: Option<f32>,
+    #[serde(default)]
+    pub asteroids: Vec<AsteroidGroup>,
+    // Sorted by delay once loaded
+    #[serde(default)]
+    pub waves: Vec<Wave>,
+    #[serde(default)]
+    pub hazards: Vec<Hazard>,
+}
+
+// Campaign
+// The authored levels, played in order, with procedural levels going on forever after them
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Campaign {
+    #[serde(rename = "level", default)]
+    pub levels: Vec<LevelDefinition>,
+}
+
+impl Default for Campaign {
+    fn default() -> Self {
+        Campaign::parse(DEFAULT_CAMPAIGN).expect("The built-in campaign is invalid")
+    }
+}
+
+impl Campaign {
+    pub fn load(path: &Path) -> Result<Campaign, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        Campaign::parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Campaign, String> {
+        let mut campaign: Campaign = toml::from_str(text).map_err(|e| e.to_string())?;
+        for (i, level) in campaign.levels.iter_mut().enumerate() {
+            level
+                .validate()
+                .map_err(|e| format!("level {}: {e}", i + 1))?;
+            level.waves.sort_by(|a, b| a.delay.total_cmp(&b.delay));
+        }
+        Ok(campaign)
+    }
+
+    // Levels count from 0
+    pub fn level(&self, level: i32) -> LevelDefinition {
+        match self.levels.get(level.max(0) as usize) {
+            Some(definition) => definition.clone(),
+            None => procedural_level(level),
+        }
+    }
+}
+
+impl LevelDefinition {
+    pub fn validate(&self) -> Result<(), String> {
+        // TOML has nan and inf, written so they fail the checks instead of slipping past them
+        if self.time_limit.is_some_and(|t| !(t > 0.0 && t.is_finite())) {
+            return Err("time_limit must be positive".to_string());
+        }
+        let groups = || {
+            self.asteroids
+                .iter()
+                .chain(self.waves.iter().flat_map(|wave| &wave.asteroids))
+        };
+        if groups().all(|group| group.count == 0) {
+            return Err("there must be at least one asteroid".to_string());
+        }
+        for group in groups() {
+            let valid_speed = |[min, max]: [f32; 2]| max.is_finite() && (0.0..=max).contains(&min);
+            if group.speed.is_some_and(|speed| !valid_speed(speed)) {
+                return Err("asteroid speed must be [min, max] with 0 <= min <= max".to_string());
+            }
+            if group.position.is_some_and(|p| !in_world(p)) {
+                return Err("asteroid position must be from 0 to 1".to_string());
+            }
+        }
+        if self
+            .waves
+            .iter()
+            .any(|wave| !(wave.delay >= 0.0 && wave.delay.is_finite()))
+        {
+            return Err("wave delay can't be negative".to_string());
+        }
+        if self.hazards.iter().any(|hazard| !in_world(hazard.position)) {
+            return Err("hazard position must be from 0 to 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Also false for nan and inf
+fn in_world(position: [f32; 2]) -> bool {
+    position.iter().all(|x| (0.0..=1.0).contains(x))
+}
+
+// -----------------------------------------------------------------------------
+
+// Keeps the old endless formula: more and faster asteroids, then hazards and waves
+fn procedural_level(level: i32) -> LevelDefinition {
+    let large = STARTING_ASTEROIDS_COUNT + level.clamp(0, SPEED_UP_LEVELS) as u32;
+    let medium = (((level - SPEED_UP_LEVELS).max(0) / 2) as u32).min(MAX_EXTRA_MEDIUM_ASTEROIDS);
+    let mut asteroids = vec![AsteroidGroup::at_border(AsteroidSize::LARGE, large)];
+    if medium > 0 {
+        asteroids.push(AsteroidGroup::at_border(AsteroidSize::MEDIUM, medium));
+    }
+
+    // Kept away from the center, where the ships respawn
+    let mut hazards = Vec::new();
+    if level >= PLANETS_FROM_LEVEL {
+        hazards.push(Hazard {
+            kind: GravityWellKind::PLANET,
+            position: [0.25, 0.3],
+        });
+    }
+    if level >= BLACKHOLES_FROM_LEVEL {
+        hazards.push(Hazard {
+            kind: GravityWellKind::BLACKHOLE,
+            position: [0.75, 0.7],
+        });
+    }
+
+    let mut waves = Vec::new();
+    if level >= WAVES_FROM_LEVEL {
+        waves.push(Wave {
+            delay: WAVE_DELAY,
+            asteroids: vec![AsteroidGroup::at_border(
+                AsteroidSize::MEDIUM,
+                (level / 5) as u32,
+            )],
+        });
+    }
+
+    LevelDefinition {
+        name: String::new(),
+        time_limit: None,
+        asteroids,
+        waves,
+        hazards,
+    }
+}
+
+// Top speed of the asteroids when a group doesn't set its own
+pub fn level_max_speed(level: i32, tuning: &AsteroidTuning) -> f32 {
+    let lower = tuning.max_speed_lower_limit;
+    let higher = tuning.max_speed_higher_limit;
+    if level <= SPEED_UP_LEVELS {
+        lower + level.max(0) as f32 * (higher - lower) / SPEED_UP_LEVELS as f32
+    } else {
+        let growth = 1.0 + (level - SPEED_UP_LEVELS) as f32 * SPEED_GROWTH_AFTER;
+        higher * growth.min(MAX_SPEED_MULTIPLIER)
+    }
+}
+
+pub fn spawn_asteroids(
+    groups: &[AsteroidGroup],
+    asteroids: &mut Asteroids,
+    world_size: Point,
+    level: i32,
+    rng: &mut impl Rng,
+) {
+    for group in groups {
+        for _ in 0..group.count {
+            let tuning = &asteroids.tuning;
+            let material = group
+                .material
+                .unwrap_or_else(|| AsteroidMaterial::random_for_level(level, rng));
+            let [min, max] = group
+                .speed
+                .unwrap_or([tuning.min_speed, level_max_speed(level, tuning)]);
+            let speed = (min + rng.gen::<f32>() * (max - min)) * material.speed_multiplier(tuning);
+            match group.position {
+                Some([x, y]) => {
+                    let position = Point {
+                        x: x * world_size.x,
+                        y: y * world_size.y,
+                    };
+                    let angle = rng.gen::<f32>() * 2.0 * PI;
+                    let velocity = Point::from_polar(speed, angle);
+                    asteroids.create(group.size, material, position, velocity);
+                }
+                None => {
+                    asteroids.create_at_border(world_size, group.size, material, speed, rng);
+                }
+            }
+        }
+    }
+}
+
+pub fn create_hazards(hazards: &[Hazard], wells: &mut GravityWells, world_size: Point) {
+    wells.clear();
+    for hazard in hazards {
+        let [x, y] = hazard.position;
+        let position = Point {
+            x: x * world_size.x,
+            y: y * world_size.y,
+        };
+        wells.create(hazard.kind, position);
+    }
+}
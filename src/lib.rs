@@ -0,0 +1,29 @@
+// The simulation and the drawing of it, without any windows, sound or networking loops, for running
+// the game from other programs, like the reinforcement learning environment
+pub mod autopilot;
+pub mod backend;
+pub mod balance;
+pub mod camera;
+pub mod desync;
+pub mod env;
+pub mod events;
+pub mod game_objects;
+pub mod highscores;
+pub mod input;
+pub mod intercept;
+pub mod intersect;
+pub mod levels;
+pub mod net;
+pub mod particles;
+pub mod physics;
+pub mod pool;
+#[cfg(feature = "python")]
+mod python;
+pub mod render;
+pub mod rollback;
+pub mod sensors;
+pub mod shapes;
+pub mod snapshot;
+pub mod tuning;
+pub mod vec_env;
+pub mod world;
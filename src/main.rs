@@ -1,69 +1,211 @@
-mod game_objects;
-mod intersect;
-mod physics;
-mod render;
-mod shapes;
-
-use game_objects::{
-    Asteroids, Bullets, Player, RotationDirection, Ships, ASTEROID_MAX_SPEED_HIGHER_LIMIT,
-    ASTEROID_MAX_SPEED_LOWER_LIMIT,
+mod audio;
+mod client;
+mod server;
+mod settings;
+mod svg;
+#[cfg(unix)]
+mod terminal;
+
+use rusteroids::{
+    autopilot, backend, balance, camera, desync, events, game_objects, highscores, input, levels,
+    net, physics, pool, render, rollback, shapes, snapshot, tuning, world,
 };
-use physics::{asteroid_asteroid_collisions, asteroid_bullet_collisions, asteroid_ship_collisions};
+
+use audio::Audio;
+use autopilot::Autopilot;
+use backend::{RenderBackend, SdlBackend};
+use balance::{run_balance, BalanceConfig};
+use camera::Camera;
+use client::Client;
+use desync::{diff_state_logs, StateLog};
+use events::GameEvent;
+use game_objects::{Player, RotationDirection};
+use highscores::{default_name, HighScore, HighScores, NameEntry};
+use input::PlayerInput;
+use levels::Campaign;
 use render::Renderer;
+use rollback::{loopback_test, RollbackSession, UdpTransport};
+use server::Server;
+use settings::Settings;
+use shapes::Point;
+use snapshot::{load_snapshot, save_snapshot};
+use svg::SvgBackend;
+#[cfg(unix)]
+use terminal::{TerminalBackend, TerminalInput};
+use tuning::{Tuning, TuningWatcher};
+use world::World;
 
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::{KeyboardState, Keycode, Scancode};
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::RenderTarget;
+use sdl2::surface::Surface;
+use sdl2::video::Window;
+use sdl2::{EventPump, Sdl};
 
+use std::path::{self, Path, PathBuf};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
-const TARGET_FRAME_RATE: f64 = 120.0; // Frames per second, must be more than 1
-const PERIOD: f64 = 1000000000.0 / TARGET_FRAME_RATE; // Nanoseconds
+// Redrawing the whole terminal is slow, so it's refreshed less often
+#[cfg(unix)]
+const TERMINAL_FRAME_RATE: f64 = 30.0;
+// Frames played by loopback runs unless --frames says otherwise
+const LOOPBACK_FRAMES: u32 = 3600;
 
 pub fn main() {
+    let settings = Settings::from_args().unwrap_or_else(|error| exit_with_error(error));
+    if let Some([a, b]) = &settings.diff_logs {
+        compare_state_logs(a, b);
+    } else if let Some(address) = &settings.server {
+        run_server(&settings, address);
+    } else if let Some(address) = &settings.connect {
+        run_client(&settings, address);
+    } else if let Some(address) = &settings.versus {
+        run_versus(&settings, address);
+    } else if settings.loopback {
+        run_loopback(&settings);
+    } else if let Some(games) = settings.balance {
+        run_balance_games(&settings, games);
+    } else if settings.headless {
+        run_headless(&settings);
+    } else if settings.terminal {
+        #[cfg(unix)]
+        run_terminal(&settings);
+        #[cfg(not(unix))]
+        exit_with_error("The terminal renderer is only available on Unix".to_string());
+    } else {
+        run_windowed(&settings);
+    }
+}
+
+fn run_headless(settings: &Settings) {
+    // Sound gets synthesized but goes nowhere
+    sdl2::hint::set("SDL_AUDIODRIVER", "dummy");
+    let sdl_context = sdl2::init().unwrap_or_else(|error| exit_with_error(error));
+    let mut audio = init_audio(&sdl_context, settings);
+    // Software rendering into memory, no window or video subsystem needed
+    let (width, height) = settings.resolution;
+    let canvas = Surface::new(width, height, PixelFormatEnum::RGB888)
+        .and_then(|surface| surface.into_canvas())
+        .unwrap_or_else(|error| exit_with_error(error));
+    let camera = Camera::new(
+        settings.world_size,
+        settings.view_size.unwrap_or(settings.world_size),
+    );
+    let mut renderer = Renderer::new(SdlBackend::new(canvas), camera, settings.scale_mode);
+    renderer.debug = settings.debug;
+
+    // Fixed time steps, as fast as possible
+    let (mut world, mut tuning_watcher) = create_world(settings);
+    let mut state_log = open_state_log(settings);
+    let mut autopilot = settings.demo.then(|| Autopilot::new(Player::PLAYER1));
+    for frame in 0..settings.frames.unwrap_or(1) {
+        reload_tuning(&mut tuning_watcher, &mut world);
+        let dt_secs = (1.0 / world.tuning.frame_rate) as f32;
+        if let Some(autopilot) = &mut autopilot {
+            autopilot.fly(&mut world);
+        }
+        world.update(dt_secs);
+        log_state(&mut state_log, frame + 1, &world);
+        renderer.update_camera(&world.ships, dt_secs);
+        play_audio(&mut audio, &world, &renderer.camera);
+        renderer.render_world(&world);
+        save_frame(&renderer, settings, frame);
+    }
+    save_screenshot(&renderer, settings);
+    save_svg(&renderer, &world, settings);
+    save_world(&world, settings);
+}
+
+#[cfg(unix)]
+fn run_terminal(settings: &Settings) {
+    let sdl_context = sdl2::init().unwrap_or_else(|error| exit_with_error(error));
+    let mut audio = init_audio(&sdl_context, settings);
+    let mut input = TerminalInput::new().unwrap_or_else(|error| exit_with_error(error));
+    let (mut columns, mut rows) = TerminalBackend::terminal_size();
+    let camera = Camera::new(
+        settings.world_size,
+        settings.view_size.unwrap_or(settings.world_size),
+    );
+    let mut renderer = Renderer::new(
+        TerminalBackend::new(columns, rows),
+        camera,
+        settings.scale_mode,
+    );
+    renderer.debug = settings.debug;
+
+    let (mut world, mut tuning_watcher) = create_world(settings);
+    let mut state_log = open_state_log(settings);
+    // No way to type a name in here, so new high scores go in under the default one
+    let mut high_scores = load_high_scores(settings);
+    let mode = settings.game_mode();
+    let name = settings.name.clone().unwrap_or_else(default_name);
+    let period = Duration::from_secs_f64(1.0 / TERMINAL_FRAME_RATE);
+    let mut start = Instant::now();
+    let mut frame = 0;
+    while settings.frames.is_none_or(|frames| frame < frames) {
+        let dt_secs = start.elapsed().as_secs_f32();
+        start = Instant::now();
+
+        reload_tuning(&mut tuning_watcher, &mut world);
+        if !input.update(&mut world.ships, Player::PLAYER1) {
+            break;
+        }
+        // There are no resize events, so check every frame
+        let size = TerminalBackend::terminal_size();
+        if size != (columns, rows) {
+            (columns, rows) = size;
+            renderer.backend = TerminalBackend::new(columns, rows);
+            renderer.update_viewport();
+        }
+
+        world.update(dt_secs);
+        log_state(&mut state_log, frame + 1, &world);
+        if let (Some((score, level)), Some(high_scores)) = (game_over(&world), &mut high_scores) {
+            let high_score = HighScore::new(&mode, &name, score, level, replay_path(settings));
+            record_high_score(high_scores, high_score);
+        }
+        renderer.update_camera(&world.ships, dt_secs);
+        play_audio(&mut audio, &world, &renderer.camera);
+        renderer.render_world(&world);
+        if renderer.backend.present().is_err() {
+            break;
+        }
+        frame += 1;
+
+        sleep(period.saturating_sub(start.elapsed()));
+    }
+    drop(input);
+    save_svg(&renderer, &world, settings);
+    save_world(&world, settings);
+}
+
+fn run_windowed(settings: &Settings) {
     // Setup sdl2 objects
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
-
-    let window = video_subsystem
-        .window("Rusteroids", 800, 600)
-        .position_centered()
-        .resizable()
-        //.maximized()
-        //.fullscreen_desktop()
-        //.fullscreen()
-        .build()
-        .unwrap();
-
-    let canvas = window
-        .into_canvas()
-        .accelerated()
-        //.present_vsync()
-        .build()
-        .unwrap();
+    let mut renderer = open_window(&sdl_context, settings, settings.world_size);
     let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut renderer = Renderer::new(canvas);
-    renderer.canvas.set_draw_color(Color::BLACK);
-    renderer.canvas.clear();
-    renderer.canvas.present();
-    renderer.update_max_coords();
-    renderer.update_scaling_factor();
-
-    // Constants
-    const STARTING_ASTEROIDS_COUNT: i32 = 3;
+    let mut audio = init_audio(&sdl_context, settings);
 
     // Game objects and variables
-    let mut asteroids = Box::new(Asteroids::default());
-    let mut bullets = Box::new(Bullets::default());
-    let mut ships = Box::new(Ships::default());
-    ships.create(renderer.max_coords / 2.0).unwrap();
-    let mut level = 0;
+    let (mut world, mut tuning_watcher) = create_world(settings);
+    let mut state_log = open_state_log(settings);
+    let mut high_scores = load_high_scores(settings);
+    let mode = settings.game_mode();
+    let text_input = video_subsystem.text_input();
+    text_input.stop();
+    // The game waits while a name is typed in for a new high score
+    let mut name_entry: Option<NameEntry> = None;
+    // Attract mode, the first key pressed starts a new game for the player
+    let mut autopilot = settings.demo.then(|| Autopilot::new(Player::PLAYER1));
     let mut start = Instant::now();
     let mut sleep_time_offset = 0.0;
+    let mut frame = 0;
 
     // Game loop
-    'running: loop {
+    'running: while settings.frames.is_none_or(|frames| frame < frames) {
         let elapsed = start.elapsed().as_nanos();
         start = Instant::now();
         let dt = elapsed as f64;
@@ -92,42 +234,90 @@ pub fn main() {
         // }
 
         for event in event_pump.poll_iter() {
+            if let Some(entry) = &mut name_entry {
+                match event {
+                    Event::TextInput { text, .. } => {
+                        entry.type_text(&text);
+                        continue;
+                    }
+                    Event::KeyDown { keycode, .. } => {
+                        match keycode {
+                            Some(Keycode::Backspace) => entry.backspace(),
+                            Some(Keycode::Return | Keycode::KpEnter) => {
+                                if let (Some(entry), Some(high_scores)) =
+                                    (name_entry.take(), &mut high_scores)
+                                {
+                                    record_high_score(
+                                        high_scores,
+                                        entry.finish(&mode, replay_path(settings)),
+                                    );
+                                }
+                                text_input.stop();
+                            }
+                            Some(Keycode::Escape) => {
+                                name_entry = None;
+                                text_input.stop();
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+            if autopilot.is_some() {
+                if let Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } = event
+                {
+                    if keycode != Keycode::Escape && keycode != Keycode::F3 {
+                        autopilot = None;
+                        (world, tuning_watcher) = create_world(settings);
+                        continue;
+                    }
+                }
+            }
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => renderer.debug = !renderer.debug,
+                // The controls are the autopilot's until the demo ends
+                Event::KeyDown { .. } | Event::KeyUp { .. } if autopilot.is_some() => {}
                 Event::Window { win_event, .. } => match win_event {
-                    WindowEvent::SizeChanged(_, _) => {
-                        renderer.update_max_coords();
-                        renderer.update_scaling_factor();
-                    }
+                    WindowEvent::SizeChanged(_, _) => renderer.update_viewport(),
                     _ => {}
                 },
                 Event::KeyDown {
                     keycode: Some(Keycode::Space),
                     ..
-                } => ships.gun_trigger_pressed(Player::PLAYER1),
+                } => world.ships.gun_trigger_pressed(Player::PLAYER1),
 
                 Event::KeyUp {
                     keycode: Some(Keycode::Space),
                     ..
-                } => ships.gun_trigger_released(Player::PLAYER1),
+                } => world.ships.gun_trigger_released(Player::PLAYER1),
                 Event::KeyDown {
                     keycode: Some(Keycode::Up),
                     ..
-                } => ships.accelerator_pressed(Player::PLAYER1),
+                } => world.ships.accelerator_pressed(Player::PLAYER1),
 
                 Event::KeyUp {
                     keycode: Some(Keycode::Up),
                     ..
-                } => ships.accelerator_released(Player::PLAYER1),
+                } => world.ships.accelerator_released(Player::PLAYER1),
                 Event::KeyDown {
                     keycode: Some(Keycode::Left),
                     ..
                 } => {
-                    ships.side_thruster_pressed(
+                    world.ships.side_thruster_pressed(
                         Player::PLAYER1,
                         RotationDirection::COUNTERCLOCKWISE,
                     );
@@ -136,13 +326,15 @@ pub fn main() {
                     keycode: Some(Keycode::Right),
                     ..
                 } => {
-                    ships.side_thruster_pressed(Player::PLAYER1, RotationDirection::CLOCKWISE);
+                    world
+                        .ships
+                        .side_thruster_pressed(Player::PLAYER1, RotationDirection::CLOCKWISE);
                 }
                 Event::KeyUp {
                     keycode: Some(Keycode::Left),
                     ..
                 } => {
-                    ships.side_thruster_released(
+                    world.ships.side_thruster_released(
                         Player::PLAYER1,
                         RotationDirection::COUNTERCLOCKWISE,
                     );
@@ -151,54 +343,437 @@ pub fn main() {
                     keycode: Some(Keycode::Right),
                     ..
                 } => {
-                    ships.side_thruster_released(Player::PLAYER1, RotationDirection::CLOCKWISE);
+                    world
+                        .ships
+                        .side_thruster_released(Player::PLAYER1, RotationDirection::CLOCKWISE);
                 }
                 _ => {}
             }
         }
 
         // Update game objects
-        // Game over
-        if ships.none_exist() {
-            level = 0;
-            asteroids.clear();
-            bullets.clear();
-            ships.create(renderer.max_coords / 2.0).unwrap();
+        reload_tuning(&mut tuning_watcher, &mut world);
+        if let Some(autopilot) = &mut autopilot {
+            autopilot.fly(&mut world);
         }
-        // Level cleared
-        if asteroids.none_exist() {
-            for _ in 0..(STARTING_ASTEROIDS_COUNT + level.min(10)) {
-                asteroids.create_at_border(renderer.max_coords).unwrap();
-                asteroids.max_speed = ASTEROID_MAX_SPEED_LOWER_LIMIT
-                    + level.min(10) as f32
-                        * (ASTEROID_MAX_SPEED_HIGHER_LIMIT - ASTEROID_MAX_SPEED_LOWER_LIMIT)
-                        / 10.0;
+        if name_entry.is_none() {
+            world.update(dt_secs);
+            log_state(&mut state_log, frame + 1, &world);
+            let game_over = game_over(&world).filter(|_| autopilot.is_none());
+            if let (Some((score, level)), Some(high_scores)) = (game_over, &high_scores) {
+                if high_scores.qualifies(&mode, score) {
+                    let name = settings.name.clone().unwrap_or_else(default_name);
+                    name_entry = Some(NameEntry::new(score, level, &name));
+                    text_input.start();
+                }
             }
-            level += 1;
         }
-        ships.update_positions(renderer.max_coords, dt_secs);
-        bullets.update_positions(renderer.max_coords, dt_secs);
-        asteroids.update_positions(renderer.max_coords, dt_secs);
-        ships.update_shooting(&mut bullets);
-
-        // Physics
-        asteroid_asteroid_collisions(&mut asteroids); // TODO: For testing, remove eventually
-        asteroid_bullet_collisions(&mut asteroids, &mut bullets);
-        asteroid_ship_collisions(&mut asteroids, &mut ships);
 
         // Render
-        renderer.canvas.set_draw_color(Color::BLACK);
-        renderer.canvas.clear();
-        renderer.render_ships(&ships);
-        renderer.render_bullets(&bullets);
-        renderer.render_asteroids(&asteroids);
-        renderer.canvas.present();
+        renderer.update_camera(&world.ships, dt_secs);
+        play_audio(&mut audio, &world, &renderer.camera);
+        renderer.render_world(&world);
+        if let (Some(entry), Some(high_scores)) = (&name_entry, &high_scores) {
+            renderer.render_high_scores(high_scores, &mode, Some(entry));
+        }
+        if autopilot.is_some() {
+            renderer.render_demo();
+        }
+        save_frame(&renderer, settings, frame);
+        renderer.backend.canvas.present();
+        frame += 1;
 
         // Try to maintain stable FPS
-        let mut sleep_time = PERIOD;
-        sleep_time_offset += 0.1 * (dt - PERIOD);
-        sleep_time_offset = sleep_time_offset.max(1.0).min(PERIOD);
+        let period = 1000000000.0 / world.tuning.frame_rate; // Nanoseconds
+        let mut sleep_time = period;
+        sleep_time_offset += 0.1 * (dt - period);
+        sleep_time_offset = sleep_time_offset.max(1.0).min(period);
         sleep_time -= sleep_time_offset;
         sleep(Duration::new(0, sleep_time as u32));
     }
+    save_screenshot(&renderer, settings);
+    save_svg(&renderer, &world, settings);
+    save_world(&world, settings);
+}
+
+// The server runs the simulation at its frame rate whether anybody is playing or not
+fn run_server(settings: &Settings, address: &str) {
+    let (world, mut tuning_watcher) = create_world(settings);
+    let mut server = Server::bind(address, world).unwrap_or_else(|error| exit_with_error(error));
+    if settings.bots {
+        server.enable_bots();
+    }
+    let mut state_log = open_state_log(settings);
+    if let Some(address) = server.local_address() {
+        eprintln!("Listening on {address}");
+    }
+    let mut next_tick = Instant::now();
+    let mut frame = 0;
+    while settings.frames.is_none_or(|frames| frame < frames) {
+        reload_tuning(&mut tuning_watcher, &mut server.world);
+        server.update();
+        log_state(&mut state_log, frame + 1, &server.world);
+        frame += 1;
+
+        next_tick += Duration::from_secs_f64(1.0 / server.world.tuning.frame_rate);
+        sleep(next_tick.saturating_duration_since(Instant::now()));
+    }
+    save_world(&server.world, settings);
+}
+
+// Runs at the server tick rate, one input sent per tick
+fn run_client(settings: &Settings, address: &str) {
+    let mut client = Client::connect(address).unwrap_or_else(|error| exit_with_error(error));
+    let sdl_context = sdl2::init().unwrap_or_else(|error| exit_with_error(error));
+    let mut renderer = open_window(&sdl_context, settings, client.world.size);
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut audio = init_audio(&sdl_context, settings);
+
+    let period = Duration::from_secs_f64(1.0 / client.tick_rate);
+    let dt_secs = period.as_secs_f32();
+    let mut next_tick = Instant::now();
+    let mut frame = 0;
+    'running: while settings.frames.is_none_or(|frames| frame < frames) {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => renderer.debug = !renderer.debug,
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(_, _),
+                    ..
+                } => renderer.update_viewport(),
+                _ => {}
+            }
+        }
+        client.send_input(keyboard_input(&event_pump));
+        if let Err(error) = client.receive() {
+            eprintln!("{error}");
+            break;
+        }
+
+        client.update_world(dt_secs);
+        renderer.update_camera(&client.world.ships, dt_secs);
+        play_audio(&mut audio, &client.world, &renderer.camera);
+        renderer.render_world(&client.world);
+        save_frame(&renderer, settings, frame);
+        renderer.backend.canvas.present();
+        frame += 1;
+
+        next_tick += period;
+        sleep(next_tick.saturating_duration_since(Instant::now()));
+    }
+    save_screenshot(&renderer, settings);
+    save_svg(&renderer, &client.world, settings);
+}
+
+// Runs at the game's frame rate, the other peer has to keep up or the game waits for it
+fn run_versus(settings: &Settings, address: &str) {
+    let (world, _) = create_world(settings);
+    let transport =
+        UdpTransport::new(&settings.bind, address).unwrap_or_else(|error| exit_with_error(error));
+    // Checked by the settings
+    let player = settings.player.unwrap();
+    let mut session = RollbackSession::new(transport, world, player);
+    session.state_log = open_state_log(settings);
+    let sdl_context = sdl2::init().unwrap_or_else(|error| exit_with_error(error));
+    let mut renderer = open_window(&sdl_context, settings, session.world.size);
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut audio = init_audio(&sdl_context, settings);
+    eprintln!("Waiting for {address} on {}", settings.bind);
+
+    let period = Duration::from_secs_f64(1.0 / session.world.tuning.frame_rate);
+    let dt_secs = period.as_secs_f32();
+    let mut next_tick = Instant::now();
+    let mut frame = 0;
+    'running: while settings.frames.is_none_or(|frames| frame < frames) {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => renderer.debug = !renderer.debug,
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(_, _),
+                    ..
+                } => renderer.update_viewport(),
+                _ => {}
+            }
+        }
+        if let Err(error) = session.advance(keyboard_input(&event_pump)) {
+            eprintln!("{error}");
+            break;
+        }
+
+        renderer.update_camera(&session.world.ships, dt_secs);
+        play_audio(&mut audio, &session.world, &renderer.camera);
+        renderer.render_world(&session.world);
+        save_frame(&renderer, settings, frame);
+        renderer.backend.canvas.present();
+        frame += 1;
+
+        next_tick += period;
+        sleep(next_tick.saturating_duration_since(Instant::now()));
+    }
+    save_screenshot(&renderer, settings);
+    save_svg(&renderer, &session.world, settings);
+    save_world(&session.world, settings);
+}
+
+// Two peers in this process over a fake network, as fast as possible
+fn run_loopback(settings: &Settings) {
+    let (world, _) = create_world(settings);
+    let frames = settings.frames.unwrap_or(LOOPBACK_FRAMES);
+    let latency = (settings.latency / 1000.0 * world.tuning.frame_rate).round() as u32;
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    let report = loopback_test(&world, frames, latency, settings.packet_loss, seed)
+        .unwrap_or_else(|error| exit_with_error(error));
+    println!(
+        "{frames} frames in {} ticks, {latency} ticks of latency, {}% packet loss",
+        report.ticks,
+        settings.packet_loss * 100.0
+    );
+    for (i, stats) in report.stats.iter().enumerate() {
+        println!(
+            "Peer {}: {} rollbacks, {} frames simulated again, {} ticks waiting",
+            i + 1,
+            stats.rollbacks,
+            stats.resimulated_frames,
+            stats.stalls
+        );
+    }
+    if !report.in_sync {
+        exit_with_error("The peers ended up out of sync".to_string());
+    }
+    println!("The peers stayed in sync");
+}
+
+// Many games played by a bot as fast as possible, for checking how tuning and levels change the
+// difficulty
+fn run_balance_games(settings: &Settings, games: u32) {
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    let mut config = BalanceConfig {
+        games,
+        seed,
+        policy: settings.policy,
+        world_size: settings.world_size,
+        max_time: settings.max_time,
+        ..Default::default()
+    };
+    if let Some(path) = &settings.campaign {
+        config.campaign = Campaign::load(path).unwrap_or_else(|error| exit_with_error(error));
+    }
+    if let Some(path) = &settings.tuning {
+        config.tuning = Tuning::load(path).unwrap_or_else(|error| exit_with_error(error));
+    }
+    println!("Seeds {seed} to {}", seed.wrapping_add(games as u64 - 1));
+    let report = run_balance(&config);
+    print!("{report}");
+    if let Some(path) = &settings.report {
+        let paths = report
+            .save(path)
+            .unwrap_or_else(|error| exit_with_error(error));
+        for path in paths {
+            println!("Report written to {}", path.display());
+        }
+    }
+}
+
+fn compare_state_logs(a: &Path, b: &Path) {
+    match diff_state_logs([a, b]) {
+        Ok(None) => println!("The logs match"),
+        Ok(Some(difference)) => exit_with_error(difference.to_string()),
+        Err(error) => exit_with_error(error),
+    }
+}
+
+// Arrow keys and space, read straight from the keyboard state
+fn keyboard_input(event_pump: &EventPump) -> PlayerInput {
+    let keyboard = KeyboardState::new(event_pump);
+    PlayerInput {
+        thrust: keyboard.is_scancode_pressed(Scancode::Up),
+        left: keyboard.is_scancode_pressed(Scancode::Left),
+        right: keyboard.is_scancode_pressed(Scancode::Right),
+        fire: keyboard.is_scancode_pressed(Scancode::Space),
+    }
+}
+
+fn open_window(
+    sdl_context: &Sdl,
+    settings: &Settings,
+    world_size: Point,
+) -> Renderer<SdlBackend<Window>> {
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("Rusteroids", settings.resolution.0, settings.resolution.1)
+        .position_centered()
+        .resizable()
+        //.maximized()
+        //.fullscreen_desktop()
+        //.fullscreen()
+        .build()
+        .unwrap();
+
+    let canvas = window
+        .into_canvas()
+        .accelerated()
+        //.present_vsync()
+        .build()
+        .unwrap();
+    let camera = Camera::new(world_size, settings.view_size.unwrap_or(world_size));
+    let mut renderer = Renderer::new(SdlBackend::new(canvas), camera, settings.scale_mode);
+    renderer.debug = settings.debug;
+    renderer.backend.clear(Color::BLACK);
+    renderer.backend.canvas.present();
+    renderer
+}
+
+// Starts the game with the campaign and tuning files, if any, and keeps watching the tuning
+// file for changes. A saved game picks up where it was, with its own tuning unless a file is given
+fn create_world(settings: &Settings) -> (World, Option<TuningWatcher>) {
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    let mut world = World::new(settings.world_size, seed);
+    if let Some(path) = &settings.campaign {
+        world.campaign = Campaign::load(path).unwrap_or_else(|error| exit_with_error(error));
+    }
+    if let Some(path) = &settings.load_snapshot {
+        world.restore_state(load_snapshot(path).unwrap_or_else(|error| exit_with_error(error)));
+    }
+    let watcher = settings.tuning.as_ref().map(|path| {
+        world.set_tuning(Tuning::load(path).unwrap_or_else(|error| exit_with_error(error)));
+        TuningWatcher::new(path)
+    });
+    (world, watcher)
+}
+
+fn open_state_log(settings: &Settings) -> Option<StateLog> {
+    settings
+        .state_log
+        .as_ref()
+        .map(|path| StateLog::create(path).unwrap_or_else(|error| exit_with_error(error)))
+}
+
+// Ticks count from 1, the state log has the state after each one
+fn log_state(state_log: &mut Option<StateLog>, tick: u32, world: &World) {
+    if let Some(state_log) = state_log {
+        state_log
+            .record(tick, &world.save_state())
+            .unwrap_or_else(|error| exit_with_error(error));
+    }
+}
+
+fn reload_tuning(watcher: &mut Option<TuningWatcher>, world: &mut World) {
+    if let Some(tuning) = watcher.as_mut().and_then(|watcher| watcher.poll()) {
+        world.set_tuning(tuning);
+    }
+}
+
+// None when there's nowhere to keep them
+fn load_high_scores(settings: &Settings) -> Option<HighScores> {
+    let path = settings
+        .high_scores
+        .clone()
+        .or_else(HighScores::default_path)?;
+    Some(HighScores::load(&path))
+}
+
+fn record_high_score(high_scores: &mut HighScores, high_score: HighScore) {
+    if high_scores.insert(high_score).is_some() {
+        high_scores
+            .save()
+            .unwrap_or_else(|error| eprintln!("{error}"));
+    }
+}
+
+// The state log, absolute so high score entries still point at it from anywhere
+fn replay_path(settings: &Settings) -> Option<PathBuf> {
+    let path = settings.state_log.as_ref()?;
+    Some(path::absolute(path).unwrap_or_else(|_| path.clone()))
+}
+
+// Score and level of the game that just ended, if any
+fn game_over(world: &World) -> Option<(u32, i32)> {
+    world.events.iter().find_map(|event| match *event {
+        GameEvent::GameOver { score, level } => Some((score, level)),
+        _ => None,
+    })
+}
+
+// The game keeps going without sound when there's no audio device
+fn init_audio(sdl_context: &Sdl, settings: &Settings) -> Option<Audio> {
+    if settings.volume == 0.0 {
+        return None;
+    }
+    Audio::new(sdl_context, settings.volume)
+        .map_err(|error| eprintln!("Audio disabled: {error}"))
+        .ok()
+}
+
+fn play_audio(audio: &mut Option<Audio>, world: &World, camera: &Camera) {
+    if let Some(audio) = audio {
+        audio.update(world, camera);
+    }
+}
+
+fn save_frame<T: RenderTarget>(
+    renderer: &Renderer<SdlBackend<T>>,
+    settings: &Settings,
+    frame: u32,
+) {
+    if let Some(directory) = &settings.frame_dump {
+        let path = directory.join(format!("frame-{frame:06}.png"));
+        renderer
+            .backend
+            .save_png(&path)
+            .unwrap_or_else(|error| exit_with_error(error));
+    }
+}
+
+fn save_screenshot<T: RenderTarget>(renderer: &Renderer<SdlBackend<T>>, settings: &Settings) {
+    if let Some(path) = &settings.screenshot {
+        renderer
+            .backend
+            .save_png(path)
+            .unwrap_or_else(|error| exit_with_error(error));
+    }
+}
+
+fn save_world(world: &World, settings: &Settings) {
+    if let Some(path) = &settings.save_snapshot {
+        save_snapshot(path, &world.save_state()).unwrap_or_else(|error| exit_with_error(error));
+    }
+}
+
+// Draws the same frame again as vector art
+fn save_svg<B: RenderBackend>(renderer: &Renderer<B>, world: &World, settings: &Settings) {
+    if let Some(path) = &settings.svg {
+        let (width, height) = renderer.backend.size();
+        let mut svg_renderer = Renderer::new(
+            SvgBackend::new(width, height),
+            renderer.camera.clone(),
+            settings.scale_mode,
+        );
+        svg_renderer.debug = renderer.debug;
+        svg_renderer.render_world(world);
+        svg_renderer
+            .backend
+            .save_svg(path)
+            .unwrap_or_else(|error| exit_with_error(error));
+    }
+}
+
+fn exit_with_error(error: String) -> ! {
+    eprintln!("{error}");
+    std::process::exit(1);
 }
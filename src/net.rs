@@ -0,0 +1,398 @@
+use crate::events::GameEvent;
+use crate::game_objects::{AsteroidMaterial, AsteroidSize, GravityWells, Ships};
+use crate::input::PlayerInput;
+use crate::pool::Handle;
+use crate::shapes::Point;
+use crate::tuning::Tuning;
+use crate::world::World;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+
+// -----------------------------------------------------------------------------
+
+pub const PROTOCOL_VERSION: u32 = 2;
+// Biggest datagram that fits through loopback and most networks, fragmented or not
+pub const MAX_PACKET_SIZE: usize = 65507;
+// Snapshots kept around to be used as delta baselines, on both ends
+pub const SNAPSHOT_HISTORY: usize = 64;
+// Inputs repeated in every packet, so a few lost packets don't lose any
+pub const INPUT_REDUNDANCY: usize = 8;
+// Positions are sent as fractions of the world plus this margin, for objects wrapping around
+const POSITION_MARGIN: f32 = 16.0;
+const VELOCITY_SCALE: f32 = 64.0; // Steps per world unit per second
+
+#[derive(Serialize, Deserialize)]
+pub enum Message {
+    // Client to server
+    Hello {
+        version: u32,
+    },
+    Input {
+        // Newest snapshot the client has, the server encodes the next ones against it
+        ack: Option<u32>,
+        // Oldest first, each one is a tick of the client
+        inputs: Vec<(u32, PlayerInput)>,
+    },
+    Bye,
+    // Server to client
+    Welcome {
+        world_size: Point,
+        tick_rate: f64,
+        tuning: Tuning,
+    },
+    Refused {
+        reason: String,
+    },
+    Snapshot {
+        tick: u32,
+        // The data is XORed with this snapshot when there is one
+        baseline: Option<u32>,
+        // Ship slot of the client receiving it, None while it has no ship
+        player: Option<u8>,
+        // Last input of the client that went into the simulation
+        last_input: Option<u32>,
+        data: Vec<u8>,
+    },
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Messages always serialize")
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Message> {
+        bincode::deserialize(bytes).ok()
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NetAsteroid {
+    // Slot and generation on the server, to match it between snapshots
+    pub id: (u16, u16),
+    pub position: [u16; 2],
+    pub velocity: [i16; 2],
+    pub size: AsteroidSize,
+    pub material: AsteroidMaterial,
+    pub hp: i8,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NetBullet {
+    pub id: (u16, u16),
+    // Back end of the bullet, the front is worked out from the velocity
+    pub position: [u16; 2],
+    pub velocity: [i16; 2],
+}
+
+// NetState
+// What clients get to see of the world: ships whole, for prediction, everything else quantized
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NetState {
+    pub level: i32,
+    pub score: u32,
+    pub level_name: String,
+    pub time_limit: Option<f32>,
+    pub level_time: f32,
+    pub ships: Ships,
+    pub gravity_wells: GravityWells,
+    pub asteroids: Vec<NetAsteroid>,
+    pub bullets: Vec<NetBullet>,
+    // Everything that happened since the previous snapshot
+    pub events: Vec<GameEvent>,
+}
+
+impl NetState {
+    // Clients index their fixed ship and gravity well arrays with what the server sends, so a
+    // broken or hostile server could otherwise crash them
+    pub fn validate(&self) -> Result<(), String> {
+        self.ships.validate().map_err(|e| format!("ships: {e}"))?;
+        self.gravity_wells
+            .validate()
+            .map_err(|e| format!("gravity wells: {e}"))?;
+        if !self.level_time.is_finite() {
+            return Err("level_time must be a number".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn capture(world: &World, events: Vec<GameEvent>) -> NetState {
+        let size = world.size;
+        let asteroids = &world.asteroids;
+        let bullets = &world.bullets;
+        let id = |handle: Handle| (handle.index as u16, handle.generation as u16);
+        NetState {
+            level: world.level,
+            score: world.score,
+            level_name: world.level_definition.name.clone(),
+            time_limit: world.level_definition.time_limit,
+            level_time: world.level_time,
+            ships: (*world.ships).clone(),
+            gravity_wells: (*world.gravity_wells).clone(),
+            asteroids: (0..asteroids.pool.capacity())
+                .filter(|&i| asteroids.pool.exists(i))
+                .filter_map(|i| {
+                    Some(NetAsteroid {
+                        id: id(asteroids.pool.handle(i)),
+                        position: quantize_position(asteroids.circle[i].center, size),
+                        velocity: quantize_velocity(asteroids.velocity[i]),
+                        size: AsteroidSize::from_radius(asteroids.circle[i].radius).ok()?,
+                        material: asteroids.material[i],
+                        hp: asteroids.hp[i],
+                    })
+                })
+                .collect(),
+            bullets: (0..bullets.pool.capacity())
+                .filter(|&i| bullets.pool.exists(i))
+                .map(|i| NetBullet {
+                    id: id(bullets.pool.handle(i)),
+                    position: quantize_position(bullets.line_segment[i].p2, size),
+                    velocity: quantize_velocity(bullets.velocity[i]),
+                })
+                .collect(),
+            events,
+        }
+    }
+}
+
+pub fn quantize_position(position: Point, world_size: Point) -> [u16; 2] {
+    let quantize = |x: f32, size: f32| {
+        let fraction = (x + POSITION_MARGIN) / (size + 2.0 * POSITION_MARGIN);
+        (fraction.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+    };
+    [
+        quantize(position.x, world_size.x),
+        quantize(position.y, world_size.y),
+    ]
+}
+
+pub fn dequantize_position(position: [u16; 2], world_size: Point) -> Point {
+    let dequantize = |q: u16, size: f32| {
+        q as f32 / u16::MAX as f32 * (size + 2.0 * POSITION_MARGIN) - POSITION_MARGIN
+    };
+    Point {
+        x: dequantize(position[0], world_size.x),
+        y: dequantize(position[1], world_size.y),
+    }
+}
+
+pub fn quantize_velocity(velocity: Point) -> [i16; 2] {
+    let quantize = |v: f32| {
+        (v * VELOCITY_SCALE)
+            .round()
+            .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    };
+    [quantize(velocity.x), quantize(velocity.y)]
+}
+
+pub fn dequantize_velocity(velocity: [i16; 2]) -> Point {
+    Point {
+        x: velocity[0] as f32 / VELOCITY_SCALE,
+        y: velocity[1] as f32 / VELOCITY_SCALE,
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// Encoded snapshots by tick, newest last
+#[derive(Default)]
+pub struct SnapshotHistory {
+    snapshots: VecDeque<(u32, Vec<u8>)>,
+}
+
+impl SnapshotHistory {
+    pub fn push(&mut self, tick: u32, bytes: Vec<u8>) {
+        if self.snapshots.len() >= SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((tick, bytes));
+    }
+
+    pub fn get(&self, tick: u32) -> Option<&[u8]> {
+        self.snapshots
+            .iter()
+            .find(|(t, _)| *t == tick)
+            .map(|(_, bytes)| bytes.as_slice())
+    }
+}
+
+// Delta against the baseline: XOR, which leaves zeros wherever nothing changed, then those zeros
+// run length encoded. Without a baseline it's the same against nothing
+pub fn delta_encode(bytes: &[u8], baseline: Option<&[u8]>) -> Vec<u8> {
+    let baseline = baseline.unwrap_or(&[]);
+    let xored: Vec<u8> = (0..bytes.len())
+        .map(|i| bytes[i] ^ baseline.get(i).copied().unwrap_or(0))
+        .collect();
+    let mut encoded = Vec::with_capacity(bytes.len() / 2);
+    write_varint(&mut encoded, bytes.len());
+    // Pairs of a run of zeros and a run of literal bytes
+    let mut i = 0;
+    while i < xored.len() {
+        let zeros = xored[i..].iter().take_while(|&&b| b == 0).count();
+        i += zeros;
+        let start = i;
+        // Lone zeros are cheaper left inside the literals
+        while i < xored.len() && !(xored[i] == 0 && xored.get(i + 1).is_none_or(|&b| b == 0)) {
+            i += 1;
+        }
+        write_varint(&mut encoded, zeros);
+        write_varint(&mut encoded, i - start);
+        encoded.extend_from_slice(&xored[start..i]);
+    }
+    encoded
+}
+
+pub fn delta_decode(encoded: &[u8], baseline: Option<&[u8]>) -> Option<Vec<u8>> {
+    let baseline = baseline.unwrap_or(&[]);
+    let mut cursor = encoded;
+    let length = read_varint(&mut cursor)?;
+    if length > MAX_PACKET_SIZE * 64 {
+        return None;
+    }
+    let mut bytes = Vec::with_capacity(length);
+    while !cursor.is_empty() {
+        let zeros = read_varint(&mut cursor)?;
+        let literals = read_varint(&mut cursor)?;
+        let end = bytes.len().checked_add(zeros)?.checked_add(literals)?;
+        if end > length || literals > cursor.len() {
+            return None;
+        }
+        bytes.resize(bytes.len() + zeros, 0);
+        bytes.extend_from_slice(&cursor[..literals]);
+        cursor = &cursor[literals..];
+    }
+    bytes.resize(length, 0);
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte ^= baseline.get(i).copied().unwrap_or(0);
+    }
+    Some(bytes)
+}
+
+// 7 bits at a time, lowest first
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<usize> {
+    let mut value = 0usize;
+    for shift in (0..usize::BITS).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs of zeros, lone zeros and changes at both ends
+    fn payload() -> Vec<u8> {
+        let mut bytes = vec![7, 0, 0, 0, 0, 3, 0, 9, 9, 0, 0];
+        bytes.extend((0..200).map(|i| (i % 5) as u8));
+        bytes.extend([0; 100]);
+        bytes.push(1);
+        bytes
+    }
+
+    fn encoded(values: &[usize], literals: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &value in values {
+            write_varint(&mut bytes, value);
+        }
+        bytes.extend_from_slice(literals);
+        bytes
+    }
+
+    #[test]
+    fn round_trip_without_baseline() {
+        let bytes = payload();
+        let encoded = delta_encode(&bytes, None);
+        assert_eq!(delta_decode(&encoded, None), Some(bytes));
+        assert_eq!(
+            delta_decode(&delta_encode(&[], None), None),
+            Some(Vec::new())
+        );
+    }
+
+    #[test]
+    fn round_trip_with_baseline() {
+        let bytes = payload();
+        let mut baseline = bytes.clone();
+        baseline[3] ^= 0x55;
+        baseline[150] = 200;
+        let encoded = delta_encode(&bytes, Some(&baseline));
+        assert!(encoded.len() < 16);
+        assert_eq!(delta_decode(&encoded, Some(&baseline)), Some(bytes));
+    }
+
+    #[test]
+    fn round_trip_with_longer_or_shorter_baseline() {
+        let bytes = payload();
+        let longer: Vec<u8> = bytes.iter().chain(&[1, 2, 3]).copied().collect();
+        let shorter = &bytes[..bytes.len() / 2];
+        for baseline in [longer.as_slice(), shorter] {
+            let encoded = delta_encode(&bytes, Some(baseline));
+            assert_eq!(delta_decode(&encoded, Some(baseline)), Some(bytes.clone()));
+        }
+    }
+
+    #[test]
+    fn truncated_varint_is_refused() {
+        assert_eq!(delta_decode(&[], None), None);
+        assert_eq!(delta_decode(&[0x80], None), None);
+        assert_eq!(delta_decode(&encoded(&[4], &[0x80]), None), None);
+        assert_eq!(delta_decode(&[0xff; 16], None), None);
+    }
+
+    #[test]
+    fn oversized_length_is_refused() {
+        let length = MAX_PACKET_SIZE * 64 + 1;
+        assert_eq!(delta_decode(&encoded(&[length], &[]), None), None);
+        // Runs longer than the length, or long enough to overflow when added up
+        assert_eq!(delta_decode(&encoded(&[4, 5, 0], &[]), None), None);
+        assert_eq!(
+            delta_decode(&encoded(&[4, usize::MAX, 1], &[1]), None),
+            None
+        );
+    }
+
+    #[test]
+    fn captured_states_are_valid_and_broken_ones_refused() {
+        let mut world = World::new(crate::world::DEFAULT_WORLD_SIZE, 3);
+        world.update(1.0 / 60.0);
+        let state = NetState::capture(&world, Vec::new());
+        assert!(state.validate().is_ok());
+        let bytes = bincode::serialize(&state).unwrap();
+        let decoded: NetState = bincode::deserialize(&bytes).unwrap();
+        assert!(decoded.validate().is_ok());
+
+        let mut broken = state.clone();
+        broken.ships.velocity[0].x = f32::NAN;
+        assert!(broken.validate().is_err());
+    }
+
+    #[test]
+    fn literals_past_the_end_are_refused() {
+        assert_eq!(delta_decode(&encoded(&[4, 0, 4], &[1, 2]), None), None);
+        assert_eq!(
+            delta_decode(&encoded(&[4, 0, 2], &[1, 2]), None),
+            Some(vec![1, 2, 0, 0])
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use crate::events::GameEvent;
+use crate::game_objects::{AsteroidMaterial, Ships, MAX_SHIPS};
+use crate::pool::{Handle, OverflowPolicy, Pool};
+use crate::shapes::{Line, Point, Triangle};
+
+use std::f32::consts::PI;
+
+// -----------------------------------------------------------------------------
+
+pub const MAX_PARTICLES: usize = 512;
+const DEBRIS_PER_RADIUS: f32 = 2.0;
+const DEBRIS_EXPLOSIVE_MULTIPLIER: f32 = 3.0;
+const DEBRIS_MAX_SPEED: f32 = 30.0;
+const DEBRIS_MAX_ANGULAR_VELOCITY: f32 = PI * 4.0;
+const DEBRIS_LIFETIME: f32 = 0.8;
+const SPARKS_PER_HIT: usize = 4;
+const SPARK_LENGTH: f32 = 0.5;
+const SPARK_SPEED: f32 = 60.0;
+const SPARK_LIFETIME: f32 = 0.2;
+const EXHAUST_PER_SECOND: f32 = 60.0;
+const EXHAUST_LENGTH: f32 = 0.6;
+const EXHAUST_SPEED: f32 = 25.0;
+const EXHAUST_SPREAD: f32 = PI / 8.0;
+const EXHAUST_LIFETIME: f32 = 0.3;
+const FRAGMENT_SPEED: f32 = 15.0;
+const FRAGMENT_MAX_ANGULAR_VELOCITY: f32 = PI * 2.0;
+const FRAGMENT_LIFETIME: f32 = 1.5;
+
+#[derive(Copy, Clone, Default)]
+pub enum ParticleKind {
+    #[default]
+    SPARK,
+    DEBRIS(AsteroidMaterial),
+    EXHAUST,
+    FRAGMENT,
+}
+
+pub struct Particles {
+    pub pool: Pool,
+    pub kind: Vec<ParticleKind>,
+    pub line_segment: Vec<Line>,
+    pub velocity: Vec<Point>,
+    pub angular_velocity: Vec<f32>,
+    pub lifetime: Vec<f32>,
+    pub max_lifetime: Vec<f32>,
+}
+
+impl Default for Particles {
+    fn default() -> Self {
+        Particles {
+            // Purely cosmetic, so the oldest ones make room for new effects
+            pool: Pool::new(MAX_PARTICLES, MAX_PARTICLES, OverflowPolicy::RECYCLE),
+            kind: vec![Default::default(); MAX_PARTICLES],
+            line_segment: vec![Default::default(); MAX_PARTICLES],
+            velocity: vec![Default::default(); MAX_PARTICLES],
+            angular_velocity: vec![Default::default(); MAX_PARTICLES],
+            lifetime: vec![Default::default(); MAX_PARTICLES],
+            max_lifetime: vec![Default::default(); MAX_PARTICLES],
+        }
+    }
+}
+
+impl Particles {
+    pub fn create(
+        &mut self,
+        kind: ParticleKind,
+        line_segment: Line,
+        velocity: Point,
+        angular_velocity: f32,
+        lifetime: f32,
+    ) -> Option<Handle> {
+        let handle = self.pool.allocate()?;
+        let index = handle.index;
+        self.kind[index] = kind;
+        self.line_segment[index] = line_segment;
+        self.velocity[index] = velocity;
+        self.angular_velocity[index] = angular_velocity;
+        self.lifetime[index] = lifetime;
+        self.max_lifetime[index] = lifetime;
+        Some(handle)
+    }
+
+    pub fn clear(&mut self) {
+        self.pool.clear()
+    }
+
+    pub fn update_positions(&mut self, dt: f32) {
+        for i in 0..MAX_PARTICLES {
+            if !self.pool.exists(i) {
+                continue;
+            }
+            self.lifetime[i] -= dt;
+            if self.lifetime[i] <= 0.0 {
+                self.pool.free(i);
+                continue;
+            }
+            self.line_segment[i].update_position(self.velocity[i], dt);
+            self.line_segment[i].rotate_around_midpoint(self.angular_velocity[i], dt);
+        }
+    }
+
+    pub fn emit_for_events(&mut self, events: &[GameEvent]) {
+        for event in events {
+            match *event {
+                GameEvent::BulletHit { position, normal } => self.emit_sparks(position, normal),
+                GameEvent::AsteroidDestroyed {
+                    position,
+                    velocity,
+                    radius,
+                    material,
+                } => self.emit_debris(position, velocity, radius, material),
+                GameEvent::ShipDestroyed {
+                    triangle, velocity, ..
+                } => self.emit_fragments(triangle, velocity),
+                GameEvent::BulletFired { .. }
+                | GameEvent::ShipDamaged { .. }
+                | GameEvent::GameOver { .. } => {}
+            }
+        }
+    }
+
+    pub fn emit_exhaust(&mut self, ships: &Ships, dt: f32) {
+        for i in 0..MAX_SHIPS {
+            if !ships.pool.exists(i)
+                || ships.acceleration(i) == 0.0
+                || rand::random::<f32>() >= EXHAUST_PER_SECOND * dt
+            {
+                continue;
+            }
+            let triangle = &ships.triangle[i];
+            let rear = (triangle.v2 + triangle.v3) / 2.0;
+            let angle = triangle.angle() + PI + (rand::random::<f32>() - 0.5) * EXHAUST_SPREAD;
+            self.create(
+                ParticleKind::EXHAUST,
+                Line {
+                    p1: rear + Point::from_polar(EXHAUST_LENGTH, angle),
+                    p2: rear,
+                },
+                ships.velocity[i] + Point::from_polar(EXHAUST_SPEED, angle),
+                0.0,
+                EXHAUST_LIFETIME,
+            );
+        }
+    }
+
+    fn emit_sparks(&mut self, position: Point, normal: Point) {
+        for _ in 0..SPARKS_PER_HIT {
+            let angle = normal.angle() + (rand::random::<f32>() - 0.5) * PI;
+            self.create(
+                ParticleKind::SPARK,
+                Line {
+                    p1: position + Point::from_polar(SPARK_LENGTH, angle),
+                    p2: position,
+                },
+                Point::from_polar(SPARK_SPEED * (0.5 + rand::random::<f32>()), angle),
+                0.0,
+                SPARK_LIFETIME,
+            );
+        }
+    }
+
+    fn emit_debris(
+        &mut self,
+        position: Point,
+        velocity: Point,
+        radius: f32,
+        material: AsteroidMaterial,
+    ) {
+        let mut count = radius * DEBRIS_PER_RADIUS;
+        if material == AsteroidMaterial::EXPLOSIVE {
+            count *= DEBRIS_EXPLOSIVE_MULTIPLIER;
+        }
+        for _ in 0..count as usize {
+            let angle = rand::random::<f32>() * 2.0 * PI;
+            let start = position + Point::from_polar(rand::random::<f32>() * radius, angle);
+            let length = 0.5 + rand::random::<f32>() * radius / 3.0;
+            let spin = (rand::random::<f32>() - 0.5) * 2.0 * DEBRIS_MAX_ANGULAR_VELOCITY;
+            self.create(
+                ParticleKind::DEBRIS(material),
+                Line {
+                    p1: start + Point::from_polar(length, rand::random::<f32>() * 2.0 * PI),
+                    p2: start,
+                },
+                velocity + Point::from_polar(rand::random::<f32>() * DEBRIS_MAX_SPEED, angle),
+                spin,
+                DEBRIS_LIFETIME * (0.5 + rand::random::<f32>()),
+            );
+        }
+    }
+
+    fn emit_fragments(&mut self, triangle: Triangle, velocity: Point) {
+        // One fragment per side of the hull, flying away from the middle of the ship
+        let center = triangle.centroid();
+        let sides = [
+            Line {
+                p1: triangle.v1,
+                p2: triangle.v2,
+            },
+            Line {
+                p1: triangle.v2,
+                p2: triangle.v3,
+            },
+            Line {
+                p1: triangle.v3,
+                p2: triangle.v1,
+            },
+        ];
+        for side in sides {
+            let direction = (side.midpoint() - center).normalized();
+            let spin = (rand::random::<f32>() - 0.5) * 2.0 * FRAGMENT_MAX_ANGULAR_VELOCITY;
+            self.create(
+                ParticleKind::FRAGMENT,
+                side,
+                velocity + direction * FRAGMENT_SPEED * (0.5 + rand::random::<f32>()),
+                spin,
+                FRAGMENT_LIFETIME,
+            );
+        }
+    }
+}
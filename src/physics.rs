@@ -1,19 +1,60 @@
+use crate::events::{DamageSource, GameEvent};
 use crate::game_objects::{
-    AsteroidSize, Asteroids, Bullets, Ships, ASTEROID_RADIUS_LARGE, ASTEROID_RADIUS_MEDIUM,
-    ASTEROID_RADIUS_SMALL, BULLET_DAMAGE, MAX_ASTEROIDS, MAX_BULLETS, MAX_SHIPS, SHIP_MASS,
+    AsteroidMaterial, AsteroidSize, Asteroids, Bullets, GravityWellKind, GravityWells, Ships,
+    ASTEROID_RADIUS_LARGE, ASTEROID_RADIUS_SMALL, MAX_GRAVITY_WELLS, MAX_SHIPS,
 };
 use crate::intersect::{
     circles_intersect, line_segment_circle_intersect, triangle_circle_intersect,
 };
 use crate::shapes::{Circle, Point};
 
-use std::f32::consts::FRAC_PI_8;
+use serde::{Deserialize, Serialize};
+
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, FRAC_PI_8, PI};
 
 // -----------------------------------------------------------------------------
 
 const ASTEROID_COEFFICIENT_OF_RESTITUTION: f32 = 0.75;
 const SHIP_COEFFICIENT_OF_RESTITUTION: f32 = 0.5;
 const ASTEROID_SPLIT_SPEED_MULTIPLIER: f32 = 1.25;
+const ASTEROID_ICE_SHATTER_PIECES: usize = 4;
+const ASTEROID_EXPLOSION_RADIUS: f32 = 12.0;
+const ASTEROID_EXPLOSION_DAMAGE: i8 = 50;
+// Cosine of the angle between the bullet and the surface normal, below it metal deflects bullets
+const METAL_DEFLECTION_MAX_INCIDENCE: f32 = 0.5;
+// Asteroids that touch are always in the same or neighbouring cells
+pub const BROAD_PHASE_CELL_SIZE: f32 = 2.0 * ASTEROID_RADIUS_LARGE;
+// Cells checked against each cell, the other half of the neighbours check this one
+const BROAD_PHASE_NEIGHBOURS: [(i32, i32); 5] = [(0, 0), (1, 0), (-1, 1), (0, 1), (1, 1)];
+// Pushes things out of a gravity well's center when they're right on it and standing still
+const UP: Point = Point { x: 0.0, y: -1.0 };
+
+// Collision numbers that can be changed from the tuning file, defaults to the constants above
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhysicsTuning {
+    pub asteroid_restitution: f32,
+    pub ship_restitution: f32,
+    pub asteroid_split_speed_multiplier: f32,
+    pub ice_shatter_pieces: usize,
+    pub explosion_radius: f32,
+    pub explosion_damage: i8,
+    pub metal_deflection_max_incidence: f32,
+}
+
+impl Default for PhysicsTuning {
+    fn default() -> Self {
+        PhysicsTuning {
+            asteroid_restitution: ASTEROID_COEFFICIENT_OF_RESTITUTION,
+            ship_restitution: SHIP_COEFFICIENT_OF_RESTITUTION,
+            asteroid_split_speed_multiplier: ASTEROID_SPLIT_SPEED_MULTIPLIER,
+            ice_shatter_pieces: ASTEROID_ICE_SHATTER_PIECES,
+            explosion_radius: ASTEROID_EXPLOSION_RADIUS,
+            explosion_damage: ASTEROID_EXPLOSION_DAMAGE,
+            metal_deflection_max_incidence: METAL_DEFLECTION_MAX_INCIDENCE,
+        }
+    }
+}
 
 // -----------------------------------------------------------------------------
 
@@ -47,117 +88,400 @@ fn displace_point_from_circle(c: &Circle, p: Point) -> Point {
     p + displacement
 }
 
-pub fn asteroid_asteroid_collisions(asteroids: &mut Asteroids) {
-    for i in 0..MAX_ASTEROIDS {
-        if !asteroids.exists[i] {
-            continue;
+// Broad phase
+// Uniform grid over the world, so only asteroids close to each other are tested for collisions
+pub struct BroadPhase {
+    pub columns: usize,
+    pub rows: usize,
+    pub cells: Vec<Vec<usize>>,
+}
+
+impl BroadPhase {
+    pub fn new(world_size: Point) -> BroadPhase {
+        let columns = (world_size.x / BROAD_PHASE_CELL_SIZE).ceil().max(1.0) as usize;
+        let rows = (world_size.y / BROAD_PHASE_CELL_SIZE).ceil().max(1.0) as usize;
+        BroadPhase {
+            columns,
+            rows,
+            cells: vec![Vec::new(); columns * rows],
         }
-        for j in (i + 1)..MAX_ASTEROIDS {
-            if !asteroids.exists[j] {
-                continue;
+    }
+
+    pub fn rebuild(&mut self, asteroids: &Asteroids) {
+        self.cells.iter_mut().for_each(|cell| cell.clear());
+        for i in 0..asteroids.pool.capacity() {
+            if asteroids.pool.exists(i) {
+                let cell = self.cell_at(asteroids.circle[i].center);
+                self.cells[cell].push(i);
             }
-            if circles_intersect(asteroids.circle[i], asteroids.circle[j]) {
-                (asteroids.velocity[i], asteroids.velocity[j]) = calculate_collision_velocities(
-                    asteroids.velocity[i],
-                    asteroids.velocity[j],
-                    1.0,
-                    1.0,
-                    ASTEROID_COEFFICIENT_OF_RESTITUTION,
-                );
-                (asteroids.circle[i].center, asteroids.circle[j].center) =
-                    displace_circles(&asteroids.circle[i], &asteroids.circle[j]);
+        }
+    }
+
+    // Asteroids spawn just outside of the world, those go into the border cells
+    fn cell_at(&self, p: Point) -> usize {
+        let column = ((p.x / BROAD_PHASE_CELL_SIZE).max(0.0) as usize).min(self.columns - 1);
+        let row = ((p.y / BROAD_PHASE_CELL_SIZE).max(0.0) as usize).min(self.rows - 1);
+        row * self.columns + column
+    }
+
+    // Every pair of asteroids in the same or neighbouring cells, once
+    fn candidate_pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let cell = &self.cells[row * self.columns + column];
+                for (dx, dy) in BROAD_PHASE_NEIGHBOURS {
+                    let (x, y) = (column as i32 + dx, row as i32 + dy);
+                    if x < 0 || x >= self.columns as i32 || y >= self.rows as i32 {
+                        continue;
+                    }
+                    let other = &self.cells[y as usize * self.columns + x as usize];
+                    for (a, &i) in cell.iter().enumerate() {
+                        // Within the same cell only the asteroids after this one
+                        let start = if (dx, dy) == (0, 0) { a + 1 } else { 0 };
+                        for &j in &other[start..] {
+                            pairs.push((i, j));
+                        }
+                    }
+                }
             }
         }
+        pairs
     }
 }
 
-pub fn asteroid_bullet_collisions(asteroids: &mut Asteroids, bullets: &mut Bullets) {
-    let mut split = [false; MAX_ASTEROIDS];
-    for i in 0..MAX_ASTEROIDS {
-        if !asteroids.exists[i] {
+pub fn asteroid_asteroid_collisions(
+    asteroids: &mut Asteroids,
+    broad_phase: &mut BroadPhase,
+    tuning: &PhysicsTuning,
+) {
+    broad_phase.rebuild(asteroids);
+    for (i, j) in broad_phase.candidate_pairs() {
+        if circles_intersect(asteroids.circle[i], asteroids.circle[j]) {
+            (asteroids.velocity[i], asteroids.velocity[j]) = calculate_collision_velocities(
+                asteroids.velocity[i],
+                asteroids.velocity[j],
+                1.0,
+                1.0,
+                tuning.asteroid_restitution,
+            );
+            (asteroids.circle[i].center, asteroids.circle[j].center) =
+                displace_circles(&asteroids.circle[i], &asteroids.circle[j]);
+        }
+    }
+}
+
+pub fn asteroid_bullet_collisions(
+    asteroids: &mut Asteroids,
+    bullets: &mut Bullets,
+    events: &mut Vec<GameEvent>,
+    tuning: &PhysicsTuning,
+) {
+    let mut destroyed = vec![false; asteroids.pool.capacity()];
+    for (i, destroyed) in destroyed.iter_mut().enumerate() {
+        if !asteroids.pool.exists(i) {
             continue;
         }
-        for j in 0..MAX_BULLETS {
-            if !bullets.exists[j] {
+        for j in 0..bullets.pool.capacity() {
+            if !bullets.pool.exists(j) {
                 continue;
             }
-            if line_segment_circle_intersect(&bullets.line_segment[j], &asteroids.circle[i]).0 {
-                bullets.exists[j] = false;
-                asteroids.hp[i] -= BULLET_DAMAGE;
-                let destroy = asteroids.hp[i] <= 0;
-                let is_small = asteroids.circle[i].radius == ASTEROID_RADIUS_SMALL;
-                split[i] = destroy && !is_small;
-                asteroids.exists[i] = !(destroy && is_small);
-                if split[i] {
-                    asteroids.velocity[i] *= ASTEROID_SPLIT_SPEED_MULTIPLIER;
+            let (collision, closest) =
+                line_segment_circle_intersect(&bullets.line_segment[j], &asteroids.circle[i]);
+            if collision {
+                // Straight through the center has no surface direction, so it's head on
+                let normal = normal_or(
+                    closest - asteroids.circle[i].center,
+                    -bullets.velocity[j].normalized(),
+                );
+                let incidence = bullets.velocity[j].normalized().dot(normal).abs();
+                if asteroids.material[i] == AsteroidMaterial::METAL
+                    && incidence < tuning.metal_deflection_max_incidence
+                {
+                    let surface = asteroids.circle[i].center + normal * asteroids.circle[i].radius;
+                    bullets.deflect(j, surface, normal);
+                    events.push(GameEvent::BulletHit {
+                        position: surface,
+                        normal,
+                    });
+                    continue;
+                }
+                bullets.pool.free(j);
+                events.push(GameEvent::BulletHit {
+                    position: closest,
+                    normal,
+                });
+                asteroids.hp[i] -= bullets.tuning.damage;
+                *destroyed = asteroids.hp[i] <= 0;
+                if *destroyed {
+                    break;
                 }
             }
         }
     }
 
-    for i in 0..MAX_ASTEROIDS {
-        if !split[i] {
-            continue;
+    // Explosions can destroy other explosive asteroids, so keep going until none are left
+    let mut exploded = vec![false; destroyed.len()];
+    while let Some(i) = (0..destroyed.len()).find(|&i| {
+        destroyed[i] && !exploded[i] && asteroids.material[i] == AsteroidMaterial::EXPLOSIVE
+    }) {
+        exploded[i] = true;
+        let blast = Circle {
+            center: asteroids.circle[i].center,
+            radius: tuning.explosion_radius,
+        };
+        for (j, destroyed) in destroyed.iter_mut().enumerate() {
+            if i == j || !asteroids.pool.exists(j) || *destroyed {
+                continue;
+            }
+            if circles_intersect(blast, asteroids.circle[j]) {
+                asteroids.hp[j] -= tuning.explosion_damage;
+                *destroyed = asteroids.hp[j] <= 0;
+            }
         }
-        asteroids.exists[i] = false;
-        let velocity1 = asteroids.velocity[i].clone().rotated(-FRAC_PI_8);
-        let velocity2 = asteroids.velocity[i].clone().rotated(FRAC_PI_8);
-        let new_radius = if asteroids.circle[i].radius == ASTEROID_RADIUS_LARGE {
-            ASTEROID_RADIUS_MEDIUM
-        } else {
-            ASTEROID_RADIUS_SMALL
+    }
+
+    for i in (0..destroyed.len()).filter(|&i| destroyed[i]) {
+        asteroids.pool.free(i);
+        let material = asteroids.material[i];
+        events.push(GameEvent::AsteroidDestroyed {
+            position: asteroids.circle[i].center,
+            velocity: asteroids.velocity[i],
+            radius: asteroids.circle[i].radius,
+            material,
+        });
+        let size = AsteroidSize::from_radius(asteroids.circle[i].radius).unwrap();
+        let smaller = match (material, size.smaller()) {
+            (AsteroidMaterial::EXPLOSIVE, _) | (_, None) => continue,
+            (_, Some(smaller)) => smaller,
         };
-        let (position1, position2) = displace_circles(
-            &Circle {
-                radius: new_radius,
-                center: asteroids.circle[i].center + velocity1.normalized() * 0.001,
-            },
-            &Circle {
-                radius: new_radius,
-                center: asteroids.circle[i].center + velocity2.normalized() * 0.001,
-            },
+        let velocity = asteroids.velocity[i] * tuning.asteroid_split_speed_multiplier;
+        if material == AsteroidMaterial::ICE {
+            shatter_asteroid(asteroids, i, velocity, tuning.ice_shatter_pieces);
+        } else {
+            split_asteroid(asteroids, i, smaller, velocity);
+        }
+    }
+}
+
+fn split_asteroid(asteroids: &mut Asteroids, i: usize, new_size: AsteroidSize, velocity: Point) {
+    let velocity1 = velocity.rotated(-FRAC_PI_8);
+    let velocity2 = velocity.rotated(FRAC_PI_8);
+    let new_radius = new_size.radius();
+    let (position1, position2) = displace_circles(
+        &Circle {
+            radius: new_radius,
+            center: asteroids.circle[i].center + velocity1.normalized() * 0.001,
+        },
+        &Circle {
+            radius: new_radius,
+            center: asteroids.circle[i].center + velocity2.normalized() * 0.001,
+        },
+    );
+    let material = asteroids.material[i];
+    asteroids.create(new_size, material, position1, velocity1);
+    asteroids.create(new_size, material, position2, velocity2);
+}
+
+fn shatter_asteroid(asteroids: &mut Asteroids, i: usize, velocity: Point, pieces: usize) {
+    // Ice skips the intermediate sizes and breaks into a ring of small pieces. Slot i is free
+    // already and the first piece may take it, so read the circle before creating any
+    let center = asteroids.circle[i].center;
+    let ring_radius = asteroids.circle[i].radius - ASTEROID_RADIUS_SMALL;
+    for k in 0..pieces {
+        let t = k as f32 / (pieces - 1) as f32;
+        let piece_velocity = velocity.rotated(-FRAC_PI_4 + t * FRAC_PI_2);
+        let ring_angle = velocity.angle() + 2.0 * PI * k as f32 / pieces as f32;
+        asteroids.create(
+            AsteroidSize::SMALL,
+            AsteroidMaterial::ICE,
+            center + Point::from_polar(ring_radius, ring_angle),
+            piece_velocity,
         );
-        let new_radius_enum = AsteroidSize::from_radius(new_radius).unwrap();
-        asteroids
-            .create(new_radius_enum, position1, velocity1)
-            .unwrap();
-        asteroids
-            .create(new_radius_enum, position2, velocity2)
-            .unwrap();
     }
 }
 
-pub fn asteroid_ship_collisions(asteroids: &mut Asteroids, ships: &mut Ships) {
-    for i in 0..MAX_ASTEROIDS {
-        if !asteroids.exists[i] {
+pub fn asteroid_ship_collisions(
+    asteroids: &mut Asteroids,
+    ships: &mut Ships,
+    events: &mut Vec<GameEvent>,
+    contacts: &mut Vec<Point>,
+    tuning: &PhysicsTuning,
+) {
+    for i in 0..asteroids.pool.capacity() {
+        if !asteroids.pool.exists(i) {
             continue;
         }
         for j in 0..MAX_SHIPS {
-            if !ships.exists[j] {
+            if !ships.pool.exists(j) {
                 continue;
             }
             let (collision, closest) =
                 triangle_circle_intersect(&ships.triangle[j], &asteroids.circle[i]);
             if collision {
+                contacts.push(closest);
                 let angular_velocity_linear = ships.angular_velocity[j]
                     * (closest - ships.triangle[j].circumcenter()).perpendicular();
                 let (new_asteroid_velocity, new_ship_velocity) = calculate_collision_velocities(
                     asteroids.velocity[i],
                     ships.velocity[j] + angular_velocity_linear,
-                    asteroids.circle[i].radius
-                        * asteroids.circle[i].radius
-                        * asteroids.circle[i].radius,
-                    SHIP_MASS,
-                    SHIP_COEFFICIENT_OF_RESTITUTION,
+                    asteroids.mass(i),
+                    ships.tuning.mass,
+                    tuning.ship_restitution,
                 );
                 let ship_v_delta = new_ship_velocity - ships.velocity[j];
                 asteroids.velocity[i] = new_asteroid_velocity;
                 ships.velocity[j] = new_ship_velocity;
                 let new_closest = displace_point_from_circle(&asteroids.circle[i], closest);
                 ships.triangle[j].update_position(new_closest - closest, 1.0);
-                ships.hp[j] -= ship_v_delta.magnitude().min(100.0) as i8;
-                ships.exists[j] = ships.hp[j] > 0;
+                let damage = ship_v_delta.magnitude().min(100.0) as i8;
+                ships.hp[j] -= damage;
+                events.push(GameEvent::ShipDamaged {
+                    position: closest,
+                    damage,
+                    source: DamageSource::ASTEROID,
+                });
+                if ships.hp[j] <= 0 {
+                    destroy_ship(ships, j, DamageSource::ASTEROID, events);
+                }
             }
         }
     }
 }
+
+pub fn apply_gravity(
+    wells: &GravityWells,
+    asteroids: &mut Asteroids,
+    bullets: &mut Bullets,
+    ships: &mut Ships,
+    dt: f32,
+) {
+    if wells.pool.none_exist() {
+        return;
+    }
+    for i in 0..asteroids.pool.capacity() {
+        if asteroids.pool.exists(i) {
+            asteroids.velocity[i] += wells.acceleration_at(asteroids.circle[i].center) * dt;
+        }
+    }
+    for i in 0..bullets.pool.capacity() {
+        if bullets.pool.exists(i) {
+            let acceleration = wells.acceleration_at(bullets.line_segment[i].p2);
+            bullets.accelerate(i, acceleration * dt);
+        }
+    }
+    for i in 0..MAX_SHIPS {
+        if ships.pool.exists(i) {
+            ships.velocity[i] += wells.acceleration_at(ships.triangle[i].circumcenter()) * dt;
+        }
+    }
+}
+
+pub fn gravity_well_collisions(
+    wells: &GravityWells,
+    asteroids: &mut Asteroids,
+    bullets: &mut Bullets,
+    ships: &mut Ships,
+    events: &mut Vec<GameEvent>,
+    contacts: &mut Vec<Point>,
+    tuning: &PhysicsTuning,
+) {
+    for w in 0..MAX_GRAVITY_WELLS {
+        if !wells.pool.exists(w) {
+            continue;
+        }
+        let is_blackhole = wells.kind[w] == GravityWellKind::BLACKHOLE;
+        // Black holes swallow whatever crosses the kill radius, planets are solid
+        let body = Circle {
+            center: wells.circle[w].center,
+            radius: if is_blackhole {
+                wells.kill_radius[w]
+            } else {
+                wells.circle[w].radius
+            },
+        };
+        for i in 0..asteroids.pool.capacity() {
+            if !asteroids.pool.exists(i) || !circles_intersect(body, asteroids.circle[i]) {
+                continue;
+            }
+            if is_blackhole {
+                asteroids.pool.free(i);
+                continue;
+            }
+            let normal = normal_or(
+                asteroids.circle[i].center - body.center,
+                normal_or(-asteroids.velocity[i], UP),
+            );
+            asteroids.velocity[i] =
+                bounce_off_static_body(asteroids.velocity[i], normal, tuning.asteroid_restitution);
+            asteroids.circle[i].center =
+                body.center + normal * (body.radius + asteroids.circle[i].radius);
+        }
+        for i in 0..bullets.pool.capacity() {
+            if bullets.pool.exists(i)
+                && line_segment_circle_intersect(&bullets.line_segment[i], &body).0
+            {
+                bullets.pool.free(i);
+            }
+        }
+        for i in 0..MAX_SHIPS {
+            if !ships.pool.exists(i) {
+                continue;
+            }
+            let (collision, closest) = triangle_circle_intersect(&ships.triangle[i], &body);
+            if !collision {
+                continue;
+            }
+            contacts.push(closest);
+            if is_blackhole {
+                ships.hp[i] = 0;
+                destroy_ship(ships, i, DamageSource::BLACKHOLE, events);
+                continue;
+            }
+            let normal = normal_or(closest - body.center, normal_or(-ships.velocity[i], UP));
+            let new_velocity =
+                bounce_off_static_body(ships.velocity[i], normal, tuning.ship_restitution);
+            let ship_v_delta = new_velocity - ships.velocity[i];
+            ships.velocity[i] = new_velocity;
+            let new_closest = displace_point_from_circle(&body, closest);
+            ships.triangle[i].update_position(new_closest - closest, 1.0);
+            let damage = ship_v_delta.magnitude().min(100.0) as i8;
+            ships.hp[i] -= damage;
+            events.push(GameEvent::ShipDamaged {
+                position: closest,
+                damage,
+                source: DamageSource::PLANET,
+            });
+            if ships.hp[i] <= 0 {
+                destroy_ship(ships, i, DamageSource::PLANET, events);
+            }
+        }
+    }
+}
+
+pub fn destroy_ship(ships: &mut Ships, i: usize, cause: DamageSource, events: &mut Vec<GameEvent>) {
+    ships.pool.free(i);
+    events.push(GameEvent::ShipDestroyed {
+        triangle: ships.triangle[i],
+        velocity: ships.velocity[i],
+        cause,
+    });
+}
+
+// The direction of vector, or fallback when it's too short to have one
+fn normal_or(vector: Point, fallback: Point) -> Point {
+    if vector.magnitude_squared() > f32::EPSILON {
+        vector.normalized()
+    } else {
+        fallback
+    }
+}
+
+fn bounce_off_static_body(velocity: Point, normal: Point, e: f32) -> Point {
+    let normal_speed = velocity.dot(normal);
+    if normal_speed >= 0.0 {
+        // Already moving away
+        return velocity;
+    }
+    velocity - (1.0 + e) * normal_speed * normal
+}
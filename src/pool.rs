@@ -0,0 +1,235 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+
+// -----------------------------------------------------------------------------
+
+// Handle
+// Identifies one object in a pool, it stops being valid when the slot is freed
+#[derive(Default, Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Handle {
+    pub index: usize,
+    pub generation: u32,
+}
+
+// -----------------------------------------------------------------------------
+
+// What to do when the pool is full and can't grow anymore
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    // Refuse to create the new object
+    DROP,
+    // Free the oldest object and reuse its slot
+    RECYCLE,
+}
+
+// Pool
+// Only keeps track of which slots are in use, the objects keep their data in their own arrays
+// indexed by the slot index and resize them to the pool capacity after allocating
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Pool {
+    exists: Vec<bool>,
+    generation: Vec<u32>,
+    free_list: Vec<usize>,
+    allocation_order: VecDeque<Handle>,
+    max_capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl Pool {
+    pub fn new(capacity: usize, max_capacity: usize, policy: OverflowPolicy) -> Pool {
+        let mut pool = Pool {
+            exists: vec![false; capacity],
+            generation: vec![0; capacity],
+            free_list: Vec::with_capacity(max_capacity),
+            allocation_order: VecDeque::with_capacity(capacity),
+            max_capacity: max_capacity.max(capacity),
+            policy,
+        };
+        pool.clear();
+        pool
+    }
+
+    pub fn allocate(&mut self) -> Option<Handle> {
+        let index = if let Some(index) = self.free_list.pop() {
+            index
+        } else if self.capacity() < self.max_capacity {
+            self.exists.push(false);
+            self.generation.push(0);
+            self.capacity() - 1
+        } else {
+            match self.policy {
+                OverflowPolicy::DROP => return None,
+                OverflowPolicy::RECYCLE => {
+                    let oldest = self.pop_oldest()?;
+                    self.free(oldest.index);
+                    self.free_list.pop()?
+                }
+            }
+        };
+        self.exists[index] = true;
+        let handle = self.handle(index);
+        self.allocation_order.push_back(handle);
+        // Freed objects are removed lazily, compact the queue once it gets too long
+        if self.allocation_order.len() > 2 * self.capacity() {
+            let (exists, generation) = (&self.exists, &self.generation);
+            self.allocation_order
+                .retain(|h| exists[h.index] && generation[h.index] == h.generation);
+        }
+        Some(handle)
+    }
+
+    pub fn free(&mut self, index: usize) {
+        if self.exists[index] {
+            self.exists[index] = false;
+            self.generation[index] = self.generation[index].wrapping_add(1);
+            self.free_list.push(index);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for index in 0..self.capacity() {
+            if self.exists[index] {
+                self.generation[index] = self.generation[index].wrapping_add(1);
+            }
+        }
+        self.exists.iter_mut().for_each(|e| *e = false);
+        // Reversed so the lowest indices are handed out first
+        self.free_list.clear();
+        self.free_list.extend((0..self.capacity()).rev());
+        self.allocation_order.clear();
+    }
+
+    pub fn exists(&self, index: usize) -> bool {
+        self.exists[index]
+    }
+
+    pub fn none_exist(&self) -> bool {
+        !self.exists.iter().any(|&e| e)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.exists.len()
+    }
+
+    pub fn handle(&self, index: usize) -> Handle {
+        Handle {
+            index,
+            generation: self.generation[index],
+        }
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<usize> {
+        if handle.index < self.capacity()
+            && self.exists[handle.index]
+            && self.generation[handle.index] == handle.generation
+        {
+            Some(handle.index)
+        } else {
+            None
+        }
+    }
+
+    // For pools read from files, which may have been cut short or edited by hand
+    pub fn validate(&self) -> Result<(), String> {
+        let capacity = self.capacity();
+        if self.generation.len() != capacity || capacity > self.max_capacity {
+            return Err("pool sizes don't match".to_string());
+        }
+        // A slot listed twice would be handed out twice
+        let mut listed = vec![false; capacity];
+        for &index in &self.free_list {
+            if index >= capacity || self.exists[index] || listed[index] {
+                return Err("pool free list has slots in use, repeated or out of range".to_string());
+            }
+            listed[index] = true;
+        }
+        if self.allocation_order.iter().any(|h| h.index >= capacity) {
+            return Err("pool allocation order has slots out of range".to_string());
+        }
+        Ok(())
+    }
+
+    fn pop_oldest(&mut self) -> Option<Handle> {
+        while let Some(handle) = self.allocation_order.pop_front() {
+            if self.get(handle).is_some() {
+                return Some(handle);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_handles_are_rejected() {
+        let mut pool = Pool::new(4, 4, OverflowPolicy::DROP);
+        let first = pool.allocate().unwrap();
+        pool.free(first.index);
+        assert_eq!(pool.get(first), None);
+        // The slot is reused, but the old handle stays stale
+        let second = pool.allocate().unwrap();
+        assert_eq!(second.index, first.index);
+        assert_ne!(second.generation, first.generation);
+        assert_eq!(pool.get(first), None);
+        assert_eq!(pool.get(second), Some(second.index));
+        pool.clear();
+        assert_eq!(pool.get(second), None);
+        assert!(pool.none_exist());
+    }
+
+    #[test]
+    fn grows_up_to_max_capacity_then_drops() {
+        let mut pool = Pool::new(2, 4, OverflowPolicy::DROP);
+        let handles: Vec<Handle> = (0..4).map(|_| pool.allocate().unwrap()).collect();
+        assert_eq!(pool.capacity(), 4);
+        assert_eq!(pool.allocate(), None);
+        assert_eq!(pool.capacity(), 4);
+        assert!(handles.iter().all(|&h| pool.get(h).is_some()));
+    }
+
+    #[test]
+    fn recycle_evicts_the_oldest_live_object() {
+        let mut pool = Pool::new(3, 3, OverflowPolicy::RECYCLE);
+        let a = pool.allocate().unwrap();
+        let b = pool.allocate().unwrap();
+        let c = pool.allocate().unwrap();
+        pool.free(a.index);
+        let d = pool.allocate().unwrap();
+        // a is gone already, so b is the oldest one still alive
+        let e = pool.allocate().unwrap();
+        assert_eq!(e.index, b.index);
+        assert_eq!(pool.get(b), None);
+        assert!([c, d, e].iter().all(|&h| pool.get(h).is_some()));
+    }
+
+    #[test]
+    fn allocation_order_is_compacted() {
+        let mut pool = Pool::new(2, 2, OverflowPolicy::RECYCLE);
+        for _ in 0..100 {
+            let handle = pool.allocate().unwrap();
+            pool.free(handle.index);
+        }
+        assert!(pool.allocation_order.len() <= 2 * pool.capacity() + 1);
+        assert!(pool.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_broken_pools() {
+        let mut pool = Pool::new(4, 4, OverflowPolicy::DROP);
+        pool.allocate();
+        assert!(pool.validate().is_ok());
+        let mut repeated = pool.clone();
+        repeated.free_list.push(repeated.free_list[0]);
+        assert!(repeated.validate().is_err());
+        let mut in_use = pool.clone();
+        in_use.free_list.push(0);
+        assert!(in_use.validate().is_err());
+        let mut short = pool.clone();
+        short.generation.pop();
+        assert!(short.validate().is_err());
+    }
+}
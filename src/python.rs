@@ -0,0 +1,560 @@
+use crate::backend::SdlBackend;
+use crate::camera::Camera;
+use crate::desync::{load_state_log, state_hash};
+use crate::env::{Action, Env, EnvConfig, StepInfo, DISCRETE_ACTIONS};
+use crate::game_objects::{Player, MAX_SHIPS};
+use crate::input::PlayerInput;
+use crate::levels::Campaign;
+use crate::render::{Renderer, ScaleMode};
+use crate::sensors::Sensors;
+use crate::shapes::Point;
+use crate::snapshot::{load_snapshot, save_snapshot};
+use crate::tuning::Tuning;
+use crate::vec_env::VecEnv;
+use crate::world::{valid_world_size, World, WorldState, DEFAULT_WORLD_SIZE, MAX_WORLD_SIZE};
+
+use numpy::{PyArray1, PyArray2, PyArray3, PyArrayMethods, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::exceptions::{PyIOError, PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::surface::Surface;
+
+use std::path::PathBuf;
+
+// -----------------------------------------------------------------------------
+
+const DEFAULT_RESOLUTION: (u32, u32) = (800, 600);
+
+// (observation, reward, done, info)
+type StepResult<'py> = (Bound<'py, PyArray1<f32>>, f32, bool, Bound<'py, PyDict>);
+// (observations, rewards, dones, infos)
+type VecStepResult<'py> = (
+    Bound<'py, PyArray2<f32>>,
+    Bound<'py, PyArray1<f32>>,
+    Bound<'py, PyArray1<bool>>,
+    Bound<'py, PyDict>,
+);
+
+// The Python extension module, everything in it is a copy of what's in the Rust library: World,
+// Env, VecEnv and Replay, with observations and frames as NumPy arrays
+#[pymodule]
+fn rusteroids(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyWorld>()?;
+    module.add_class::<PyEnv>()?;
+    module.add_class::<PyVecEnv>()?;
+    module.add_class::<PyReplay>()?;
+    module.add("DISCRETE_ACTIONS", DISCRETE_ACTIONS)?;
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+
+// The game itself, for up to MAX_SHIPS players flown by setting their inputs before every update
+#[pyclass(name = "World")]
+struct PyWorld {
+    world: World,
+    inputs: [PlayerInput; MAX_SHIPS],
+}
+
+#[pymethods]
+impl PyWorld {
+    #[new]
+    #[pyo3(signature = (
+        seed=0,
+        width=DEFAULT_WORLD_SIZE.x,
+        height=DEFAULT_WORLD_SIZE.y,
+        campaign=None,
+        tuning=None,
+    ))]
+    fn new(
+        seed: u64,
+        width: f32,
+        height: f32,
+        campaign: Option<PathBuf>,
+        tuning: Option<PathBuf>,
+    ) -> PyResult<PyWorld> {
+        let size = Point {
+            x: width,
+            y: height,
+        };
+        if !valid_world_size(size) {
+            return Err(PyValueError::new_err(format!(
+                "Invalid size {width}x{height}, expected up to {MAX_WORLD_SIZE} each way"
+            )));
+        }
+        let mut world = World::new(size, seed);
+        if let Some(path) = campaign {
+            world.campaign = Campaign::load(&path).map_err(PyIOError::new_err)?;
+        }
+        if let Some(path) = tuning {
+            world.set_tuning(Tuning::load(&path).map_err(PyIOError::new_err)?);
+        }
+        Ok(PyWorld::from_world(world))
+    }
+
+    // A saved game, from --save-snapshot or save_snapshot
+    #[staticmethod]
+    fn load_snapshot(path: PathBuf) -> PyResult<PyWorld> {
+        let state = load_snapshot(&path).map_err(PyIOError::new_err)?;
+        Ok(PyWorld::from_state(state))
+    }
+
+    fn save_snapshot(&self, path: PathBuf) -> PyResult<()> {
+        save_snapshot(&path, &self.world.save_state()).map_err(PyIOError::new_err)
+    }
+
+    // Players are 1 and 2, like --player. Thrust and turning are held until the next call, firing
+    // needs to be released before it fires again
+    #[pyo3(signature = (player, thrust=false, left=false, right=false, fire=false))]
+    fn set_input(
+        &mut self,
+        player: usize,
+        thrust: bool,
+        left: bool,
+        right: bool,
+        fire: bool,
+    ) -> PyResult<()> {
+        let player = Some(player)
+            .filter(|&p| (1..=MAX_SHIPS).contains(&p))
+            .and_then(|p| Player::from_index(p - 1))
+            .ok_or_else(|| PyValueError::new_err(format!("Invalid player {player}")))?;
+        let input = PlayerInput {
+            thrust,
+            left,
+            right,
+            fire,
+        };
+        let index = player.to_index();
+        input.apply(self.inputs[index], &mut self.world.ships, player);
+        self.inputs[index] = input;
+        Ok(())
+    }
+
+    // One tick of dt seconds, one frame at the tuned frame rate by default
+    #[pyo3(signature = (dt=None))]
+    fn update(&mut self, dt: Option<f32>) {
+        let dt = dt.unwrap_or((1.0 / self.world.tuning.frame_rate) as f32);
+        self.world.update(dt);
+    }
+
+    #[getter]
+    fn size(&self) -> (f32, f32) {
+        (self.world.size.x, self.world.size.y)
+    }
+
+    #[getter]
+    fn score(&self) -> u32 {
+        self.world.score
+    }
+
+    #[getter]
+    fn level(&self) -> i32 {
+        self.world.level
+    }
+
+    // One row for every ship that exists: player, x, y, velocity x and y, angle and HP
+    fn ships<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        let ships = &self.world.ships;
+        let rows: Vec<Vec<f32>> = (0..ships.pool.capacity())
+            .filter(|&i| ships.pool.exists(i))
+            .map(|i| {
+                let position = ships.triangle[i].circumcenter();
+                vec![
+                    (i + 1) as f32,
+                    position.x,
+                    position.y,
+                    ships.velocity[i].x,
+                    ships.velocity[i].y,
+                    ships.triangle[i].angle(),
+                    ships.hp[i] as f32,
+                ]
+            })
+            .collect();
+        to_rows(py, rows, 7)
+    }
+
+    // One row for every asteroid: x, y, velocity x and y and radius
+    fn asteroids<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        let asteroids = &self.world.asteroids;
+        let rows: Vec<Vec<f32>> = (0..asteroids.pool.capacity())
+            .filter(|&i| asteroids.pool.exists(i))
+            .map(|i| {
+                let circle = &asteroids.circle[i];
+                vec![
+                    circle.center.x,
+                    circle.center.y,
+                    asteroids.velocity[i].x,
+                    asteroids.velocity[i].y,
+                    circle.radius,
+                ]
+            })
+            .collect();
+        to_rows(py, rows, 5)
+    }
+
+    // Same as the hashes in state logs
+    fn state_hash(&self) -> u64 {
+        state_hash(&self.world.save_state())
+    }
+
+    #[pyo3(signature = (width=DEFAULT_RESOLUTION.0, height=DEFAULT_RESOLUTION.1))]
+    fn render<'py>(
+        &self,
+        py: Python<'py>,
+        width: u32,
+        height: u32,
+    ) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        render_frame(py, &self.world, width, height)
+    }
+}
+
+impl PyWorld {
+    fn from_world(world: World) -> PyWorld {
+        PyWorld {
+            world,
+            inputs: [PlayerInput::default(); MAX_SHIPS],
+        }
+    }
+
+    fn from_state(state: WorldState) -> PyWorld {
+        let mut world = World::new(state.size, 0);
+        world.restore_state(state);
+        PyWorld::from_world(world)
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// Env, with actions as an int below DISCRETE_ACTIONS or a (thrust, turn, fire) tuple
+#[pyclass(name = "Env")]
+struct PyEnv {
+    env: Env,
+}
+
+#[pymethods]
+impl PyEnv {
+    #[new]
+    #[pyo3(signature = (
+        frame_skip=4,
+        max_steps=Some(10_000),
+        rays=16,
+        sensor_range=50.0,
+        campaign=None,
+        tuning=None,
+    ))]
+    fn new(
+        frame_skip: u32,
+        max_steps: Option<u32>,
+        rays: usize,
+        sensor_range: f32,
+        campaign: Option<PathBuf>,
+        tuning: Option<PathBuf>,
+    ) -> PyResult<PyEnv> {
+        let config = env_config(frame_skip, max_steps, rays, sensor_range, campaign, tuning)?;
+        Ok(PyEnv {
+            env: Env::new(config),
+        })
+    }
+
+    #[pyo3(signature = (seed=0))]
+    fn reset<'py>(&mut self, py: Python<'py>, seed: u64) -> Bound<'py, PyArray1<f32>> {
+        PyArray1::from_vec(py, self.env.reset(seed).to_vec())
+    }
+
+    fn step<'py>(
+        &mut self,
+        py: Python<'py>,
+        action: &Bound<'py, PyAny>,
+    ) -> PyResult<StepResult<'py>> {
+        let action = match action.extract::<usize>() {
+            Ok(action) => Action::Discrete(action),
+            Err(_) => {
+                let (thrust, turn, fire) = action.extract()?;
+                Action::Continuous { thrust, turn, fire }
+            }
+        };
+        let (observation, reward, done, info) = self.env.step(action);
+        let observation = PyArray1::from_vec(py, observation.to_vec());
+        Ok((observation, reward, done, info_dict(py, &info)?))
+    }
+
+    #[getter]
+    fn observation_size(&self) -> usize {
+        self.env.observation_size()
+    }
+
+    // A copy of the game as it is now
+    fn world(&self) -> PyWorld {
+        PyWorld::from_state(self.env.world().save_state())
+    }
+
+    #[pyo3(signature = (width=DEFAULT_RESOLUTION.0, height=DEFAULT_RESOLUTION.1))]
+    fn render<'py>(
+        &self,
+        py: Python<'py>,
+        width: u32,
+        height: u32,
+    ) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        render_frame(py, self.env.world(), width, height)
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// VecEnv, with actions as an array of ints or one (thrust, turn, fire) row for each environment.
+// Steps release the GIL
+#[pyclass(name = "VecEnv")]
+struct PyVecEnv {
+    envs: VecEnv,
+}
+
+#[pymethods]
+impl PyVecEnv {
+    #[new]
+    #[pyo3(signature = (
+        num_envs,
+        seed=0,
+        frame_skip=4,
+        max_steps=Some(10_000),
+        rays=16,
+        sensor_range=50.0,
+        campaign=None,
+        tuning=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        num_envs: usize,
+        seed: u64,
+        frame_skip: u32,
+        max_steps: Option<u32>,
+        rays: usize,
+        sensor_range: f32,
+        campaign: Option<PathBuf>,
+        tuning: Option<PathBuf>,
+    ) -> PyResult<PyVecEnv> {
+        let config = env_config(frame_skip, max_steps, rays, sensor_range, campaign, tuning)?;
+        Ok(PyVecEnv {
+            envs: VecEnv::new(config, num_envs, seed),
+        })
+    }
+
+    #[pyo3(signature = (seed=0))]
+    fn reset<'py>(&mut self, py: Python<'py>, seed: u64) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        self.envs.reset(seed);
+        self.observations(py)
+    }
+
+    // With infos as a dict of arrays
+    fn step<'py>(
+        &mut self,
+        py: Python<'py>,
+        actions: &Bound<'py, PyAny>,
+    ) -> PyResult<VecStepResult<'py>> {
+        let actions: Vec<Action> = match actions.extract::<PyReadonlyArray1<i64>>() {
+            Ok(actions) => actions
+                .as_array()
+                .iter()
+                .map(|&action| Action::Discrete(usize::try_from(action).unwrap_or(usize::MAX)))
+                .collect(),
+            Err(_) => {
+                let actions = actions.extract::<PyReadonlyArray2<f32>>()?;
+                let actions = actions.as_array();
+                if actions.ncols() != 3 {
+                    return Err(PyValueError::new_err(
+                        "Continuous actions need 3 columns: thrust, turn and fire",
+                    ));
+                }
+                actions
+                    .rows()
+                    .into_iter()
+                    .map(|row| Action::Continuous {
+                        thrust: row[0],
+                        turn: row[1],
+                        fire: row[2],
+                    })
+                    .collect()
+            }
+        };
+        if actions.len() != self.envs.num_envs() {
+            return Err(PyValueError::new_err(format!(
+                "Got {} actions for {} environments",
+                actions.len(),
+                self.envs.num_envs()
+            )));
+        }
+        let envs = &mut self.envs;
+        py.detach(|| envs.step(&actions));
+
+        let infos = PyDict::new(py);
+        let info = |field: fn(&StepInfo) -> f32| -> Vec<f32> {
+            self.envs.infos().iter().map(field).collect()
+        };
+        infos.set_item("steps", PyArray1::from_vec(py, info(|i| i.steps as f32)))?;
+        infos.set_item("score", PyArray1::from_vec(py, info(|i| i.score as f32)))?;
+        infos.set_item("level", PyArray1::from_vec(py, info(|i| i.level as f32)))?;
+        infos.set_item("hp", PyArray1::from_vec(py, info(|i| i.hp as f32)))?;
+        infos.set_item("accuracy", PyArray1::from_vec(py, info(StepInfo::accuracy)))?;
+        let truncated: Vec<bool> = self.envs.infos().iter().map(|i| i.truncated).collect();
+        infos.set_item("truncated", PyArray1::from_vec(py, truncated))?;
+        Ok((
+            self.observations(py)?,
+            PyArray1::from_slice(py, self.envs.rewards()),
+            PyArray1::from_slice(py, self.envs.dones()),
+            infos,
+        ))
+    }
+
+    #[getter]
+    fn num_envs(&self) -> usize {
+        self.envs.num_envs()
+    }
+
+    #[getter]
+    fn observation_size(&self) -> usize {
+        self.envs.observation_size()
+    }
+
+    #[pyo3(signature = (index, width=DEFAULT_RESOLUTION.0, height=DEFAULT_RESOLUTION.1))]
+    fn render<'py>(
+        &self,
+        py: Python<'py>,
+        index: usize,
+        width: u32,
+        height: u32,
+    ) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        if index >= self.envs.num_envs() {
+            return Err(PyIndexError::new_err(format!("No environment {index}")));
+        }
+        render_frame(py, self.envs.env(index).world(), width, height)
+    }
+}
+
+impl PyVecEnv {
+    fn observations<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<f32>>> {
+        PyArray1::from_slice(py, self.envs.observations())
+            .reshape([self.envs.num_envs(), self.envs.observation_size()])
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// A run recorded with --state-log, every tick of it as a World
+#[pyclass(name = "Replay")]
+struct PyReplay {
+    states: Vec<(u32, WorldState)>,
+}
+
+#[pymethods]
+impl PyReplay {
+    #[new]
+    fn new(path: PathBuf) -> PyResult<PyReplay> {
+        Ok(PyReplay {
+            states: load_state_log(&path).map_err(PyIOError::new_err)?,
+        })
+    }
+
+    fn __len__(&self) -> usize {
+        self.states.len()
+    }
+
+    fn __getitem__(&self, index: usize) -> PyResult<PyWorld> {
+        Ok(PyWorld::from_state(self.state(index)?.clone()))
+    }
+
+    // The tick each state is from, they count from 1
+    fn ticks(&self) -> Vec<u32> {
+        self.states.iter().map(|(tick, _)| *tick).collect()
+    }
+
+    #[pyo3(signature = (index, width=DEFAULT_RESOLUTION.0, height=DEFAULT_RESOLUTION.1))]
+    fn render<'py>(
+        &self,
+        py: Python<'py>,
+        index: usize,
+        width: u32,
+        height: u32,
+    ) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let world = PyWorld::from_state(self.state(index)?.clone()).world;
+        render_frame(py, &world, width, height)
+    }
+}
+
+impl PyReplay {
+    fn state(&self, index: usize) -> PyResult<&WorldState> {
+        self.states
+            .get(index)
+            .map(|(_, state)| state)
+            .ok_or_else(|| PyIndexError::new_err(format!("No state {index} in the replay")))
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// No sensors at all with 0 rays
+fn env_config(
+    frame_skip: u32,
+    max_steps: Option<u32>,
+    rays: usize,
+    sensor_range: f32,
+    campaign: Option<PathBuf>,
+    tuning: Option<PathBuf>,
+) -> PyResult<EnvConfig> {
+    let mut config = EnvConfig {
+        frame_skip,
+        max_steps,
+        sensors: (rays > 0).then_some(Sensors {
+            rays,
+            range: sensor_range,
+        }),
+        ..Default::default()
+    };
+    if let Some(path) = campaign {
+        config.campaign = Campaign::load(&path).map_err(PyIOError::new_err)?;
+    }
+    if let Some(path) = tuning {
+        config.tuning = Tuning::load(&path).map_err(PyIOError::new_err)?;
+    }
+    Ok(config)
+}
+
+fn info_dict<'py>(py: Python<'py>, info: &StepInfo) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("steps", info.steps)?;
+    dict.set_item("score", info.score)?;
+    dict.set_item("level", info.level)?;
+    dict.set_item("hp", info.hp)?;
+    dict.set_item("shots", info.shots)?;
+    dict.set_item("hits", info.hits)?;
+    dict.set_item("accuracy", info.accuracy())?;
+    dict.set_item("truncated", info.truncated)?;
+    Ok(dict)
+}
+
+fn to_rows<'py>(
+    py: Python<'py>,
+    rows: Vec<Vec<f32>>,
+    columns: usize,
+) -> PyResult<Bound<'py, PyArray2<f32>>> {
+    let count = rows.len();
+    PyArray1::from_vec(py, rows.concat()).reshape([count, columns])
+}
+
+// The whole world with the HUD, as height x width x RGB bytes. Drawn in software like --headless,
+// no window or display needed
+fn render_frame<'py>(
+    py: Python<'py>,
+    world: &World,
+    width: u32,
+    height: u32,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    let canvas = Surface::new(width, height, PixelFormatEnum::RGB888)
+        .and_then(|surface| surface.into_canvas())
+        .map_err(PyValueError::new_err)?;
+    let camera = Camera::new(world.size, world.size);
+    let mut renderer = Renderer::new(SdlBackend::new(canvas), camera, ScaleMode::LETTERBOX);
+    renderer.render_world(world);
+    let pixels = renderer.backend.pixels().map_err(PyValueError::new_err)?;
+    PyArray1::from_vec(py, pixels).reshape([height as usize, width as usize, 3])
+}
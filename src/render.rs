@@ -1,100 +1,466 @@
-use crate::game_objects::{Asteroids, Bullets, Ships, MAX_ASTEROIDS, MAX_BULLETS, MAX_SHIPS};
-use crate::shapes::Point;
+use crate::backend::RenderBackend;
+use crate::camera::Camera;
+use crate::events::GameEvent;
+use crate::game_objects::{
+    AsteroidMaterial, Asteroids, Bullets, GravityWellKind, GravityWells, Ships, MAX_GRAVITY_WELLS,
+    MAX_SHIPS,
+};
+use crate::highscores::{HighScores, NameEntry, MAX_NAME_LENGTH};
+use crate::particles::{ParticleKind, Particles, MAX_PARTICLES};
+use crate::physics::BROAD_PHASE_CELL_SIZE;
+use crate::shapes::{Line, Point, Triangle};
+use crate::world::World;
 
-use sdl2::gfx::primitives::DrawRenderer;
 use sdl2::pixels::Color;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::rect::Rect;
 
-pub struct Renderer {
-    pub canvas: Canvas<Window>,
-    pub max_coords: Point,
-    scaling_factor: f32,
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+
+const HUD_MARGIN: f32 = 4.0; // Pixels
+const HUD_LINE_HEIGHT: f32 = 12.0;
+const DEMO_CHARACTER_WIDTH: usize = 8; // Pixels, of the built-in font
+const DEBUG_VECTOR_SCALE: f32 = 0.25; // Seconds, vectors are drawn as the distance covered in that time
+const DEBUG_CONTACT_RADIUS: f32 = 0.5;
+const DEBUG_SHAPE_COLOR: Color = Color::RGB(0, 255, 255);
+const DEBUG_VELOCITY_COLOR: Color = Color::RGB(0, 255, 0);
+const DEBUG_ACCELERATION_COLOR: Color = Color::RGB(255, 0, 0);
+const DEBUG_CONTACT_COLOR: Color = Color::RGB(255, 255, 0);
+const DEBUG_CELL_COLOR: Color = Color::RGBA(255, 0, 255, 96);
+
+// How the world is fitted into a window with a different aspect ratio
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum ScaleMode {
+    // Keep the aspect ratio, with black bars on the sides
+    #[default]
+    LETTERBOX,
+    // Fill the whole window, distorting the shapes
+    STRETCH,
 }
 
-impl Renderer {
-    pub fn new(canvas: Canvas<Window>) -> Renderer {
-        Renderer {
-            canvas,
-            max_coords: Point::default(),
-            scaling_factor: 0.0,
+// Draws the game with any backend: a window, a software surface, an SVG file...
+pub struct Renderer<B: RenderBackend> {
+    pub backend: B,
+    pub camera: Camera,
+    // Draw the debug overlay on top of the game
+    pub debug: bool,
+    scale_mode: ScaleMode,
+    render_time: Duration,
+    window_size: Point,
+    // View to screen transform
+    scale: Point,
+    offset: Point,
+}
+
+impl<B: RenderBackend> Renderer<B> {
+    pub fn new(backend: B, camera: Camera, scale_mode: ScaleMode) -> Renderer<B> {
+        let mut renderer = Renderer {
+            backend,
+            camera,
+            debug: false,
+            scale_mode,
+            render_time: Duration::ZERO,
+            window_size: Point::default(),
+            scale: Point::default(),
+            offset: Point::default(),
+        };
+        renderer.update_viewport();
+        renderer
+    }
+
+    pub fn update_viewport(&mut self) {
+        let canvas_size = self.backend.size();
+        self.window_size = Point {
+            x: canvas_size.0 as f32,
+            y: canvas_size.1 as f32,
+        };
+        self.update_transform();
+    }
+
+    pub fn update_camera(&mut self, ships: &Ships, dt: f32) {
+        self.camera.follow(ships, dt);
+        self.update_transform();
+    }
+
+    fn update_transform(&mut self) {
+        let view_size = self.camera.visible_size();
+        self.scale = match self.scale_mode {
+            ScaleMode::LETTERBOX => {
+                let scale =
+                    (self.window_size.x / view_size.x).min(self.window_size.y / view_size.y);
+                Point { x: scale, y: scale }
+            }
+            ScaleMode::STRETCH => Point {
+                x: self.window_size.x / view_size.x,
+                y: self.window_size.y / view_size.y,
+            },
+        };
+        self.offset = Point {
+            x: (self.window_size.x - view_size.x * self.scale.x) / 2.0,
+            y: (self.window_size.y - view_size.y * self.scale.y) / 2.0,
+        };
+        // Objects wrapping around the edges must not be drawn over the bars
+        self.backend.set_clip(Some(Rect::new(
+            self.offset.x as i32,
+            self.offset.y as i32,
+            (view_size.x * self.scale.x) as u32,
+            (view_size.y * self.scale.y) as u32,
+        )));
+    }
+
+    pub fn render_world(&mut self, world: &World) {
+        let start = Instant::now();
+        self.backend.clear(Color::BLACK);
+        self.render_gravity_wells(&world.gravity_wells);
+        self.render_particles(&world.particles);
+        self.render_ships(&world.ships);
+        self.render_bullets(&world.bullets);
+        self.render_asteroids(&world.asteroids);
+        self.render_hud(world);
+        if self.debug {
+            self.render_debug(world);
+        }
+        self.render_time = start.elapsed();
+    }
+
+    // Physics internals: collision shapes, motion vectors, contacts, broad phase and timings
+    pub fn render_debug(&mut self, world: &World) {
+        // Broad phase cells holding asteroids
+        let broad_phase = &world.broad_phase;
+        for (cell, indices) in broad_phase.cells.iter().enumerate() {
+            if indices.is_empty() {
+                continue;
+            }
+            let corner = Point {
+                x: (cell % broad_phase.columns) as f32 * BROAD_PHASE_CELL_SIZE,
+                y: (cell / broad_phase.columns) as f32 * BROAD_PHASE_CELL_SIZE,
+            };
+            self.draw_cell(corner, DEBUG_CELL_COLOR);
+        }
+
+        let asteroids = &world.asteroids;
+        for i in 0..asteroids.pool.capacity() {
+            if asteroids.pool.exists(i) {
+                let center = asteroids.circle[i].center;
+                let acceleration = world.gravity_wells.acceleration_at(center);
+                self.draw_vector(center, asteroids.velocity[i], DEBUG_VELOCITY_COLOR);
+                self.draw_vector(center, acceleration, DEBUG_ACCELERATION_COLOR);
+                self.draw_label(center, &format!("{i}:{}", asteroids.hp[i]));
+            }
+        }
+
+        let bullets = &world.bullets;
+        for i in 0..bullets.pool.capacity() {
+            if bullets.pool.exists(i) {
+                let tip = bullets.line_segment[i].p2;
+                self.draw_vector(tip, bullets.velocity[i], DEBUG_VELOCITY_COLOR);
+            }
+        }
+
+        let ships = &world.ships;
+        for i in 0..MAX_SHIPS {
+            if ships.pool.exists(i) {
+                let circumcircle = ships.triangle[i].circumcircle();
+                let center = circumcircle.center;
+                let acceleration = ships.triangle[i].direction() * ships.acceleration(i)
+                    + world.gravity_wells.acceleration_at(center);
+                self.draw_triangle(&ships.triangle[i], DEBUG_SHAPE_COLOR);
+                self.draw_circle(center, circumcircle.radius, DEBUG_SHAPE_COLOR);
+                self.draw_vector(center, ships.velocity[i], DEBUG_VELOCITY_COLOR);
+                self.draw_vector(center, acceleration, DEBUG_ACCELERATION_COLOR);
+                self.draw_label(center, &format!("{i}:{}", ships.hp[i]));
+            }
+        }
+
+        let wells = &world.gravity_wells;
+        for i in 0..MAX_GRAVITY_WELLS {
+            if wells.pool.exists(i) {
+                let circle = wells.circle[i];
+                self.draw_circle(circle.center, circle.radius, DEBUG_SHAPE_COLOR);
+                if wells.kind[i] == GravityWellKind::BLACKHOLE {
+                    self.draw_circle(circle.center, wells.kill_radius[i], DEBUG_SHAPE_COLOR);
+                }
+            }
+        }
+
+        for &contact in &world.debug.contacts {
+            self.draw_filled_circle(contact, DEBUG_CONTACT_RADIUS, DEBUG_CONTACT_COLOR);
+        }
+        for event in &world.events {
+            if let GameEvent::BulletHit { position, .. } = *event {
+                self.draw_filled_circle(position, DEBUG_CONTACT_RADIUS, DEBUG_CONTACT_COLOR);
+            }
+        }
+
+        // Timings of the last update and render, in the bottom left corner
+        let render_time = ("render", self.render_time);
+        let phase_times = world.debug.phase_times.iter().chain([&render_time]);
+        let lines = world.debug.phase_times.len() + 1;
+        let view_height = self.camera.visible_size().y * self.scale.y;
+        for (line, (name, time)) in phase_times.enumerate() {
+            let position = Point {
+                x: self.offset.x + HUD_MARGIN,
+                y: self.offset.y + view_height
+                    - HUD_MARGIN
+                    - (lines - line) as f32 * HUD_LINE_HEIGHT,
+            };
+            let text = format!("{name} {:.3} ms", time.as_secs_f64() * 1000.0);
+            self.backend.text(position, &text, Color::WHITE);
         }
     }
 
-    pub fn update_max_coords(&mut self) {
-        let canvas_size = self.canvas.output_size().unwrap();
-        let x = canvas_size.0 as f32;
-        let y = canvas_size.1 as f32;
-        let (max_x, max_y) = if x >= y { (x / y, 1.0) } else { (1.0, y / x) };
-        self.max_coords = Point {
-            x: max_x * 100.0,
-            y: max_y * 100.0,
+    pub fn render_hud(&mut self, world: &World) {
+        let position = Point {
+            x: self.offset.x + HUD_MARGIN,
+            y: self.offset.y + HUD_MARGIN,
+        };
+        let mut title = format!("LEVEL {}", world.level);
+        if !world.level_definition.name.is_empty() {
+            title += &format!(" {}", world.level_definition.name.to_uppercase());
+        }
+        if let Some(time_limit) = world.level_definition.time_limit {
+            let time_left = (time_limit - world.level_time).max(0.0).ceil();
+            title += &format!(" {time_left}s");
+        }
+        self.backend.text(position, &title, Color::WHITE);
+        let line = Point {
+            x: 0.0,
+            y: HUD_LINE_HEIGHT,
         };
+        let score = format!("SCORE {}", world.score);
+        self.backend.text(position + line, &score, Color::WHITE);
+        for i in 0..MAX_SHIPS {
+            if world.ships.pool.exists(i) {
+                let line = Point {
+                    x: 0.0,
+                    y: (i + 2) as f32 * HUD_LINE_HEIGHT,
+                };
+                let text = format!("P{} HP {}", i + 1, world.ships.hp[i]);
+                self.backend.text(position + line, &text, Color::WHITE);
+            }
+        }
+    }
+
+    // Over the game while the autopilot is flying
+    pub fn render_demo(&mut self) {
+        let text = "DEMO - PRESS ANY KEY TO PLAY";
+        let position = Point {
+            x: (self.window_size.x - (text.len() * DEMO_CHARACTER_WIDTH) as f32) / 2.0,
+            y: self.window_size.y * 0.75,
+        };
+        self.backend.text(position, text, Color::WHITE);
+    }
+
+    // The table for the mode, with the name being typed in below it
+    pub fn render_high_scores(
+        &mut self,
+        high_scores: &HighScores,
+        mode: &str,
+        entry: Option<&NameEntry>,
+    ) {
+        let mut lines = vec![format!("HIGH SCORES ({})", mode.to_uppercase())];
+        for (i, high_score) in high_scores.top(mode).enumerate() {
+            lines.push(format!(
+                "{:>2}. {:<width$} {:>8} LEVEL {}",
+                i + 1,
+                high_score.name,
+                high_score.score,
+                high_score.level,
+                width = MAX_NAME_LENGTH,
+            ));
+        }
+        if let Some(entry) = entry {
+            lines.push(String::new());
+            lines.push(format!("NEW HIGH SCORE {}, ENTER YOUR NAME", entry.score));
+            lines.push(format!("> {}_", entry.name));
+        }
+        let position = Point {
+            x: self.window_size.x * 0.25,
+            y: self.window_size.y * 0.25,
+        };
+        for (i, text) in lines.iter().enumerate() {
+            let line = Point {
+                x: 0.0,
+                y: i as f32 * HUD_LINE_HEIGHT,
+            };
+            self.backend.text(position + line, text, Color::WHITE);
+        }
     }
 
-    pub fn update_scaling_factor(&mut self) {
-        let canvas_size = self.canvas.output_size().unwrap();
-        let x = canvas_size.0 as f32;
-        let y = canvas_size.1 as f32;
-        self.scaling_factor = if x >= y { y } else { x } / 100.0
-    }
-
-    pub fn render_asteroids(&self, asteroids: &Asteroids) {
-        for i in 0..MAX_ASTEROIDS {
-            if asteroids.exists[i] {
-                self.canvas
-                    .aa_circle(
-                        (asteroids.circle[i].center.x * self.scaling_factor) as i16,
-                        (asteroids.circle[i].center.y * self.scaling_factor) as i16,
-                        (asteroids.circle[i].radius * self.scaling_factor) as i16,
-                        Color::WHITE,
-                    )
-                    .unwrap();
+    pub fn render_asteroids(&mut self, asteroids: &Asteroids) {
+        for i in 0..asteroids.pool.capacity() {
+            if asteroids.pool.exists(i) {
+                let center = asteroids.circle[i].center;
+                let radius = asteroids.circle[i].radius;
+                if !self.camera.is_visible(center, radius) {
+                    continue;
+                }
+                let color = asteroid_color(asteroids.material[i]);
+                self.draw_circle(center, radius, color);
+                match asteroids.material[i] {
+                    AsteroidMaterial::METAL => self.draw_circle(center, radius * 0.6, color),
+                    AsteroidMaterial::EXPLOSIVE => {
+                        self.draw_filled_circle(center, radius * 0.3, color)
+                    }
+                    _ => {}
+                }
             }
         }
     }
 
-    pub fn render_bullets(&self, bullets: &Bullets) {
-        for i in 0..MAX_BULLETS {
-            if bullets.exists[i] {
-                self.canvas
-                    .thick_line(
-                        (bullets.line_segment[i].p1.x * self.scaling_factor) as i16,
-                        (bullets.line_segment[i].p1.y * self.scaling_factor) as i16,
-                        (bullets.line_segment[i].p2.x * self.scaling_factor) as i16,
-                        (bullets.line_segment[i].p2.y * self.scaling_factor) as i16,
-                        2,
-                        Color::WHITE,
-                    )
-                    .unwrap();
+    pub fn render_bullets(&mut self, bullets: &Bullets) {
+        for i in 0..bullets.pool.capacity() {
+            if bullets.pool.exists(i) {
+                let line = &bullets.line_segment[i];
+                if !self.camera.is_visible(line.midpoint(), 1.0) {
+                    continue;
+                }
+                let shift = self.view_shift(line.midpoint());
+                let p1 = self.to_screen(line.p1 + shift);
+                let p2 = self.to_screen(line.p2 + shift);
+                self.backend.line(p1, p2, 2.0, Color::WHITE);
             }
         }
     }
 
-    pub fn render_ships(&self, ships: &Ships) {
+    pub fn render_ships(&mut self, ships: &Ships) {
         for i in 0..MAX_SHIPS {
-            if ships.exists[i] {
-                self.canvas
-                    .aa_trigon(
-                        (ships.triangle[i].v1.x * self.scaling_factor) as i16,
-                        (ships.triangle[i].v1.y * self.scaling_factor) as i16,
-                        (ships.triangle[i].v2.x * self.scaling_factor) as i16,
-                        (ships.triangle[i].v2.y * self.scaling_factor) as i16,
-                        (ships.triangle[i].v3.x * self.scaling_factor) as i16,
-                        (ships.triangle[i].v3.y * self.scaling_factor) as i16,
-                        Color::WHITE,
-                    )
-                    .unwrap();
+            if ships.pool.exists(i) {
                 let c = ships.triangle[i].circumcircle();
-                self.canvas
-                    .aa_circle(
-                        (c.center.x * self.scaling_factor) as i16,
-                        (c.center.y * self.scaling_factor) as i16,
-                        (c.radius * self.scaling_factor) as i16,
-                        Color::WHITE,
-                    )
-                    .unwrap();
+                if !self.camera.is_visible(c.center, c.radius) {
+                    continue;
+                }
+                self.draw_triangle(&ships.triangle[i], Color::WHITE);
+            }
+        }
+    }
+
+    pub fn render_gravity_wells(&mut self, wells: &GravityWells) {
+        for i in 0..MAX_GRAVITY_WELLS {
+            if wells.pool.exists(i) {
+                let center = wells.circle[i].center;
+                let radius = wells.circle[i].radius;
+                if !self
+                    .camera
+                    .is_visible(center, radius.max(wells.kill_radius[i] * 2.0))
+                {
+                    continue;
+                }
+                match wells.kind[i] {
+                    GravityWellKind::PLANET => {
+                        self.draw_filled_circle(center, radius, Color::RGB(30, 60, 120));
+                        self.draw_circle(center, radius, Color::RGB(90, 140, 255));
+                    }
+                    GravityWellKind::BLACKHOLE => {
+                        let kill_radius = wells.kill_radius[i];
+                        self.draw_circle(center, kill_radius, Color::RGB(140, 60, 200));
+                        self.draw_circle(center, kill_radius * 2.0, Color::RGB(70, 30, 100));
+                        self.draw_filled_circle(center, radius, Color::BLACK);
+                    }
+                }
             }
         }
     }
+
+    pub fn render_particles(&mut self, particles: &Particles) {
+        for i in 0..MAX_PARTICLES {
+            if particles.pool.exists(i) {
+                if !self
+                    .camera
+                    .is_visible(particles.line_segment[i].midpoint(), 1.0)
+                {
+                    continue;
+                }
+                let mut color = match particles.kind[i] {
+                    ParticleKind::SPARK => Color::RGB(255, 230, 150),
+                    ParticleKind::DEBRIS(material) => asteroid_color(material),
+                    ParticleKind::EXHAUST => Color::RGB(255, 150, 50),
+                    ParticleKind::FRAGMENT => Color::WHITE,
+                };
+                // Fade out over the lifetime
+                color.a = (255.0 * particles.lifetime[i] / particles.max_lifetime[i]) as u8;
+                self.draw_line(&particles.line_segment[i], color);
+            }
+        }
+    }
+
+    // Moves a shape next to the camera as a whole, so its vertices stay together across the
+    // wraparound
+    fn view_shift(&self, reference: Point) -> Point {
+        self.camera.to_view(reference) - reference
+    }
+
+    // Takes positions already relative to the view
+    fn to_screen(&self, p: Point) -> Point {
+        Point {
+            x: p.x * self.scale.x + self.offset.x,
+            y: p.y * self.scale.y + self.offset.y,
+        }
+    }
+
+    fn draw_line(&mut self, line: &Line, color: Color) {
+        let shift = self.view_shift(line.midpoint());
+        let p1 = self.to_screen(line.p1 + shift);
+        let p2 = self.to_screen(line.p2 + shift);
+        self.backend.line(p1, p2, 1.0, color);
+    }
+
+    fn draw_triangle(&mut self, triangle: &Triangle, color: Color) {
+        let shift = self.view_shift(triangle.centroid());
+        let vertices = [
+            self.to_screen(triangle.v1 + shift),
+            self.to_screen(triangle.v2 + shift),
+            self.to_screen(triangle.v3 + shift),
+        ];
+        self.backend.polygon(&vertices, color);
+    }
+
+    // Circles become ellipses when stretched
+    fn draw_circle(&mut self, center: Point, radius: f32, color: Color) {
+        let center = self.to_screen(self.camera.to_view(center));
+        self.backend
+            .ellipse(center, radius * self.scale.x, radius * self.scale.y, color);
+    }
+
+    fn draw_vector(&mut self, origin: Point, vector: Point, color: Color) {
+        let line = Line {
+            p1: origin,
+            p2: origin + vector * DEBUG_VECTOR_SCALE,
+        };
+        self.draw_line(&line, color);
+    }
+
+    fn draw_label(&mut self, position: Point, text: &str) {
+        let position = self.to_screen(self.camera.to_view(position));
+        self.backend.text(position, text, Color::WHITE);
+    }
+
+    // Broad phase grid cell, from its top left corner in world coordinates
+    fn draw_cell(&mut self, corner: Point, color: Color) {
+        let size = BROAD_PHASE_CELL_SIZE;
+        let shift = self.view_shift(corner + Point { x: size, y: size } / 2.0);
+        let vertices = [
+            self.to_screen(corner + shift),
+            self.to_screen(corner + Point { x: size, y: 0.0 } + shift),
+            self.to_screen(corner + Point { x: size, y: size } + shift),
+            self.to_screen(corner + Point { x: 0.0, y: size } + shift),
+        ];
+        self.backend.polygon(&vertices, color);
+    }
+
+    fn draw_filled_circle(&mut self, center: Point, radius: f32, color: Color) {
+        let center = self.to_screen(self.camera.to_view(center));
+        self.backend
+            .filled_ellipse(center, radius * self.scale.x, radius * self.scale.y, color);
+    }
+}
+
+fn asteroid_color(material: AsteroidMaterial) -> Color {
+    match material {
+        AsteroidMaterial::ROCK => Color::WHITE,
+        AsteroidMaterial::ICE => Color::RGB(150, 220, 255),
+        AsteroidMaterial::METAL => Color::RGB(170, 170, 190),
+        AsteroidMaterial::EXPLOSIVE => Color::RGB(255, 120, 40),
+    }
 }
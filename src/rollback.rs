@@ -0,0 +1,625 @@
+use crate::desync::{state_hash, StateLog};
+use crate::events::GameEvent;
+use crate::game_objects::{Player, MAX_SHIPS};
+use crate::input::PlayerInput;
+use crate::shapes::Point;
+use crate::world::{World, WorldState};
+
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::mem;
+use std::net::UdpSocket;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+
+const PROTOCOL_VERSION: u32 = 2;
+// Local inputs are simulated this many frames after they are read, so they usually reach the
+// other peer before it needs them
+pub const INPUT_DELAY: u32 = 2;
+// How far the simulation can run ahead of the other peer's inputs, it waits for them after that
+pub const MAX_ROLLBACK_FRAMES: u32 = 8;
+// Unacknowledged inputs go out again in every packet, up to this many
+const MAX_INPUTS_PER_MESSAGE: usize = 64;
+// Frames between the state hashes compared with the other peer
+const CHECKSUM_PERIOD: u32 = 30;
+const MAX_CHECKSUMS: usize = 16;
+const PEER_TIMEOUT: Duration = Duration::from_secs(5);
+const SPAWN_SPACING: f32 = 10.0;
+
+#[derive(Serialize, Deserialize)]
+enum RollbackMessage {
+    Inputs {
+        version: u32,
+        // Frame of the first input
+        start: u32,
+        inputs: Vec<PlayerInput>,
+        // Inputs received so far from the other peer, all of the frames before this one
+        received: u32,
+        // Hash of the newest confirmed state that was hashed, and its frame
+        checksum: Option<(u32, u64)>,
+    },
+    Bye,
+}
+
+impl RollbackMessage {
+    fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Messages always serialize")
+    }
+
+    fn decode(bytes: &[u8]) -> Option<RollbackMessage> {
+        bincode::deserialize(bytes).ok()
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// Transport
+// Carries packets to the other peer, losing or reordering them is fine
+pub trait Transport {
+    fn send(&mut self, bytes: &[u8]);
+    fn receive(&mut self) -> Option<Vec<u8>>;
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    pub fn new(bind_address: &str, peer_address: &str) -> Result<UdpTransport, String> {
+        let socket = UdpSocket::bind(bind_address).map_err(|e| format!("{bind_address}: {e}"))?;
+        socket
+            .connect(peer_address)
+            .map_err(|e| format!("{peer_address}: {e}"))?;
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(UdpTransport { socket })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, bytes: &[u8]) {
+        self.socket.send(bytes).ok();
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        let mut buffer = [0; 2048];
+        loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(count) => return Some(buffer[..count].to_vec()),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return None,
+                // The other peer isn't listening yet
+                Err(_) => continue,
+            }
+        }
+    }
+}
+
+// LoopbackLink
+// Two peers in the same process, with a fake network in between that delays, reorders and loses
+// packets. Time only moves on when tick is called, so a run always plays out the same
+pub struct LoopbackLink {
+    time: u32,
+    latency: u32, // Ticks
+    jitter: u32,  // Ticks
+    packet_loss: f64,
+    rng: Pcg32,
+    // Arrival tick and packet, for each side
+    in_flight: [Vec<(u32, Vec<u8>)>; 2],
+}
+
+impl LoopbackLink {
+    pub fn new(latency: u32, packet_loss: f64, seed: u64) -> Rc<RefCell<LoopbackLink>> {
+        Rc::new(RefCell::new(LoopbackLink {
+            time: 0,
+            latency,
+            jitter: latency / 4,
+            packet_loss,
+            rng: Pcg32::seed_from_u64(seed),
+            in_flight: [Vec::new(), Vec::new()],
+        }))
+    }
+
+    pub fn tick(&mut self) {
+        self.time += 1;
+    }
+
+    pub fn transports(link: &Rc<RefCell<LoopbackLink>>) -> (LoopbackTransport, LoopbackTransport) {
+        let transport = |side| LoopbackTransport {
+            link: Rc::clone(link),
+            side,
+        };
+        (transport(0), transport(1))
+    }
+}
+
+pub struct LoopbackTransport {
+    link: Rc<RefCell<LoopbackLink>>,
+    side: usize,
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&mut self, bytes: &[u8]) {
+        let link = &mut *self.link.borrow_mut();
+        if link.rng.gen_bool(link.packet_loss) {
+            return;
+        }
+        let jitter = link.rng.gen_range(0..=link.jitter);
+        let arrival = link
+            .time
+            .saturating_add(link.latency)
+            .saturating_add(jitter);
+        link.in_flight[1 - self.side].push((arrival, bytes.to_vec()));
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        let link = &mut *self.link.borrow_mut();
+        let time = link.time;
+        let in_flight = &mut link.in_flight[self.side];
+        let i = in_flight.iter().position(|&(arrival, _)| arrival <= time)?;
+        Some(in_flight.remove(i).1)
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+#[derive(Default, Copy, Clone)]
+pub struct RollbackStats {
+    pub rollbacks: u32,
+    pub resimulated_frames: u32,
+    // Ticks spent waiting for the other peer's inputs
+    pub stalls: u32,
+}
+
+struct SavedFrame {
+    frame: u32,
+    state: WorldState,
+    held: [PlayerInput; MAX_SHIPS],
+}
+
+// RollbackSession
+// One side of a two player match. Both peers simulate the whole game, guessing that the other
+// player keeps holding the same controls, and when the real inputs say otherwise they go back to
+// the last frame that was right and simulate the frames after it again
+pub struct RollbackSession<T: Transport> {
+    transport: T,
+    pub world: World,
+    local: Player,
+    remote: Player,
+    // Next frame to simulate
+    frame: u32,
+    // Controls held in the last simulated frame, the next inputs are applied as changes from them
+    held: [PlayerInput; MAX_SHIPS],
+    local_inputs: BTreeMap<u32, PlayerInput>,
+    remote_inputs: BTreeMap<u32, PlayerInput>,
+    // All the remote inputs before this frame arrived
+    remote_confirmed: u32,
+    // All the local inputs before this frame reached the other peer
+    remote_received: u32,
+    // Remote inputs that were guessed, for the frames simulated before they arrived
+    predictions: BTreeMap<u32, PlayerInput>,
+    // State before each of the last frames, oldest first
+    saved_frames: VecDeque<SavedFrame>,
+    // Saved states before this frame were already hashed or logged, if they had to be
+    checked: u32,
+    local_checksums: VecDeque<(u32, u64)>,
+    remote_checksums: VecDeque<(u32, u64)>,
+    last_heard: Option<Instant>,
+    pub stats: RollbackStats,
+    // Gets every state once it can't change anymore
+    pub state_log: Option<StateLog>,
+}
+
+impl<T: Transport> RollbackSession<T> {
+    // Both peers have to start from the same world
+    pub fn new(transport: T, mut world: World, local: Player) -> RollbackSession<T> {
+        start_versus(&mut world);
+        let remote = match local {
+            Player::PLAYER1 => Player::PLAYER2,
+            _ => Player::PLAYER1,
+        };
+        let delay: BTreeMap<u32, PlayerInput> = (0..INPUT_DELAY)
+            .map(|frame| (frame, PlayerInput::default()))
+            .collect();
+        RollbackSession {
+            transport,
+            world,
+            local,
+            remote,
+            frame: 0,
+            held: [PlayerInput::default(); MAX_SHIPS],
+            local_inputs: delay.clone(),
+            remote_inputs: delay,
+            remote_confirmed: INPUT_DELAY,
+            remote_received: INPUT_DELAY,
+            predictions: BTreeMap::new(),
+            saved_frames: VecDeque::new(),
+            checked: 0,
+            local_checksums: VecDeque::new(),
+            remote_checksums: VecDeque::new(),
+            last_heard: None,
+            stats: RollbackStats::default(),
+            state_log: None,
+        }
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    // Every simulated frame used the real inputs of both players
+    pub fn is_confirmed(&self) -> bool {
+        self.remote_confirmed >= self.frame
+    }
+
+    // One tick: reads what arrived, goes back if a guess was wrong and simulates the next frame.
+    // Returns false when it had to wait for the other peer instead
+    pub fn advance(&mut self, input: PlayerInput) -> Result<bool, String> {
+        self.poll()?;
+        if self.frame >= self.remote_confirmed + MAX_ROLLBACK_FRAMES {
+            self.stats.stalls += 1;
+            // Nothing new happened this tick
+            self.world.events.clear();
+            return Ok(false);
+        }
+        self.local_inputs.insert(self.frame + INPUT_DELAY, input);
+        self.send_inputs();
+        self.save_frame();
+        let inputs = self.inputs(self.frame);
+        simulate_frame(&mut self.world, inputs, &mut self.held);
+        self.frame += 1;
+        self.prune();
+        Ok(true)
+    }
+
+    // Exchanges inputs without simulating a new frame
+    pub fn poll(&mut self) -> Result<(), String> {
+        let mispredicted = self.receive()?;
+        if let Some(frame) = mispredicted {
+            self.roll_back(frame);
+        }
+        self.check_confirmed_frames()?;
+        self.send_inputs();
+        if self
+            .last_heard
+            .is_some_and(|last_heard| last_heard.elapsed() > PEER_TIMEOUT)
+        {
+            return Err("Lost the connection to the other player".to_string());
+        }
+        Ok(())
+    }
+
+    // First frame that was simulated with a wrong guess, if any
+    fn receive(&mut self) -> Result<Option<u32>, String> {
+        let mut mispredicted: Option<u32> = None;
+        while let Some(bytes) = self.transport.receive() {
+            let (start, inputs, received) = match RollbackMessage::decode(&bytes) {
+                Some(RollbackMessage::Inputs {
+                    version,
+                    start,
+                    inputs,
+                    received,
+                    checksum,
+                }) => {
+                    if version != PROTOCOL_VERSION {
+                        return Err(format!(
+                            "The other player uses protocol version {version}, this one uses {PROTOCOL_VERSION}"
+                        ));
+                    }
+                    let newest = self.remote_checksums.back().map(|&(frame, _)| frame);
+                    if let Some(checksum) = checksum.filter(|&(frame, _)| Some(frame) > newest) {
+                        push_checksum(&mut self.remote_checksums, checksum);
+                    }
+                    (start, inputs, received)
+                }
+                Some(RollbackMessage::Bye) => return Err("The other player left".to_string()),
+                None => continue,
+            };
+            self.last_heard = Some(Instant::now());
+            self.remote_received = self.remote_received.max(received);
+            // Inputs always start at or before the first missing one, so only the new ones count
+            for (frame, input) in (start..).zip(inputs) {
+                if frame != self.remote_confirmed {
+                    continue;
+                }
+                self.remote_inputs.insert(frame, input);
+                self.remote_confirmed += 1;
+                if self
+                    .predictions
+                    .remove(&frame)
+                    .is_some_and(|prediction| prediction != input)
+                {
+                    mispredicted = Some(mispredicted.map_or(frame, |f| f.min(frame)));
+                }
+            }
+        }
+        Ok(mispredicted)
+    }
+
+    // Hashes and logs the saved states that all the inputs before them are known for, and
+    // compares the hashes with the other peer's
+    fn check_confirmed_frames(&mut self) -> Result<(), String> {
+        for saved in &self.saved_frames {
+            if saved.frame < self.checked || saved.frame > self.remote_confirmed {
+                continue;
+            }
+            if let Some(state_log) = &mut self.state_log {
+                state_log.record(saved.frame, &saved.state)?;
+            }
+            if saved.frame % CHECKSUM_PERIOD == 0 {
+                let checksum = (saved.frame, state_hash(&saved.state));
+                push_checksum(&mut self.local_checksums, checksum);
+            }
+            self.checked = saved.frame + 1;
+        }
+        for &(frame, hash) in &self.remote_checksums {
+            let local = self.local_checksums.iter().find(|&&(f, _)| f == frame);
+            if local.is_some_and(|&(_, local_hash)| local_hash != hash) {
+                return Err(format!(
+                    "The game went out of sync with the other player at frame {frame}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Simulates the frames since the wrong guess again, with the inputs known now. Particles and
+    // events of those frames were already shown once, so they aren't produced again
+    fn roll_back(&mut self, frame: u32) {
+        let Some(position) = self.saved_frames.iter().position(|s| s.frame == frame) else {
+            return;
+        };
+        self.stats.rollbacks += 1;
+        let saved = &self.saved_frames[position];
+        let (state, held) = (saved.state.clone(), saved.held);
+        self.saved_frames.truncate(position);
+        let particles = mem::take(&mut self.world.particles);
+        self.world.restore_state(state);
+        self.held = held;
+        let end = self.frame;
+        for frame in frame..end {
+            self.frame = frame;
+            self.save_frame();
+            let inputs = self.inputs(frame);
+            simulate_frame(&mut self.world, inputs, &mut self.held);
+            self.stats.resimulated_frames += 1;
+        }
+        self.frame = end;
+        self.world.particles = particles;
+        self.world.events.clear();
+    }
+
+    // Inputs of both players for a frame, guessing the remote one when it didn't arrive yet
+    fn inputs(&mut self, frame: u32) -> [PlayerInput; MAX_SHIPS] {
+        let remote = match self.remote_inputs.get(&frame) {
+            Some(&input) => input,
+            None => {
+                let last = self.remote_inputs.values().next_back().copied();
+                let prediction = last.unwrap_or_default();
+                self.predictions.insert(frame, prediction);
+                prediction
+            }
+        };
+        let mut inputs = [PlayerInput::default(); MAX_SHIPS];
+        inputs[self.local.to_index()] = self.local_inputs[&frame];
+        inputs[self.remote.to_index()] = remote;
+        inputs
+    }
+
+    fn save_frame(&mut self) {
+        if self.saved_frames.len() > MAX_ROLLBACK_FRAMES as usize {
+            self.saved_frames.pop_front();
+        }
+        self.saved_frames.push_back(SavedFrame {
+            frame: self.frame,
+            state: self.world.save_state(),
+            held: self.held,
+        });
+    }
+
+    // Everything the other peer acknowledged that can't be rolled back to anymore
+    fn prune(&mut self) {
+        let oldest = self.saved_frames.front().map_or(self.frame, |s| s.frame);
+        let keep_local = oldest.min(self.remote_received);
+        self.local_inputs = self.local_inputs.split_off(&keep_local);
+        // The last confirmed remote input is the next guess
+        let keep_remote = oldest.min(self.remote_confirmed.saturating_sub(1));
+        self.remote_inputs = self.remote_inputs.split_off(&keep_remote);
+    }
+
+    fn send_inputs(&mut self) {
+        let start = self.remote_received;
+        let message = RollbackMessage::Inputs {
+            version: PROTOCOL_VERSION,
+            start,
+            inputs: self
+                .local_inputs
+                .range(start..)
+                .map(|(_, &input)| input)
+                .take(MAX_INPUTS_PER_MESSAGE)
+                .collect(),
+            received: self.remote_confirmed,
+            checksum: self.local_checksums.back().copied(),
+        };
+        self.transport.send(&message.encode());
+    }
+}
+
+impl<T: Transport> Drop for RollbackSession<T> {
+    fn drop(&mut self) {
+        self.transport.send(&RollbackMessage::Bye.encode());
+    }
+}
+
+fn push_checksum(checksums: &mut VecDeque<(u32, u64)>, checksum: (u32, u64)) {
+    if checksums.len() >= MAX_CHECKSUMS {
+        checksums.pop_front();
+    }
+    checksums.push_back(checksum);
+}
+
+// Versus games have both ships from the start, the world only creates the first one
+pub fn start_versus(world: &mut World) {
+    let ships = &mut world.ships;
+    let existing = (0..ships.pool.capacity())
+        .filter(|&i| ships.pool.exists(i))
+        .count();
+    for _ in existing..MAX_SHIPS {
+        let offset = Point {
+            x: SPAWN_SPACING,
+            y: 0.0,
+        };
+        ships.create(world.size / 2.0 + offset);
+    }
+}
+
+// Applies the inputs of both players and moves the world on by one frame. Everybody starts again
+// with nothing held when a new game starts
+pub fn simulate_frame(
+    world: &mut World,
+    inputs: [PlayerInput; MAX_SHIPS],
+    held: &mut [PlayerInput; MAX_SHIPS],
+) {
+    for (i, input) in inputs.iter().enumerate() {
+        if let Some(player) = Player::from_index(i) {
+            input.apply(held[i], &mut world.ships, player);
+        }
+    }
+    *held = inputs;
+    let dt = (1.0 / world.tuning.frame_rate) as f32;
+    world.update(dt);
+    let game_over = world
+        .events
+        .iter()
+        .any(|event| matches!(event, GameEvent::GameOver { .. }));
+    if game_over {
+        start_versus(world);
+        *held = [PlayerInput::default(); MAX_SHIPS];
+    }
+}
+
+// Copy of a world that plays out the same from here
+pub fn clone_world(world: &World) -> World {
+    let mut clone = World::new(world.size, 0);
+    clone.campaign = world.campaign.clone();
+    clone.restore_state(world.save_state());
+    clone
+}
+
+// -----------------------------------------------------------------------------
+
+pub struct LoopbackReport {
+    // Ticks it took both peers to simulate and confirm all of the frames
+    pub ticks: u32,
+    pub stats: [RollbackStats; 2],
+    // Both peers ended up where a plain simulation with the same inputs does
+    pub in_sync: bool,
+}
+
+// Plays a match between two peers over a loopback link, with random inputs held for a while
+// each, and checks the result against the same inputs simulated without any rollback
+pub fn loopback_test(
+    world: &World,
+    frames: u32,
+    latency: u32,
+    packet_loss: f64,
+    seed: u64,
+) -> Result<LoopbackReport, String> {
+    let mut rng = Pcg32::seed_from_u64(seed);
+    let scripts: Vec<Vec<PlayerInput>> = (0..MAX_SHIPS)
+        .map(|_| random_inputs(frames, &mut rng))
+        .collect();
+    let link = LoopbackLink::new(latency, packet_loss, rng.gen());
+    let (transport1, transport2) = LoopbackLink::transports(&link);
+    let mut peers = [
+        RollbackSession::new(transport1, clone_world(world), Player::PLAYER1),
+        RollbackSession::new(transport2, clone_world(world), Player::PLAYER2),
+    ];
+
+    // Lost inputs keep being sent again, so everything gets through eventually
+    let max_ticks = frames.saturating_mul(10).saturating_add(1000);
+    let mut ticks = 0;
+    while peers
+        .iter()
+        .any(|peer| peer.frame() < frames || !peer.is_confirmed())
+    {
+        if ticks == max_ticks {
+            return Err(format!("The peers didn't finish after {ticks} ticks"));
+        }
+        link.borrow_mut().tick();
+        for (peer, script) in peers.iter_mut().zip(&scripts) {
+            if peer.frame() < frames {
+                peer.advance(script[peer.frame() as usize])?;
+            } else {
+                peer.poll()?;
+            }
+        }
+        ticks += 1;
+    }
+
+    let mut reference = clone_world(world);
+    start_versus(&mut reference);
+    let mut held = [PlayerInput::default(); MAX_SHIPS];
+    for frame in 0..frames {
+        let mut inputs = [PlayerInput::default(); MAX_SHIPS];
+        if let Some(script_frame) = frame.checked_sub(INPUT_DELAY) {
+            for (input, script) in inputs.iter_mut().zip(&scripts) {
+                *input = script[script_frame as usize];
+            }
+        }
+        simulate_frame(&mut reference, inputs, &mut held);
+    }
+    let state = |world: &World| bincode::serialize(&world.save_state()).ok();
+    let expected = state(&reference);
+    Ok(LoopbackReport {
+        ticks,
+        stats: [peers[0].stats, peers[1].stats],
+        in_sync: peers.iter().all(|peer| state(&peer.world) == expected),
+    })
+}
+
+// Something like a player would press, each input held for a few frames
+fn random_inputs(frames: u32, rng: &mut impl Rng) -> Vec<PlayerInput> {
+    let mut inputs = Vec::with_capacity(frames as usize);
+    while inputs.len() < frames as usize {
+        let input = PlayerInput {
+            thrust: rng.gen_bool(0.5),
+            left: rng.gen_bool(0.3),
+            right: rng.gen_bool(0.3),
+            fire: rng.gen_bool(0.6),
+        };
+        let hold = rng.gen_range(5..40);
+        inputs.extend(std::iter::repeat_n(input, hold));
+    }
+    inputs.truncate(frames as usize);
+    inputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::DEFAULT_WORLD_SIZE;
+
+    #[test]
+    fn peers_stay_in_sync_over_a_bad_link() {
+        let world = World::new(DEFAULT_WORLD_SIZE, 3);
+        let report = loopback_test(&world, 300, 6, 0.1, 9).unwrap();
+        assert!(report.in_sync);
+        assert!(report.stats.iter().any(|stats| stats.rollbacks > 0));
+    }
+
+    #[test]
+    fn huge_latencies_never_arrive() {
+        let link = LoopbackLink::new(u32::MAX, 0.0, 1);
+        let (mut transport1, mut transport2) = LoopbackLink::transports(&link);
+        link.borrow_mut().tick();
+        transport1.send(&[1, 2, 3]);
+        link.borrow_mut().tick();
+        assert!(transport2.receive().is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::intersect::{ray_circle_intersect, ray_line_segment_intersect, ray_triangle_intersect};
+use crate::shapes::Point;
+use crate::world::World;
+
+use std::f32::consts::TAU;
+
+// -----------------------------------------------------------------------------
+
+pub const DEFAULT_RAYS: usize = 16;
+pub const DEFAULT_RANGE: f32 = 50.0;
+// Distance, what was hit as one of OBJECT_KINDS ones and zeros, and relative velocity
+pub const FEATURES_PER_RAY: usize = 1 + OBJECT_KINDS + 2;
+const OBJECT_KINDS: usize = 4;
+
+// What a ray ran into
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SensedObject {
+    NOTHING,
+    ASTEROID,
+    SHIP,
+    BULLET,
+    GRAVITYWELL,
+}
+
+#[derive(Copy, Clone)]
+pub struct RayHit {
+    // The sensor range when there was nothing
+    pub distance: f32,
+    pub object: SensedObject,
+    // Velocity of what was hit minus the ship's, along the ray (negative when it's coming
+    // closer) and across it (positive to the right of the ray, on screen)
+    pub relative_velocity: Point,
+}
+
+// Sensors
+// Rays cast all around a ship, like a lidar: the first one straight ahead and the rest evenly
+// spaced counterclockwise from it. Every object is looked for at its nearest copy across the
+// world's edges, so the range should stay under half the world size
+#[derive(Copy, Clone, Debug)]
+pub struct Sensors {
+    pub rays: usize,
+    pub range: f32,
+}
+
+impl Default for Sensors {
+    fn default() -> Self {
+        Sensors {
+            rays: DEFAULT_RAYS,
+            range: DEFAULT_RANGE,
+        }
+    }
+}
+
+impl Sensors {
+    // None when the ship doesn't exist
+    pub fn cast(&self, world: &World, ship: usize) -> Option<Vec<RayHit>> {
+        let ships = &world.ships;
+        if !ships.pool.exists(ship) {
+            return None;
+        }
+        let origin = ships.triangle[ship].circumcenter();
+        let heading = ships.triangle[ship].angle();
+        let velocity = ships.velocity[ship];
+        let hits = (0..self.rays)
+            .map(|i| {
+                let angle = heading - i as f32 * TAU / self.rays as f32;
+                let direction = Point::from_polar(1.0, angle);
+                self.cast_ray(world, ship, origin, direction, velocity)
+            })
+            .collect();
+        Some(hits)
+    }
+
+    fn cast_ray(
+        &self,
+        world: &World,
+        ship: usize,
+        origin: Point,
+        direction: Point,
+        velocity: Point,
+    ) -> RayHit {
+        let size = world.size;
+        // Where a copy of something at this position closest to the ship is
+        let nearest = |position: Point| origin + (position - origin).wrapped(size);
+        let mut closest = (self.range, SensedObject::NOTHING, velocity);
+        let mut hit = |distance: Option<f32>, object: SensedObject, object_velocity: Point| {
+            if let Some(distance) = distance.filter(|&d| d < closest.0) {
+                closest = (distance, object, object_velocity);
+            }
+        };
+
+        let asteroids = &world.asteroids;
+        for i in (0..asteroids.pool.capacity()).filter(|&i| asteroids.pool.exists(i)) {
+            let mut circle = asteroids.circle[i];
+            circle.center = nearest(circle.center);
+            let distance = ray_circle_intersect(origin, direction, &circle);
+            hit(distance, SensedObject::ASTEROID, asteroids.velocity[i]);
+        }
+        let ships = &world.ships;
+        for i in (0..ships.pool.capacity()).filter(|&i| i != ship && ships.pool.exists(i)) {
+            let mut triangle = ships.triangle[i];
+            let center = triangle.circumcenter();
+            triangle.update_position(nearest(center) - center, 1.0);
+            let distance = ray_triangle_intersect(origin, direction, &triangle);
+            hit(distance, SensedObject::SHIP, ships.velocity[i]);
+        }
+        let bullets = &world.bullets;
+        for i in (0..bullets.pool.capacity()).filter(|&i| bullets.pool.exists(i)) {
+            let mut line_segment = bullets.line_segment[i];
+            let start = line_segment.p1;
+            line_segment.update_position(nearest(start) - start, 1.0);
+            let distance = ray_line_segment_intersect(origin, direction, &line_segment);
+            hit(distance, SensedObject::BULLET, bullets.velocity[i]);
+        }
+        let gravity_wells = &world.gravity_wells;
+        for i in (0..gravity_wells.pool.capacity()).filter(|&i| gravity_wells.pool.exists(i)) {
+            let mut circle = gravity_wells.circle[i];
+            circle.center = nearest(circle.center);
+            let distance = ray_circle_intersect(origin, direction, &circle);
+            hit(distance, SensedObject::GRAVITYWELL, Point::default());
+        }
+
+        let (distance, object, object_velocity) = closest;
+        let relative = object_velocity - velocity;
+        RayHit {
+            distance,
+            object,
+            relative_velocity: Point {
+                x: relative.dot(direction),
+                y: relative.dot(direction.perpendicular()),
+            },
+        }
+    }
+
+    // FEATURES_PER_RAY for every ray, distances as fractions of the range and velocities as
+    // fractions of the world size per second. A ship that doesn't exist sees nothing
+    pub fn features(&self, world: &World, ship: usize) -> Vec<f32> {
+        let mut features = Vec::with_capacity(self.rays * FEATURES_PER_RAY);
+        let Some(hits) = self.cast(world, ship) else {
+            for _ in 0..self.rays {
+                features.push(1.0);
+                features.extend([0.0; FEATURES_PER_RAY - 1]);
+            }
+            return features;
+        };
+        for hit in hits {
+            features.push(hit.distance / self.range);
+            let mut kind = [0.0; OBJECT_KINDS];
+            if hit.object != SensedObject::NOTHING {
+                kind[hit.object as usize - 1] = 1.0;
+            }
+            features.extend(kind);
+            features.push(hit.relative_velocity.x / world.size.x);
+            features.push(hit.relative_velocity.y / world.size.y);
+        }
+        features
+    }
+}
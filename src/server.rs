@@ -0,0 +1,326 @@
+use crate::autopilot::Autopilot;
+use crate::events::{DamageSource, GameEvent};
+use crate::game_objects::{Player, MAX_SHIPS};
+use crate::input::PlayerInput;
+use crate::net::{delta_encode, Message, NetState, SnapshotHistory, PROTOCOL_VERSION};
+use crate::physics::destroy_ship;
+use crate::pool::Handle;
+use crate::shapes::Point;
+use crate::world::World;
+
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+// Ticks between snapshots
+const SNAPSHOT_PERIOD: u32 = 2;
+// Inputs waiting to be simulated, a client further ahead than this loses the oldest ones
+const MAX_QUEUED_INPUTS: usize = 16;
+const SPAWN_SPACING: f32 = 10.0;
+
+struct Connection {
+    address: SocketAddr,
+    // None while its ship is destroyed, until the next game starts
+    ship: Option<Handle>,
+    queued_inputs: VecDeque<(u32, PlayerInput)>,
+    input: PlayerInput,
+    last_input: Option<u32>,
+    last_received_input: Option<u32>,
+    ack: Option<u32>,
+    last_heard: Instant,
+}
+
+// Server
+// Runs the only real simulation, takes the inputs of up to MAX_SHIPS clients and sends them
+// delta encoded snapshots of the world
+pub struct Server {
+    socket: UdpSocket,
+    pub world: World,
+    connections: Vec<Connection>,
+    tick: u32,
+    history: SnapshotHistory,
+    // Since the last snapshot that was sent
+    events: Vec<GameEvent>,
+    // One for each ship, flying it while no client is, when bots are on
+    bots: Option<Vec<Autopilot>>,
+}
+
+impl Server {
+    pub fn bind(address: &str, world: World) -> Result<Server, String> {
+        let socket = UdpSocket::bind(address).map_err(|e| format!("{address}: {e}"))?;
+        socket
+            .set_nonblocking(true)
+            .map_err(|e| format!("{address}: {e}"))?;
+        Ok(Server {
+            socket,
+            world,
+            connections: Vec::new(),
+            tick: 0,
+            history: SnapshotHistory::default(),
+            events: Vec::new(),
+            bots: None,
+        })
+    }
+
+    // Fills the empty player slots with autopilots, every game starts with all the ships
+    pub fn enable_bots(&mut self) {
+        self.bots = Some(
+            (0..MAX_SHIPS)
+                .filter_map(Player::from_index)
+                .map(Autopilot::new)
+                .collect(),
+        );
+        self.spawn_bot_ships();
+    }
+
+    pub fn local_address(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+
+    // One tick of the simulation
+    pub fn update(&mut self) {
+        self.receive();
+        self.drop_silent_clients();
+        self.apply_inputs();
+        self.fly_bots();
+
+        let dt = (1.0 / self.world.tuning.frame_rate) as f32;
+        self.world.update(dt);
+        self.events.extend_from_slice(&self.world.events);
+        // Everybody gets a ship again when a new game starts
+        let game_over = self
+            .world
+            .events
+            .iter()
+            .any(|event| matches!(event, GameEvent::GameOver { .. }));
+        if game_over {
+            for i in 0..self.connections.len() {
+                self.claim_ship(i);
+            }
+            self.spawn_bot_ships();
+        }
+
+        self.tick += 1;
+        if self.tick.is_multiple_of(SNAPSHOT_PERIOD) {
+            self.send_snapshots();
+        }
+    }
+
+    fn receive(&mut self) {
+        let mut buffer = [0; 2048];
+        loop {
+            let (count, address) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                // Windows reports ICMP port unreachable from earlier sends here, just skip them
+                Err(_) => continue,
+            };
+            let Some(message) = Message::decode(&buffer[..count]) else {
+                continue;
+            };
+            let connection = self.connections.iter().position(|c| c.address == address);
+            match (message, connection) {
+                (Message::Hello { version }, None) => self.connect(address, version),
+                (Message::Hello { .. }, Some(_)) => self.send_welcome(address),
+                (Message::Input { ack, inputs }, Some(i)) => {
+                    let connection = &mut self.connections[i];
+                    connection.last_heard = Instant::now();
+                    if ack > connection.ack {
+                        connection.ack = ack;
+                    }
+                    for (sequence, input) in inputs {
+                        if connection.last_received_input < Some(sequence) {
+                            connection.queued_inputs.push_back((sequence, input));
+                            connection.last_received_input = Some(sequence);
+                        }
+                    }
+                    while connection.queued_inputs.len() > MAX_QUEUED_INPUTS {
+                        connection.queued_inputs.pop_front();
+                    }
+                }
+                (Message::Bye, Some(i)) => self.disconnect(i),
+                _ => {}
+            }
+        }
+    }
+
+    fn connect(&mut self, address: SocketAddr, version: u32) {
+        let refusal = if version != PROTOCOL_VERSION {
+            Some(format!(
+                "Protocol version {version} is not supported, the server uses {PROTOCOL_VERSION}"
+            ))
+        } else if self.connections.len() >= MAX_SHIPS {
+            Some("The server is full".to_string())
+        } else {
+            None
+        };
+        if let Some(reason) = refusal {
+            self.send(address, &Message::Refused { reason });
+            return;
+        }
+        eprintln!("{address} connected");
+        self.connections.push(Connection {
+            address,
+            ship: None,
+            queued_inputs: VecDeque::new(),
+            input: PlayerInput::default(),
+            last_input: None,
+            last_received_input: None,
+            ack: None,
+            last_heard: Instant::now(),
+        });
+        self.claim_ship(self.connections.len() - 1);
+        self.send_welcome(address);
+    }
+
+    fn disconnect(&mut self, i: usize) {
+        let connection = self.connections.remove(i);
+        eprintln!("{} disconnected", connection.address);
+        let ships = &mut self.world.ships;
+        if let Some(index) = connection.ship.and_then(|ship| ships.pool.get(ship)) {
+            destroy_ship(ships, index, DamageSource::DISCONNECT, &mut self.events);
+        }
+    }
+
+    fn drop_silent_clients(&mut self) {
+        while let Some(i) = self
+            .connections
+            .iter()
+            .position(|c| c.last_heard.elapsed() > CLIENT_TIMEOUT)
+        {
+            self.disconnect(i);
+        }
+    }
+
+    // Takes over a ship nobody is flying, or spawns a new one
+    fn claim_ship(&mut self, i: usize) {
+        let ships = &mut self.world.ships;
+        if self.connections[i]
+            .ship
+            .is_some_and(|ship| ships.pool.get(ship).is_some())
+        {
+            return;
+        }
+        let owned: Vec<usize> = self
+            .connections
+            .iter()
+            .filter_map(|c| c.ship.and_then(|ship| ships.pool.get(ship)))
+            .collect();
+        let free =
+            (0..MAX_SHIPS).find(|&index| ships.pool.exists(index) && !owned.contains(&index));
+        let ship = match free {
+            Some(index) => Some(ships.pool.handle(index)),
+            None => {
+                let offset = Point {
+                    x: owned.len() as f32 * SPAWN_SPACING,
+                    y: 0.0,
+                };
+                ships.create(self.world.size / 2.0 + offset)
+            }
+        };
+        // A bot might have been flying it
+        if let (Some(index), Some(bots)) =
+            (ship.and_then(|ship| ships.pool.get(ship)), &mut self.bots)
+        {
+            bots[index].release(ships);
+        }
+        let connection = &mut self.connections[i];
+        connection.ship = ship;
+        // The new ship starts with nothing pressed
+        connection.input = PlayerInput::default();
+    }
+
+    // Ship slots left over after the clients got theirs
+    fn spawn_bot_ships(&mut self) {
+        if self.bots.is_none() {
+            return;
+        }
+        let ships = &mut self.world.ships;
+        for index in 0..MAX_SHIPS {
+            if !ships.pool.exists(index) {
+                let offset = Point {
+                    x: index as f32 * SPAWN_SPACING,
+                    y: 0.0,
+                };
+                ships.create(self.world.size / 2.0 + offset);
+            }
+        }
+    }
+
+    fn fly_bots(&mut self) {
+        let Some(bots) = &mut self.bots else {
+            return;
+        };
+        let ships = &self.world.ships;
+        let owned: Vec<usize> = self
+            .connections
+            .iter()
+            .filter_map(|c| c.ship.and_then(|ship| ships.pool.get(ship)))
+            .collect();
+        for bot in bots {
+            if !owned.contains(&bot.player.to_index()) {
+                bot.fly(&mut self.world);
+            }
+        }
+    }
+
+    // One queued input per tick, the last one stays held when the queue runs dry
+    fn apply_inputs(&mut self) {
+        for connection in &mut self.connections {
+            let Some((sequence, input)) = connection.queued_inputs.pop_front() else {
+                continue;
+            };
+            connection.last_input = Some(sequence);
+            let ships = &mut self.world.ships;
+            let player = connection
+                .ship
+                .and_then(|ship| ships.pool.get(ship))
+                .and_then(Player::from_index);
+            if let Some(player) = player {
+                input.apply(connection.input, ships, player);
+                connection.input = input;
+            }
+        }
+    }
+
+    fn send_snapshots(&mut self) {
+        let state = NetState::capture(&self.world, mem::take(&mut self.events));
+        let bytes = bincode::serialize(&state).expect("Snapshots always serialize");
+        for connection in &self.connections {
+            let baseline = connection
+                .ack
+                .and_then(|tick| Some((tick, self.history.get(tick)?)));
+            let message = Message::Snapshot {
+                tick: self.tick,
+                baseline: baseline.map(|(tick, _)| tick),
+                player: connection
+                    .ship
+                    .and_then(|ship| self.world.ships.pool.get(ship))
+                    .map(|index| index as u8),
+                last_input: connection.last_input,
+                data: delta_encode(&bytes, baseline.map(|(_, bytes)| bytes)),
+            };
+            self.send(connection.address, &message);
+        }
+        self.history.push(self.tick, bytes);
+    }
+
+    fn send_welcome(&self, address: SocketAddr) {
+        let message = Message::Welcome {
+            world_size: self.world.size,
+            tick_rate: self.world.tuning.frame_rate,
+            tuning: self.world.tuning,
+        };
+        self.send(address, &message);
+    }
+
+    // Lost packets are the protocol's problem, not an error
+    fn send(&self, address: SocketAddr, message: &Message) {
+        self.socket.send_to(&message.encode(), address).ok();
+    }
+}
@@ -0,0 +1,312 @@
+use crate::balance::{Policy, DEFAULT_MAX_TIME};
+use crate::game_objects::{Player, MAX_SHIPS};
+use crate::render::ScaleMode;
+use crate::shapes::Point;
+use crate::world::{valid_world_size, DEFAULT_WORLD_SIZE, MAX_WORLD_SIZE};
+
+use std::env;
+use std::path::PathBuf;
+
+// -----------------------------------------------------------------------------
+
+const DEFAULT_RESOLUTION: (u32, u32) = (800, 600);
+const DEFAULT_VOLUME: f32 = 0.5;
+const DEFAULT_BIND_ADDRESS: &str = "0.0.0.0:7777";
+const DEFAULT_LATENCY: f64 = 100.0; // Milliseconds
+const MAX_LATENCY: f64 = 10000.0; // Milliseconds
+const DEFAULT_PACKET_LOSS: f64 = 0.05;
+
+pub struct Settings {
+    pub world_size: Point,
+    // Part of the world visible at once, the camera scrolls when it's smaller than the world
+    pub view_size: Option<Point>,
+    pub scale_mode: ScaleMode,
+    // Start with the debug overlay shown, F3 toggles it in the window
+    pub debug: bool,
+    // Size of the window, or of the image when headless
+    pub resolution: (u32, u32),
+    // From 0 to 1, 0 doesn't open the audio device at all
+    pub volume: f32,
+    // Render into memory instead of a window, running as fast as possible
+    pub headless: bool,
+    // Draw with text in the terminal and read keys from it, instead of opening a window
+    pub terminal: bool,
+    // Run an authoritative multiplayer server on this address instead of playing
+    pub server: Option<String>,
+    // Play on the server at this address
+    pub connect: Option<String>,
+    // Play a rollback versus match against the peer at this address
+    pub versus: Option<String>,
+    // Local address for versus matches
+    pub bind: String,
+    // Which ship this peer flies in a versus match, the other peer has to fly the other one
+    pub player: Option<Player>,
+    // Run a versus match between two peers in this process and check they stay in sync
+    pub loopback: bool,
+    // Fake network for the loopback peers
+    pub latency: f64, // Milliseconds
+    pub packet_loss: f64,
+    // Play this many games without drawing them and print statistics about them, starting from
+    // seed and counting up
+    pub balance: Option<u32>,
+    // Who plays the balance games
+    pub policy: Policy,
+    // Write the balance statistics as JSON, or CSV for any other extension
+    pub report: Option<PathBuf>,
+    // Balance games still going after this are cut short
+    pub max_time: f32, // Seconds
+    // Let the autopilot fly player 1, in the window until a key is pressed
+    pub demo: bool,
+    // Let the autopilot fly the server's ships that no client is flying
+    pub bots: bool,
+    // Stop after this many frames, headless runs default to a single frame
+    pub frames: Option<u32>,
+    // Write the last frame as a PNG image
+    pub screenshot: Option<PathBuf>,
+    // Write every frame as a PNG image into this directory
+    pub frame_dump: Option<PathBuf>,
+    // Write the last frame as an SVG image
+    pub svg: Option<PathBuf>,
+    // Seed for everything random in the game, a new one every run when left out
+    pub seed: Option<u64>,
+    // Start from a saved game instead of a new one
+    pub load_snapshot: Option<PathBuf>,
+    // Save the game when quitting, to pick it up later with load_snapshot
+    pub save_snapshot: Option<PathBuf>,
+    // Levels to play instead of the built-in campaign
+    pub campaign: Option<PathBuf>,
+    // Gameplay numbers, reloaded whenever the file changes
+    pub tuning: Option<PathBuf>,
+    // Where the high score table is kept, instead of the user data directory
+    pub high_scores: Option<PathBuf>,
+    // Name typed in for new high scores, the login name when left out
+    pub name: Option<String>,
+    // Write the state after every tick into this file
+    pub state_log: Option<PathBuf>,
+    // Compare two state logs instead of playing
+    pub diff_logs: Option<[PathBuf; 2]>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            world_size: DEFAULT_WORLD_SIZE,
+            view_size: None,
+            scale_mode: Default::default(),
+            debug: false,
+            resolution: DEFAULT_RESOLUTION,
+            volume: DEFAULT_VOLUME,
+            headless: false,
+            terminal: false,
+            server: None,
+            connect: None,
+            versus: None,
+            bind: DEFAULT_BIND_ADDRESS.to_string(),
+            player: None,
+            loopback: false,
+            latency: DEFAULT_LATENCY,
+            packet_loss: DEFAULT_PACKET_LOSS,
+            balance: None,
+            policy: Policy::AUTOPILOT,
+            report: None,
+            max_time: DEFAULT_MAX_TIME,
+            demo: false,
+            bots: false,
+            frames: None,
+            screenshot: None,
+            frame_dump: None,
+            svg: None,
+            seed: None,
+            load_snapshot: None,
+            save_snapshot: None,
+            campaign: None,
+            tuning: None,
+            high_scores: None,
+            name: None,
+            state_log: None,
+            diff_logs: None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn from_args() -> Result<Settings, String> {
+        let mut settings = Settings::default();
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {arg}"));
+            match arg.as_str() {
+                "--world-size" => settings.world_size = parse_size(&value()?)?,
+                "--view-size" => settings.view_size = Some(parse_size(&value()?)?),
+                "--scale-mode" => {
+                    settings.scale_mode = match value()?.as_str() {
+                        "letterbox" => ScaleMode::LETTERBOX,
+                        "stretch" => ScaleMode::STRETCH,
+                        other => return Err(format!("Invalid scale mode {other}")),
+                    }
+                }
+                "--debug" => settings.debug = true,
+                "--resolution" => {
+                    let size = parse_size(&value()?)?;
+                    settings.resolution = (size.x as u32, size.y as u32);
+                }
+                "--volume" => {
+                    let volume = value()?;
+                    settings.volume = volume
+                        .parse()
+                        .ok()
+                        .filter(|v| (0.0..=1.0).contains(v))
+                        .ok_or(format!("Invalid volume {volume}, expected 0 to 1"))?;
+                }
+                "--headless" => settings.headless = true,
+                "--terminal" => settings.terminal = true,
+                "--server" => settings.server = Some(value()?),
+                "--connect" => settings.connect = Some(value()?),
+                "--versus" => settings.versus = Some(value()?),
+                "--bind" => settings.bind = value()?,
+                "--player" => {
+                    let player = value()?;
+                    settings.player = Some(
+                        player
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|&p| (1..=MAX_SHIPS).contains(&p))
+                            .and_then(|p| Player::from_index(p - 1))
+                            .ok_or(format!("Invalid player {player}, expected 1 or 2"))?,
+                    );
+                }
+                "--loopback" => settings.loopback = true,
+                "--latency" => {
+                    let latency = value()?;
+                    settings.latency = latency
+                        .parse()
+                        .ok()
+                        .filter(|l| (0.0..=MAX_LATENCY).contains(l))
+                        .ok_or(format!(
+                            "Invalid latency {latency}, expected 0 to {MAX_LATENCY}"
+                        ))?;
+                }
+                "--packet-loss" => {
+                    let packet_loss = value()?;
+                    settings.packet_loss = packet_loss
+                        .parse()
+                        .ok()
+                        .filter(|l| (0.0..1.0).contains(l))
+                        .ok_or(format!(
+                            "Invalid packet loss {packet_loss}, expected 0 to less than 1"
+                        ))?;
+                }
+                "--balance" => {
+                    let games = value()?;
+                    settings.balance = Some(
+                        games
+                            .parse()
+                            .ok()
+                            .filter(|&g| g > 0)
+                            .ok_or(format!("Invalid number of games {games}"))?,
+                    );
+                }
+                "--policy" => {
+                    let policy = value()?;
+                    settings.policy = Policy::parse(&policy).ok_or(format!(
+                        "Invalid policy {policy}, expected idle, random or autopilot"
+                    ))?;
+                }
+                "--report" => settings.report = Some(PathBuf::from(value()?)),
+                "--max-time" => {
+                    let max_time = value()?;
+                    settings.max_time = max_time
+                        .parse()
+                        .ok()
+                        .filter(|&t: &f32| t > 0.0)
+                        .ok_or(format!("Invalid max time {max_time}"))?;
+                }
+                "--demo" => settings.demo = true,
+                "--bots" => settings.bots = true,
+                "--frames" => {
+                    let frames = value()?;
+                    settings.frames = Some(
+                        frames
+                            .parse()
+                            .map_err(|_| format!("Invalid frames {frames}"))?,
+                    );
+                }
+                "--screenshot" => settings.screenshot = Some(PathBuf::from(value()?)),
+                "--frame-dump" => settings.frame_dump = Some(PathBuf::from(value()?)),
+                "--svg" => settings.svg = Some(PathBuf::from(value()?)),
+                "--seed" => {
+                    let seed = value()?;
+                    settings.seed = Some(seed.parse().map_err(|_| format!("Invalid seed {seed}"))?);
+                }
+                "--load-snapshot" => settings.load_snapshot = Some(PathBuf::from(value()?)),
+                "--save-snapshot" => settings.save_snapshot = Some(PathBuf::from(value()?)),
+                "--campaign" => settings.campaign = Some(PathBuf::from(value()?)),
+                "--tuning" => settings.tuning = Some(PathBuf::from(value()?)),
+                "--high-scores" => settings.high_scores = Some(PathBuf::from(value()?)),
+                "--name" => settings.name = Some(value()?),
+                "--state-log" => settings.state_log = Some(PathBuf::from(value()?)),
+                "--diff-logs" => {
+                    let a = PathBuf::from(value()?);
+                    settings.diff_logs = Some([a, PathBuf::from(value()?)]);
+                }
+                _ => return Err(format!("Unknown argument {arg}")),
+            }
+        }
+        let modes = [
+            settings.headless,
+            settings.terminal,
+            settings.server.is_some(),
+            settings.connect.is_some(),
+            settings.versus.is_some(),
+            settings.loopback,
+            settings.balance.is_some(),
+        ];
+        if modes.iter().filter(|&&mode| mode).count() > 1 {
+            return Err(
+                "Only one of --headless, --terminal, --server, --connect, --versus, \
+                 --loopback and --balance can be used"
+                    .to_string(),
+            );
+        }
+        // Both peers simulate the same game, so it has to start the same on both
+        if settings.versus.is_some() && (settings.seed.is_none() || settings.player.is_none()) {
+            return Err(
+                "--versus needs --seed, the same on both peers, and --player, 1 on one peer \
+                 and 2 on the other"
+                    .to_string(),
+            );
+        }
+        let windowed = modes.iter().all(|&mode| !mode);
+        if settings.demo && !(windowed || settings.headless) {
+            return Err("--demo only works in the window or with --headless".to_string());
+        }
+        if settings.bots && settings.server.is_none() {
+            return Err("--bots only works with --server".to_string());
+        }
+        Ok(settings)
+    }
+
+    // High scores are only compared between games of the same mode
+    pub fn game_mode(&self) -> String {
+        match &self.campaign {
+            Some(path) => format!(
+                "campaign:{}",
+                path.file_stem().unwrap_or_default().to_string_lossy()
+            ),
+            None => "campaign".to_string(),
+        }
+    }
+}
+
+// Parses sizes written as WIDTHxHEIGHT
+fn parse_size(text: &str) -> Result<Point, String> {
+    let invalid = || format!("Invalid size {text}, expected WIDTHxHEIGHT up to {MAX_WORLD_SIZE}");
+    let (x, y) = text.split_once('x').ok_or_else(invalid)?;
+    let x = x.parse::<f32>().map_err(|_| invalid())?;
+    let y = y.parse::<f32>().map_err(|_| invalid())?;
+    let size = Point { x, y };
+    if !valid_world_size(size) {
+        return Err(invalid());
+    }
+    Ok(size)
+}
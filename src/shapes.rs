@@ -1,9 +1,11 @@
+use serde::{Deserialize, Serialize};
+
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
 // -----------------------------------------------------------------------------
 
 // Point
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
 pub struct Point {
     pub x: f32,
     pub y: f32,
@@ -100,6 +102,10 @@ impl Neg for Point {
 }
 
 impl Point {
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite()
+    }
+
     pub fn update_position(&mut self, delta: Point, dt: f32) {
         *self += delta * dt;
     }
@@ -142,6 +148,10 @@ impl Point {
         }
     }
 
+    pub fn dot(&self, other: Point) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
     pub fn normalized(&self) -> Point {
         *self * self.magnitude().recip()
     }
@@ -157,6 +167,15 @@ impl Point {
         self.y.atan2(self.x)
     }
 
+    // The shortest offset that leads to the same place in a world that wraps around
+    pub fn wrapped(&self, world_size: Point) -> Point {
+        let wrap = |d: f32, size: f32| (d + size / 2.0).rem_euclid(size) - size / 2.0;
+        Point {
+            x: wrap(self.x, world_size.x),
+            y: wrap(self.y, world_size.y),
+        }
+    }
+
     pub fn rotated(&self, angle: f32) -> Point {
         let (sin, cos) = angle.sin_cos();
         Point {
@@ -169,7 +188,7 @@ impl Point {
 // -----------------------------------------------------------------------------
 
 // Line
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
 pub struct Line {
     pub p1: Point,
     pub p2: Point,
@@ -180,12 +199,22 @@ impl Line {
         self.p1.update_position(delta, dt);
         self.p2.update_position(delta, dt);
     }
+
+    pub fn rotate_around_midpoint(&mut self, delta: f32, dt: f32) {
+        let m = self.midpoint();
+        self.p1 = (self.p1 - m).rotated(delta * dt) + m;
+        self.p2 = (self.p2 - m).rotated(delta * dt) + m;
+    }
+
+    pub fn midpoint(&self) -> Point {
+        (self.p1 + self.p2) / 2.0
+    }
 }
 
 // -----------------------------------------------------------------------------
 
 // Circle
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
 pub struct Circle {
     pub center: Point,
     pub radius: f32,
@@ -211,7 +240,7 @@ impl Circle {
 // -----------------------------------------------------------------------------
 
 // Triangle
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Copy, Clone, Serialize, Deserialize)]
 pub struct Triangle {
     pub v1: Point,
     pub v2: Point,
@@ -0,0 +1,41 @@
+use crate::world::WorldState;
+
+use std::fs;
+use std::path::Path;
+
+// -----------------------------------------------------------------------------
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"RSTROIDS";
+// Bump whenever anything in WorldState changes, old snapshots are refused instead of misread
+const SNAPSHOT_VERSION: u32 = 1;
+
+// The magic bytes, the version as 4 little endian bytes, and then the state in bincode
+pub fn save_snapshot(path: &Path, state: &WorldState) -> Result<(), String> {
+    let body = bincode::serialize(state).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut bytes = Vec::with_capacity(SNAPSHOT_MAGIC.len() + 4 + body.len());
+    bytes.extend_from_slice(SNAPSHOT_MAGIC);
+    bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&body);
+    fs::write(path, bytes).map_err(|e| format!("{}: {e}", path.display()))
+}
+
+pub fn load_snapshot(path: &Path) -> Result<WorldState, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let error = |message: &str| format!("{}: {message}", path.display());
+    let body = bytes
+        .strip_prefix(SNAPSHOT_MAGIC)
+        .ok_or_else(|| error("not a snapshot"))?;
+    if body.len() < 4 {
+        return Err(error("snapshot is truncated"));
+    }
+    let (version, body) = body.split_at(4);
+    let version = u32::from_le_bytes(version.try_into().unwrap());
+    if version != SNAPSHOT_VERSION {
+        return Err(error(&format!(
+            "snapshot version {version} is not supported, expected {SNAPSHOT_VERSION}"
+        )));
+    }
+    let state: WorldState = bincode::deserialize(body).map_err(|e| error(&e.to_string()))?;
+    state.validate().map_err(|e| error(&e))?;
+    Ok(state)
+}
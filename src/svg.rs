@@ -0,0 +1,173 @@
+use crate::backend::RenderBackend;
+use crate::shapes::Point;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+// -----------------------------------------------------------------------------
+
+// Size of the built-in SDL font, so text takes the same space in both backends
+const FONT_SIZE: u32 = 8;
+
+// Collects the frame as SVG elements, to export it as vector art
+pub struct SvgBackend {
+    width: u32,
+    height: u32,
+    background: Color,
+    clip: Option<Rect>,
+    elements: String,
+}
+
+impl SvgBackend {
+    pub fn new(width: u32, height: u32) -> SvgBackend {
+        SvgBackend {
+            width,
+            height,
+            background: Color::BLACK,
+            clip: None,
+            elements: String::new(),
+        }
+    }
+
+    pub fn to_svg(&self) -> String {
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+            self.width, self.height
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r#"<rect width="100%" height="100%" {}/>"#,
+            fill(self.background)
+        )
+        .unwrap();
+        if let Some(clip) = self.clip {
+            writeln!(
+                svg,
+                r#"<clipPath id="view"><rect x="{}" y="{}" width="{}" height="{}"/></clipPath>"#,
+                clip.x(),
+                clip.y(),
+                clip.width(),
+                clip.height()
+            )
+            .unwrap();
+            writeln!(svg, r#"<g clip-path="url(#view)">"#).unwrap();
+            svg.push_str(&self.elements);
+            writeln!(svg, "</g>").unwrap();
+        } else {
+            svg.push_str(&self.elements);
+        }
+        writeln!(svg, "</svg>").unwrap();
+        svg
+    }
+
+    pub fn save_svg(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_svg()).map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+impl RenderBackend for SvgBackend {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn clear(&mut self, color: Color) {
+        self.background = color;
+        self.elements.clear();
+    }
+
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        self.clip = clip;
+    }
+
+    fn line(&mut self, p1: Point, p2: Point, width: f32, color: Color) {
+        writeln!(
+            self.elements,
+            r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" stroke-width="{width}" stroke-linecap="round" {}/>"#,
+            p1.x,
+            p1.y,
+            p2.x,
+            p2.y,
+            stroke(color)
+        )
+        .unwrap();
+    }
+
+    fn ellipse(&mut self, center: Point, rx: f32, ry: f32, color: Color) {
+        writeln!(
+            self.elements,
+            r#"<ellipse cx="{:.2}" cy="{:.2}" rx="{rx:.2}" ry="{ry:.2}" fill="none" {}/>"#,
+            center.x,
+            center.y,
+            stroke(color)
+        )
+        .unwrap();
+    }
+
+    fn filled_ellipse(&mut self, center: Point, rx: f32, ry: f32, color: Color) {
+        writeln!(
+            self.elements,
+            r#"<ellipse cx="{:.2}" cy="{:.2}" rx="{rx:.2}" ry="{ry:.2}" {}/>"#,
+            center.x,
+            center.y,
+            fill(color)
+        )
+        .unwrap();
+    }
+
+    fn polygon(&mut self, vertices: &[Point], color: Color) {
+        let points: Vec<String> = vertices
+            .iter()
+            .map(|v| format!("{:.2},{:.2}", v.x, v.y))
+            .collect();
+        writeln!(
+            self.elements,
+            r#"<polygon points="{}" fill="none" {}/>"#,
+            points.join(" "),
+            stroke(color)
+        )
+        .unwrap();
+    }
+
+    fn text(&mut self, position: Point, text: &str, color: Color) {
+        let escaped = text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+        // SVG positions text by its baseline, SDL by its top left corner
+        writeln!(
+            self.elements,
+            r#"<text x="{:.2}" y="{:.2}" font-family="monospace" font-size="{FONT_SIZE}" {}>{escaped}</text>"#,
+            position.x,
+            position.y + FONT_SIZE as f32,
+            fill(color)
+        )
+        .unwrap();
+    }
+}
+
+fn stroke(color: Color) -> String {
+    format!(
+        r#"stroke="rgb({},{},{})" stroke-opacity="{:.3}""#,
+        color.r,
+        color.g,
+        color.b,
+        color.a as f32 / 255.0
+    )
+}
+
+fn fill(color: Color) -> String {
+    format!(
+        r#"fill="rgb({},{},{})" fill-opacity="{:.3}""#,
+        color.r,
+        color.g,
+        color.b,
+        color.a as f32 / 255.0
+    )
+}
@@ -0,0 +1,314 @@
+use crate::backend::RenderBackend;
+use crate::game_objects::{Player, RotationDirection, Ships};
+use crate::shapes::Point;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use std::f32::consts::PI;
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+
+// Each character cell holds a 2x4 grid of braille dots
+const DOTS_PER_CELL_X: u32 = 2;
+const DOTS_PER_CELL_Y: u32 = 4;
+const BRAILLE_BLANK: u32 = 0x2800;
+const BRAILLE_DOT_BITS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+// Terminals only report key presses and auto repeats, a key counts as released when it hasn't
+// been seen for this long
+const KEY_HOLD_TIMEOUT: Duration = Duration::from_millis(250);
+
+// Draws with unicode braille characters and ANSI colors
+pub struct TerminalBackend {
+    columns: u32,
+    rows: u32,
+    dots: Vec<u8>,
+    colors: Vec<Color>,
+    text: Vec<Option<char>>,
+    clip: Option<Rect>,
+}
+
+impl TerminalBackend {
+    pub fn new(columns: u32, rows: u32) -> TerminalBackend {
+        let cells = (columns * rows) as usize;
+        TerminalBackend {
+            columns,
+            rows,
+            dots: vec![0; cells],
+            colors: vec![Color::BLACK; cells],
+            text: vec![None; cells],
+            clip: None,
+        }
+    }
+
+    // Size of the terminal in characters, leaving the last row free so it never scrolls
+    pub fn terminal_size() -> (u32, u32) {
+        // SAFETY: TIOCGWINSZ only writes into the winsize struct passed to it
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+        let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+        if result != 0 || size.ws_col == 0 || size.ws_row < 2 {
+            (80, 24)
+        } else {
+            (size.ws_col as u32, size.ws_row as u32 - 1)
+        }
+    }
+
+    pub fn present(&self) -> io::Result<()> {
+        let mut frame = String::from("\x1b[H");
+        let mut current_color = None;
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let cell = (row * self.columns + column) as usize;
+                let character = match self.text[cell] {
+                    Some(character) => character,
+                    None => char::from_u32(BRAILLE_BLANK + self.dots[cell] as u32).unwrap(),
+                };
+                let color = self.colors[cell];
+                if current_color != Some(color) {
+                    write!(frame, "\x1b[38;2;{};{};{}m", color.r, color.g, color.b).unwrap();
+                    current_color = Some(color);
+                }
+                frame.push(character);
+            }
+            frame.push_str("\r\n");
+        }
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(frame.as_bytes())?;
+        stdout.flush()
+    }
+
+    fn plot(&mut self, x: f32, y: f32, color: Color) {
+        if x < 0.0 || y < 0.0 {
+            return;
+        }
+        let (x, y) = (x as u32, y as u32);
+        let (width, height) = self.size();
+        if x >= width || y >= height {
+            return;
+        }
+        if let Some(clip) = self.clip {
+            if !clip.contains_point((x as i32, y as i32)) {
+                return;
+            }
+        }
+        let cell = ((y / DOTS_PER_CELL_Y) * self.columns + x / DOTS_PER_CELL_X) as usize;
+        let bit = BRAILLE_DOT_BITS[(x % DOTS_PER_CELL_X) as usize][(y % DOTS_PER_CELL_Y) as usize];
+        // No blending per dot, so transparency just darkens the color
+        let fade = |c: u8| (c as u32 * color.a as u32 / 255) as u8;
+        let color = Color::RGB(fade(color.r), fade(color.g), fade(color.b));
+        if color == Color::BLACK {
+            self.dots[cell] &= !bit;
+        } else {
+            self.dots[cell] |= bit;
+            self.colors[cell] = color;
+        }
+    }
+}
+
+impl RenderBackend for TerminalBackend {
+    fn size(&self) -> (u32, u32) {
+        (self.columns * DOTS_PER_CELL_X, self.rows * DOTS_PER_CELL_Y)
+    }
+
+    fn clear(&mut self, color: Color) {
+        self.dots.iter_mut().for_each(|d| *d = 0);
+        self.colors.iter_mut().for_each(|c| *c = color);
+        self.text.iter_mut().for_each(|t| *t = None);
+    }
+
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        self.clip = clip;
+    }
+
+    fn line(&mut self, p1: Point, p2: Point, _width: f32, color: Color) {
+        let d = p2 - p1;
+        let steps = d.x.abs().max(d.y.abs()).ceil().max(1.0);
+        for i in 0..=steps as u32 {
+            let p = p1 + d * (i as f32 / steps);
+            self.plot(p.x, p.y, color);
+        }
+    }
+
+    fn ellipse(&mut self, center: Point, rx: f32, ry: f32, color: Color) {
+        let steps = (2.0 * PI * rx.max(ry)).ceil().max(8.0) as u32;
+        for i in 0..steps {
+            let t = 2.0 * PI * i as f32 / steps as f32;
+            self.plot(center.x + rx * t.cos(), center.y + ry * t.sin(), color);
+        }
+    }
+
+    fn filled_ellipse(&mut self, center: Point, rx: f32, ry: f32, color: Color) {
+        let ry_steps = ry.ceil() as i32;
+        for dy in -ry_steps..=ry_steps {
+            let t = (dy as f32 / ry.max(1.0)).clamp(-1.0, 1.0);
+            let half_width = rx * (1.0 - t * t).sqrt();
+            let mut x = center.x - half_width;
+            while x <= center.x + half_width {
+                self.plot(x, center.y + dy as f32, color);
+                x += 1.0;
+            }
+        }
+    }
+
+    fn polygon(&mut self, vertices: &[Point], color: Color) {
+        for i in 0..vertices.len() {
+            let next = (i + 1) % vertices.len();
+            self.line(vertices[i], vertices[next], 1.0, color);
+        }
+    }
+
+    fn text(&mut self, position: Point, text: &str, color: Color) {
+        if position.x < 0.0 || position.y < 0.0 {
+            return;
+        }
+        let row = position.y as u32 / DOTS_PER_CELL_Y;
+        let column = position.x as u32 / DOTS_PER_CELL_X;
+        if row >= self.rows {
+            return;
+        }
+        for (i, character) in text.chars().enumerate() {
+            let column = column + i as u32;
+            if column >= self.columns {
+                break;
+            }
+            let cell = (row * self.columns + column) as usize;
+            self.text[cell] = Some(character);
+            self.colors[cell] = color;
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// Variants are upper case like every other enum in the game
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Copy, Clone, PartialEq)]
+enum TerminalKey {
+    UP,
+    LEFT,
+    RIGHT,
+    SPACE,
+    QUIT,
+}
+
+// Puts the terminal in raw mode to read single key presses, restores it when dropped
+pub struct TerminalInput {
+    original: libc::termios,
+    accelerator_seen: Option<Instant>,
+    left_seen: Option<Instant>,
+    right_seen: Option<Instant>,
+}
+
+impl TerminalInput {
+    pub fn new() -> Result<TerminalInput, String> {
+        // SAFETY: termios is plain data, filled in by tcgetattr before being used
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err("Standard input is not a terminal".to_string());
+        }
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        // Reads return straight away, even with nothing to read
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err("Could not put the terminal in raw mode".to_string());
+        }
+        // Alternate screen and hidden cursor
+        print!("\x1b[?1049h\x1b[?25l");
+        Ok(TerminalInput {
+            original,
+            accelerator_seen: None,
+            left_seen: None,
+            right_seen: None,
+        })
+    }
+
+    // Feeds the pending key presses to the player ship, returns false when asked to quit
+    pub fn update(&mut self, ships: &mut Ships, player: Player) -> bool {
+        let now = Instant::now();
+        for key in read_keys() {
+            match key {
+                TerminalKey::QUIT => return false,
+                TerminalKey::SPACE => {
+                    // Every auto repeat fires again
+                    ships.gun_trigger_released(player);
+                    ships.gun_trigger_pressed(player);
+                }
+                TerminalKey::UP => {
+                    ships.accelerator_pressed(player);
+                    self.accelerator_seen = Some(now);
+                }
+                TerminalKey::LEFT => {
+                    ships.side_thruster_pressed(player, RotationDirection::COUNTERCLOCKWISE);
+                    self.left_seen = Some(now);
+                }
+                TerminalKey::RIGHT => {
+                    ships.side_thruster_pressed(player, RotationDirection::CLOCKWISE);
+                    self.right_seen = Some(now);
+                }
+            }
+        }
+
+        let expired = |seen: &mut Option<Instant>| {
+            let expired = seen.is_some_and(|t| now.duration_since(t) > KEY_HOLD_TIMEOUT);
+            if expired {
+                *seen = None;
+            }
+            expired
+        };
+        if expired(&mut self.accelerator_seen) {
+            ships.accelerator_released(player);
+        }
+        if expired(&mut self.left_seen) {
+            ships.side_thruster_released(player, RotationDirection::COUNTERCLOCKWISE);
+        }
+        if expired(&mut self.right_seen) {
+            ships.side_thruster_released(player, RotationDirection::CLOCKWISE);
+        }
+        ships.gun_trigger_released(player);
+        true
+    }
+}
+
+impl Drop for TerminalInput {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        io::stdout().flush().ok();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+fn read_keys() -> Vec<TerminalKey> {
+    let mut buffer = [0; 64];
+    let count = io::stdin().read(&mut buffer).unwrap_or(0);
+    let bytes = &buffer[..count];
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            // Arrow keys are sent as ESC [ A..D
+            0x1b if bytes.get(i + 1) == Some(&b'[') && i + 2 < bytes.len() => {
+                match bytes[i + 2] {
+                    b'A' => keys.push(TerminalKey::UP),
+                    b'C' => keys.push(TerminalKey::RIGHT),
+                    b'D' => keys.push(TerminalKey::LEFT),
+                    _ => {}
+                }
+                i += 2;
+            }
+            0x1b | b'q' | 0x03 => keys.push(TerminalKey::QUIT),
+            b' ' => keys.push(TerminalKey::SPACE),
+            b'w' => keys.push(TerminalKey::UP),
+            b'a' => keys.push(TerminalKey::LEFT),
+            b'd' => keys.push(TerminalKey::RIGHT),
+            _ => {}
+        }
+        i += 1;
+    }
+    keys
+}
@@ -0,0 +1,252 @@
+use crate::game_objects::{AsteroidTuning, BulletTuning, ShipTuning};
+use crate::physics::PhysicsTuning;
+
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// -----------------------------------------------------------------------------
+
+const DEFAULT_FRAME_RATE: f64 = 120.0;
+const RELOAD_CHECK_PERIOD: Duration = Duration::from_millis(500);
+// More pieces than this from one asteroid would soon fill the whole asteroid pool
+const MAX_ICE_SHATTER_PIECES: usize = 16;
+
+// Tuning
+// Gameplay numbers read from a TOML file, anything left out keeps its default value
+#[derive(Copy, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tuning {
+    // Frames per second, must be more than 1
+    pub frame_rate: f64,
+    pub asteroids: AsteroidTuning,
+    pub bullets: BulletTuning,
+    pub ships: ShipTuning,
+    pub physics: PhysicsTuning,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Tuning {
+            frame_rate: DEFAULT_FRAME_RATE,
+            asteroids: Default::default(),
+            bullets: Default::default(),
+            ships: Default::default(),
+            physics: Default::default(),
+        }
+    }
+}
+
+impl Tuning {
+    pub fn load(path: &Path) -> Result<Tuning, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let tuning: Tuning =
+            toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))?;
+        tuning
+            .validate()
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(tuning)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let asteroids = &self.asteroids;
+        let ships = &self.ships;
+        let physics = &self.physics;
+        // TOML has nan and inf, which would get past the checks below and break the simulation
+        let numbers = [
+            asteroids.min_speed,
+            asteroids.max_speed_lower_limit,
+            asteroids.max_speed_higher_limit,
+            asteroids.speed_multiplier_ice,
+            asteroids.speed_multiplier_metal,
+            asteroids.density_metal,
+            self.bullets.speed,
+            self.bullets.length,
+            ships.mass,
+            ships.max_speed,
+            ships.deacceleration,
+            ships.max_angular_velocity,
+            physics.asteroid_restitution,
+            physics.ship_restitution,
+            physics.asteroid_split_speed_multiplier,
+            physics.explosion_radius,
+            physics.metal_deflection_max_incidence,
+        ];
+        let arrays = [
+            ships.acceleration,
+            ships.angular_acceleration,
+            ships.gun_fire_period,
+        ];
+        check(
+            self.frame_rate.is_finite()
+                && numbers
+                    .iter()
+                    .chain(arrays.iter().flatten())
+                    .all(|x| x.is_finite()),
+            "numbers can't be nan or inf",
+        )?;
+        check(self.frame_rate > 1.0, "frame_rate must be more than 1")?;
+
+        check(
+            asteroids.min_speed >= 0.0,
+            "asteroids.min_speed can't be negative",
+        )?;
+        check(
+            asteroids.min_speed <= asteroids.max_speed_lower_limit
+                && asteroids.max_speed_lower_limit <= asteroids.max_speed_higher_limit,
+            "asteroids.min_speed <= max_speed_lower_limit <= max_speed_higher_limit",
+        )?;
+        check(
+            asteroids.speed_multiplier_ice > 0.0 && asteroids.speed_multiplier_metal > 0.0,
+            "asteroid speed multipliers must be positive",
+        )?;
+        check(
+            asteroids.density_metal > 0.0,
+            "asteroids.density_metal must be positive",
+        )?;
+        check(
+            asteroids.health.all().iter().flatten().all(|&hp| hp > 0),
+            "asteroids.health must be positive",
+        )?;
+
+        check(self.bullets.speed > 0.0, "bullets.speed must be positive")?;
+        check(self.bullets.length > 0.0, "bullets.length must be positive")?;
+        check(self.bullets.damage > 0, "bullets.damage must be positive")?;
+
+        check(ships.mass > 0.0, "ships.mass must be positive")?;
+        check(ships.max_speed > 0.0, "ships.max_speed must be positive")?;
+        check(
+            ships.acceleration.iter().all(|&a| a > 0.0),
+            "ships.acceleration must be positive",
+        )?;
+        check(
+            ships.deacceleration >= 0.0,
+            "ships.deacceleration can't be negative",
+        )?;
+        check(
+            ships.max_angular_velocity > 0.0,
+            "ships.max_angular_velocity must be positive",
+        )?;
+        check(
+            ships.angular_acceleration.iter().all(|&a| a > 0.0),
+            "ships.angular_acceleration must be positive",
+        )?;
+        check(
+            ships.gun_fire_period.iter().all(|&t| t > 0.0),
+            "ships.gun_fire_period must be positive",
+        )?;
+
+        let restitutions = [physics.asteroid_restitution, physics.ship_restitution];
+        check(
+            restitutions.iter().all(|e| (0.0..=1.0).contains(e)),
+            "restitution coefficients must be from 0 to 1",
+        )?;
+        check(
+            physics.asteroid_split_speed_multiplier > 0.0,
+            "physics.asteroid_split_speed_multiplier must be positive",
+        )?;
+        check(
+            (2..=MAX_ICE_SHATTER_PIECES).contains(&physics.ice_shatter_pieces),
+            &format!("physics.ice_shatter_pieces must be from 2 to {MAX_ICE_SHATTER_PIECES}"),
+        )?;
+        check(
+            physics.explosion_radius >= 0.0 && physics.explosion_damage >= 0,
+            "physics.explosion_radius and explosion_damage can't be negative",
+        )?;
+        check(
+            (0.0..=1.0).contains(&physics.metal_deflection_max_incidence),
+            "physics.metal_deflection_max_incidence must be from 0 to 1",
+        )
+    }
+}
+
+fn check(condition: bool, message: &str) -> Result<(), String> {
+    if condition {
+        Ok(())
+    } else {
+        Err(message.to_string())
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// Reloads the tuning file when it changes on disk, so balance can be tweaked while playing
+pub struct TuningWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl TuningWatcher {
+    pub fn new(path: &Path) -> TuningWatcher {
+        TuningWatcher {
+            path: path.to_path_buf(),
+            modified: modified_time(path),
+            last_check: Instant::now(),
+        }
+    }
+
+    // The new tuning when the file changed, a broken file is reported and otherwise ignored
+    pub fn poll(&mut self) -> Option<Tuning> {
+        if self.last_check.elapsed() < RELOAD_CHECK_PERIOD {
+            return None;
+        }
+        self.last_check = Instant::now();
+        let modified = modified_time(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        match Tuning::load(&self.path) {
+            Ok(tuning) => {
+                eprintln!("Reloaded {}", self.path.display());
+                Some(tuning)
+            }
+            Err(error) => {
+                eprintln!("{error}, keeping the previous tuning");
+                None
+            }
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        assert!(Tuning::default().validate().is_ok());
+    }
+
+    #[test]
+    fn nan_and_inf_are_refused() {
+        let edits: [fn(&mut Tuning); 5] = [
+            |t| t.frame_rate = f64::INFINITY,
+            |t| t.asteroids.max_speed_higher_limit = f32::INFINITY,
+            |t| t.bullets.speed = f32::NAN,
+            |t| t.ships.max_speed = f32::INFINITY,
+            |t| t.ships.acceleration[2] = f32::INFINITY,
+        ];
+        for edit in edits {
+            let mut tuning = Tuning::default();
+            edit(&mut tuning);
+            assert!(tuning.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn ice_shatter_pieces_are_capped() {
+        let mut tuning = Tuning::default();
+        tuning.physics.ice_shatter_pieces = MAX_ICE_SHATTER_PIECES;
+        assert!(tuning.validate().is_ok());
+        tuning.physics.ice_shatter_pieces = usize::MAX;
+        assert!(tuning.validate().is_err());
+    }
+}
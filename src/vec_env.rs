@@ -0,0 +1,138 @@
+use crate::env::{Action, Env, EnvConfig, StepInfo};
+
+use rayon::prelude::*;
+
+// -----------------------------------------------------------------------------
+
+// VecEnv
+// Many independent environments stepped together on all cores, with their observations, rewards
+// and done flags side by side in flat buffers. Finished episodes start over on their own, so every
+// step has an observation for every environment
+pub struct VecEnv {
+    envs: Vec<Env>,
+    observation_size: usize,
+    // Every episode gets its own seed from this one, the environment's index and how many episodes
+    // it played before, so the same seed and actions always play out the same no matter the threads
+    seed: u64,
+    episodes: Vec<u64>,
+    observations: Vec<f32>,
+    rewards: Vec<f32>,
+    dones: Vec<bool>,
+    infos: Vec<StepInfo>,
+}
+
+impl VecEnv {
+    pub fn new(config: EnvConfig, num_envs: usize, seed: u64) -> VecEnv {
+        let observation_size = config.observation_size();
+        let envs = (0..num_envs)
+            .into_par_iter()
+            .map(|_| Env::new(config.clone()))
+            .collect();
+        let mut vec_env = VecEnv {
+            envs,
+            observation_size,
+            seed,
+            episodes: vec![0; num_envs],
+            observations: vec![0.0; num_envs * observation_size],
+            rewards: vec![0.0; num_envs],
+            dones: vec![false; num_envs],
+            infos: vec![StepInfo::default(); num_envs],
+        };
+        vec_env.reset(seed);
+        vec_env
+    }
+
+    // Starts new episodes in all of the environments
+    pub fn reset(&mut self, seed: u64) -> &[f32] {
+        self.seed = seed;
+        let num_envs = self.envs.len();
+        (
+            self.envs.par_iter_mut(),
+            self.episodes.par_iter_mut(),
+            self.observations.par_chunks_mut(self.observation_size),
+        )
+            .into_par_iter()
+            .enumerate()
+            .for_each(|(i, (env, episodes, observation))| {
+                *episodes = 0;
+                env.reset(episode_seed(seed, num_envs, i, 0))
+                    .copy_to(observation);
+            });
+        self.rewards.fill(0.0);
+        self.dones.fill(false);
+        self.infos.fill(StepInfo::default());
+        &self.observations
+    }
+
+    // One action for every environment. Environments whose episodes end with this step are reset
+    // right away: their done flag and info are for the episode that ended, and their observation
+    // is the first one of the next
+    pub fn step(&mut self, actions: &[Action]) {
+        assert_eq!(
+            actions.len(),
+            self.envs.len(),
+            "One action for each environment"
+        );
+        let (seed, num_envs) = (self.seed, self.envs.len());
+        (
+            self.envs.par_iter_mut(),
+            self.episodes.par_iter_mut(),
+            self.observations.par_chunks_mut(self.observation_size),
+            self.rewards.par_iter_mut(),
+            self.dones.par_iter_mut(),
+            self.infos.par_iter_mut(),
+            actions.par_iter(),
+        )
+            .into_par_iter()
+            .enumerate()
+            .for_each(
+                |(i, (env, episodes, observation, reward, done, info, &action))| {
+                    let (next, step_reward, step_done, step_info) = env.step(action);
+                    (*reward, *done, *info) = (step_reward, step_done, step_info);
+                    if step_done {
+                        *episodes += 1;
+                        env.reset(episode_seed(seed, num_envs, i, *episodes))
+                            .copy_to(observation);
+                    } else {
+                        next.copy_to(observation);
+                    }
+                },
+            );
+    }
+
+    pub fn num_envs(&self) -> usize {
+        self.envs.len()
+    }
+
+    // Values for each environment in observations
+    pub fn observation_size(&self) -> usize {
+        self.observation_size
+    }
+
+    // observation_size values for every environment, one after the other
+    pub fn observations(&self) -> &[f32] {
+        &self.observations
+    }
+
+    pub fn rewards(&self) -> &[f32] {
+        &self.rewards
+    }
+
+    pub fn dones(&self) -> &[bool] {
+        &self.dones
+    }
+
+    pub fn infos(&self) -> &[StepInfo] {
+        &self.infos
+    }
+
+    // For looking at or drawing one of them
+    pub fn env(&self, index: usize) -> &Env {
+        &self.envs[index]
+    }
+}
+
+fn episode_seed(seed: u64, num_envs: usize, index: usize, episode: u64) -> u64 {
+    seed.wrapping_add(episode.wrapping_mul(num_envs as u64))
+        .wrapping_add(index as u64)
+}
@@ -0,0 +1,382 @@
+use crate::events::{DamageSource, GameEvent};
+use crate::game_objects::{AsteroidSize, Asteroids, Bullets, GravityWells, Ships, MAX_SHIPS};
+use crate::levels::{create_hazards, spawn_asteroids, Campaign, LevelDefinition};
+use crate::particles::Particles;
+use crate::physics::{
+    apply_gravity, asteroid_asteroid_collisions, asteroid_bullet_collisions,
+    asteroid_ship_collisions, destroy_ship, gravity_well_collisions, BroadPhase,
+};
+use crate::shapes::Point;
+use crate::tuning::Tuning;
+
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+// -----------------------------------------------------------------------------
+
+pub const DEFAULT_WORLD_SIZE: Point = Point {
+    x: 400.0 / 3.0,
+    y: 100.0,
+};
+// Each side, bigger worlds would need an enormous broad-phase grid
+pub const MAX_WORLD_SIZE: f32 = 10000.0;
+
+// World
+// Everything in the game simulation, independent of how (or if) it gets displayed
+pub struct World {
+    pub size: Point,
+    pub asteroids: Box<Asteroids>,
+    pub bullets: Box<Bullets>,
+    pub ships: Box<Ships>,
+    pub gravity_wells: Box<GravityWells>,
+    pub particles: Box<Particles>,
+    pub events: Vec<GameEvent>,
+    // Levels count from 1 once started, 0 before the first one
+    pub level: i32,
+    // Points for the asteroids destroyed since the game started
+    pub score: u32,
+    pub campaign: Campaign,
+    pub level_definition: LevelDefinition,
+    // Seconds since the current level started
+    pub level_time: f32,
+    next_wave: usize,
+    pub tuning: Tuning,
+    // Everything random in the simulation comes from here, so a seed always plays out the same
+    pub rng: Pcg32,
+    pub broad_phase: BroadPhase,
+    pub debug: DebugInfo,
+}
+
+// WorldState
+// Everything needed to carry on a game exactly where it was, particles and debug info aside
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WorldState {
+    pub size: Point,
+    pub asteroids: Asteroids,
+    pub bullets: Bullets,
+    pub ships: Ships,
+    pub gravity_wells: GravityWells,
+    pub level: i32,
+    pub score: u32,
+    pub level_definition: LevelDefinition,
+    pub level_time: f32,
+    pub next_wave: usize,
+    pub tuning: Tuning,
+    pub rng: Pcg32,
+}
+
+// Positive and at most MAX_WORLD_SIZE on each side, which also rules out nan and inf
+pub fn valid_world_size(size: Point) -> bool {
+    [size.x, size.y]
+        .iter()
+        .all(|&side| side > 0.0 && side <= MAX_WORLD_SIZE)
+}
+
+impl WorldState {
+    // Saved states can be cut short or edited by hand, restoring one that doesn't hold together
+    // would panic or misbehave on the first update
+    pub fn validate(&self) -> Result<(), String> {
+        if !valid_world_size(self.size) {
+            return Err(format!("world size must be from 0 to {MAX_WORLD_SIZE}"));
+        }
+        if !self.level_time.is_finite() {
+            return Err("level_time must be a number".to_string());
+        }
+        let objects = [
+            ("asteroids", self.asteroids.validate()),
+            ("bullets", self.bullets.validate()),
+            ("ships", self.ships.validate()),
+            ("gravity wells", self.gravity_wells.validate()),
+        ];
+        for (name, result) in objects {
+            result.map_err(|e| format!("{name}: {e}"))?;
+        }
+        self.tuning.validate()?;
+        // Before the first update there's no level yet, only the empty default
+        if self.level > 0 {
+            self.level_definition
+                .validate()
+                .map_err(|e| format!("level: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+// What the last update went through, only looked at by the debug overlay
+#[derive(Default)]
+pub struct DebugInfo {
+    // Closest points of the shapes that collided with ships
+    pub contacts: Vec<Point>,
+    pub phase_times: Vec<(&'static str, Duration)>,
+}
+
+impl World {
+    pub fn new(size: Point, seed: u64) -> World {
+        let mut world = World {
+            size,
+            asteroids: Box::default(),
+            bullets: Box::default(),
+            ships: Box::default(),
+            gravity_wells: Box::default(),
+            particles: Box::default(),
+            events: Vec::new(),
+            level: 0,
+            score: 0,
+            campaign: Campaign::default(),
+            level_definition: LevelDefinition::default(),
+            level_time: 0.0,
+            next_wave: 0,
+            tuning: Tuning::default(),
+            rng: Pcg32::seed_from_u64(seed),
+            broad_phase: BroadPhase::new(size),
+            debug: DebugInfo::default(),
+        };
+        world.ships.create(size / 2.0);
+        world
+    }
+
+    // Takes effect straight away, even in the middle of a game
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        self.tuning = tuning;
+        self.asteroids.tuning = tuning.asteroids;
+        self.bullets.tuning = tuning.bullets;
+        self.ships.tuning = tuning.ships;
+    }
+
+    pub fn save_state(&self) -> WorldState {
+        WorldState {
+            size: self.size,
+            asteroids: (*self.asteroids).clone(),
+            bullets: (*self.bullets).clone(),
+            ships: (*self.ships).clone(),
+            gravity_wells: (*self.gravity_wells).clone(),
+            level: self.level,
+            score: self.score,
+            level_definition: self.level_definition.clone(),
+            level_time: self.level_time,
+            next_wave: self.next_wave,
+            tuning: self.tuning,
+            rng: self.rng.clone(),
+        }
+    }
+
+    // The campaign is kept, the levels after the restored one still come from it
+    pub fn restore_state(&mut self, state: WorldState) {
+        self.size = state.size;
+        self.broad_phase = BroadPhase::new(state.size);
+        *self.asteroids = state.asteroids;
+        *self.bullets = state.bullets;
+        *self.ships = state.ships;
+        *self.gravity_wells = state.gravity_wells;
+        self.particles.clear();
+        self.events.clear();
+        self.level = state.level;
+        self.score = state.score;
+        self.level_definition = state.level_definition;
+        self.level_time = state.level_time;
+        self.next_wave = state.next_wave;
+        self.set_tuning(state.tuning);
+        self.rng = state.rng;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.events.clear();
+        self.debug.contacts.clear();
+        self.debug.phase_times.clear();
+        let mut phase_start = Instant::now();
+
+        // Game over
+        if self.ships.none_exist() {
+            self.events.push(GameEvent::GameOver {
+                score: self.score,
+                level: self.level,
+            });
+            self.level = 0;
+            self.score = 0;
+            self.level_definition = LevelDefinition::default();
+            self.next_wave = 0;
+            self.asteroids.clear();
+            self.bullets.clear();
+            self.gravity_wells.clear();
+            self.particles.clear();
+            self.ships.create(self.size / 2.0);
+        }
+        // Waves come early when the asteroids are cleared, the level ends after the last one
+        self.level_time += dt;
+        let waves = self.level_definition.waves.len();
+        while self.next_wave < waves
+            && (self.asteroids.none_exist()
+                || self.level_definition.waves[self.next_wave].delay <= self.level_time)
+        {
+            let wave = &self.level_definition.waves[self.next_wave];
+            spawn_asteroids(
+                &wave.asteroids,
+                &mut self.asteroids,
+                self.size,
+                self.level - 1,
+                &mut self.rng,
+            );
+            self.next_wave += 1;
+        }
+        // Level cleared
+        if self.asteroids.none_exist() {
+            self.start_level(self.level);
+            self.level += 1;
+        }
+        // Out of time
+        if self
+            .level_definition
+            .time_limit
+            .is_some_and(|limit| self.level_time > limit)
+        {
+            for i in 0..MAX_SHIPS {
+                if self.ships.pool.exists(i) {
+                    destroy_ship(&mut self.ships, i, DamageSource::TIMEOUT, &mut self.events);
+                }
+            }
+        }
+        self.end_phase("spawning", &mut phase_start);
+
+        apply_gravity(
+            &self.gravity_wells,
+            &mut self.asteroids,
+            &mut self.bullets,
+            &mut self.ships,
+            dt,
+        );
+        self.ships.update_positions(self.size, dt);
+        self.bullets.update_positions(self.size, dt);
+        self.asteroids.update_positions(self.size, dt);
+        self.particles.update_positions(dt);
+        self.ships
+            .update_shooting(&mut self.bullets, &mut self.events, dt);
+        self.end_phase("movement", &mut phase_start);
+
+        // Physics
+        let tuning = &self.tuning.physics;
+        // TODO: For testing, remove eventually
+        asteroid_asteroid_collisions(&mut self.asteroids, &mut self.broad_phase, tuning);
+        asteroid_bullet_collisions(
+            &mut self.asteroids,
+            &mut self.bullets,
+            &mut self.events,
+            tuning,
+        );
+        asteroid_ship_collisions(
+            &mut self.asteroids,
+            &mut self.ships,
+            &mut self.events,
+            &mut self.debug.contacts,
+            tuning,
+        );
+        gravity_well_collisions(
+            &self.gravity_wells,
+            &mut self.asteroids,
+            &mut self.bullets,
+            &mut self.ships,
+            &mut self.events,
+            &mut self.debug.contacts,
+            tuning,
+        );
+        self.end_phase("collisions", &mut phase_start);
+
+        // Score
+        for event in &self.events {
+            if let GameEvent::AsteroidDestroyed { radius, .. } = *event {
+                self.score += AsteroidSize::from_radius(radius).map_or(0, |size| size.points());
+            }
+        }
+
+        // Effects
+        self.particles.emit_for_events(&self.events);
+        self.particles.emit_exhaust(&self.ships, dt);
+        self.end_phase("effects", &mut phase_start);
+    }
+
+    // Levels count from 0 here
+    fn start_level(&mut self, level: i32) {
+        self.level_definition = self.campaign.level(level);
+        self.level_time = 0.0;
+        self.next_wave = 0;
+        create_hazards(
+            &self.level_definition.hazards,
+            &mut self.gravity_wells,
+            self.size,
+        );
+        spawn_asteroids(
+            &self.level_definition.asteroids,
+            &mut self.asteroids,
+            self.size,
+            level,
+            &mut self.rng,
+        );
+    }
+
+    fn end_phase(&mut self, name: &'static str, phase_start: &mut Instant) {
+        let now = Instant::now();
+        self.debug.phase_times.push((name, now - *phase_start));
+        *phase_start = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A few seconds in, so there are asteroids, bullets and a ship to break
+    fn played_state() -> WorldState {
+        let mut world = World::new(DEFAULT_WORLD_SIZE, 7);
+        for _ in 0..120 {
+            world.update(1.0 / 60.0);
+        }
+        world.save_state()
+    }
+
+    fn live_asteroid(state: &WorldState) -> usize {
+        let pool = &state.asteroids.pool;
+        (0..pool.capacity()).find(|&i| pool.exists(i)).unwrap()
+    }
+
+    #[test]
+    fn saved_states_are_valid() {
+        assert!(World::new(DEFAULT_WORLD_SIZE, 7)
+            .save_state()
+            .validate()
+            .is_ok());
+        assert!(played_state().validate().is_ok());
+    }
+
+    #[test]
+    fn edited_asteroids_are_refused() {
+        let mut state = played_state();
+        let i = live_asteroid(&state);
+        state.asteroids.circle[i].radius = 5.0;
+        assert!(state.validate().is_err());
+
+        let mut state = played_state();
+        state.asteroids.velocity[i].x = f32::NAN;
+        assert!(state.validate().is_err());
+
+        let mut state = played_state();
+        state.asteroids.hp.pop();
+        assert!(state.validate().is_err());
+    }
+
+    #[test]
+    fn edited_ships_and_settings_are_refused() {
+        let mut state = played_state();
+        state.ships.triangle[0].v1.y = f32::INFINITY;
+        assert!(state.validate().is_err());
+
+        let mut state = played_state();
+        state.size.x = f32::INFINITY;
+        assert!(state.validate().is_err());
+
+        let mut state = played_state();
+        state.tuning.frame_rate = 0.0;
+        assert!(state.validate().is_err());
+    }
+}